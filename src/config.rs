@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::OnceLock;
+
+static CONFIG: OnceLock<BrokerConfig> = OnceLock::new();
+
+#[derive(Debug, Clone)]
+pub struct BrokerConfig {
+    pub node_id: i32,
+    /// The address the broker accepts connections on.
+    pub listener_host: String,
+    pub listener_port: i32,
+    pub advertised_host: String,
+    pub advertised_port: i32,
+    pub socket_request_max_bytes: usize,
+    pub log_dir: String,
+    pub log_segment_bytes: u64,
+    pub log_roll_ms: i64,
+    pub log_index_interval_bytes: usize,
+    pub max_incremental_fetch_session_cache_slots: usize,
+    pub num_partitions: i32,
+    pub default_replication_factor: i16,
+    pub auto_create_topics_enable: bool,
+    pub group_min_session_timeout_ms: i32,
+    pub group_max_session_timeout_ms: i32,
+    pub group_initial_rebalance_delay_ms: i32,
+    pub group_consumer_session_timeout_ms: i32,
+    pub group_consumer_heartbeat_interval_ms: i32,
+    /// Server side assignors for consumer protocol groups, the first being the default.
+    pub group_consumer_assignors: Vec<String>,
+    pub offsets_topic_num_partitions: i32,
+    pub offset_metadata_max_bytes: usize,
+}
+
+impl Default for BrokerConfig {
+    fn default() -> Self {
+        BrokerConfig {
+            node_id: 1,
+            listener_host: "127.0.0.1".to_string(),
+            listener_port: 9092,
+            advertised_host: "localhost".to_string(),
+            advertised_port: 9092,
+            socket_request_max_bytes: 100 * 1024 * 1024,
+            log_dir: "/tmp/kraft-combined-logs".to_string(),
+            log_segment_bytes: 1024 * 1024 * 1024,
+            log_roll_ms: 7 * 24 * 60 * 60 * 1000,
+            log_index_interval_bytes: 4096,
+            max_incremental_fetch_session_cache_slots: 1000,
+            num_partitions: 1,
+            default_replication_factor: 1,
+            auto_create_topics_enable: false,
+            group_min_session_timeout_ms: 6000,
+            group_max_session_timeout_ms: 30 * 60 * 1000,
+            group_initial_rebalance_delay_ms: 3000,
+            group_consumer_session_timeout_ms: 45000,
+            group_consumer_heartbeat_interval_ms: 5000,
+            group_consumer_assignors: vec!["uniform".to_string(), "range".to_string()],
+            offsets_topic_num_partitions: 50,
+            offset_metadata_max_bytes: 4096,
+        }
+    }
+}
+
+impl BrokerConfig {
+    /// Loads a `server.properties` style file, falling back to defaults for missing keys.
+    pub fn load(path: &str) -> anyhow::Result<BrokerConfig> {
+        let content = fs::read_to_string(path)?;
+        Ok(BrokerConfig::from_properties(&parse_properties(&content)))
+    }
+
+    pub fn from_properties(props: &HashMap<String, String>) -> BrokerConfig {
+        let defaults = BrokerConfig::default();
+        let (listener_host, listener_port) = props
+            .get("listeners")
+            .and_then(|listeners| parse_listener(listeners))
+            // An empty host binds every interface, as in Kafka.
+            .map(|(host, port)| (if host.is_empty() { "0.0.0.0".to_string() } else { host }, port))
+            .unwrap_or((defaults.listener_host, defaults.listener_port));
+        let (advertised_host, advertised_port) = props
+            .get("advertised.listeners")
+            .or_else(|| props.get("listeners"))
+            .and_then(|listeners| parse_listener(listeners))
+            .map(|(host, port)| {
+                let host = if host.is_empty() || host == "0.0.0.0" { "localhost".to_string() } else { host };
+                (host, port)
+            })
+            .unwrap_or((defaults.advertised_host, defaults.advertised_port));
+        BrokerConfig {
+            node_id: parse_or(props, "node.id", defaults.node_id),
+            listener_host,
+            listener_port,
+            advertised_host,
+            advertised_port,
+            socket_request_max_bytes: parse_or(props, "socket.request.max.bytes", defaults.socket_request_max_bytes),
+            log_dir: props
+                .get("log.dirs")
+                .or_else(|| props.get("log.dir"))
+                .and_then(|dirs| dirs.split(',').next())
+                .map_or(defaults.log_dir, |dir| dir.trim().to_string()),
+            log_segment_bytes: parse_or(props, "log.segment.bytes", defaults.log_segment_bytes),
+            log_roll_ms: match props.get("log.roll.ms") {
+                Some(_) => parse_or(props, "log.roll.ms", defaults.log_roll_ms),
+                None => parse_or(props, "log.roll.hours", defaults.log_roll_ms / 3_600_000) * 3_600_000,
+            },
+            log_index_interval_bytes: parse_or(props, "log.index.interval.bytes", defaults.log_index_interval_bytes),
+            max_incremental_fetch_session_cache_slots: parse_or(
+                props,
+                "max.incremental.fetch.session.cache.slots",
+                defaults.max_incremental_fetch_session_cache_slots,
+            ),
+            num_partitions: parse_or(props, "num.partitions", defaults.num_partitions),
+            default_replication_factor: parse_or(
+                props,
+                "default.replication.factor",
+                defaults.default_replication_factor,
+            ),
+            auto_create_topics_enable: parse_or(
+                props,
+                "auto.create.topics.enable",
+                defaults.auto_create_topics_enable,
+            ),
+            group_min_session_timeout_ms: parse_or(
+                props,
+                "group.min.session.timeout.ms",
+                defaults.group_min_session_timeout_ms,
+            ),
+            group_max_session_timeout_ms: parse_or(
+                props,
+                "group.max.session.timeout.ms",
+                defaults.group_max_session_timeout_ms,
+            ),
+            group_initial_rebalance_delay_ms: parse_or(
+                props,
+                "group.initial.rebalance.delay.ms",
+                defaults.group_initial_rebalance_delay_ms,
+            ),
+            group_consumer_session_timeout_ms: parse_or(
+                props,
+                "group.consumer.session.timeout.ms",
+                defaults.group_consumer_session_timeout_ms,
+            ),
+            group_consumer_heartbeat_interval_ms: parse_or(
+                props,
+                "group.consumer.heartbeat.interval.ms",
+                defaults.group_consumer_heartbeat_interval_ms,
+            ),
+            group_consumer_assignors: props
+                .get("group.consumer.assignors")
+                .map_or(defaults.group_consumer_assignors, |assignors| {
+                    assignors.split(',').map(str::trim).filter(|a| !a.is_empty()).map(str::to_string).collect()
+                }),
+            offsets_topic_num_partitions: parse_or(
+                props,
+                "offsets.topic.num.partitions",
+                defaults.offsets_topic_num_partitions,
+            ),
+            offset_metadata_max_bytes: parse_or(
+                props,
+                "offset.metadata.max.bytes",
+                defaults.offset_metadata_max_bytes,
+            ),
+        }
+    }
+}
+
+pub fn init(config: BrokerConfig) {
+    let _ = CONFIG.set(config);
+}
+
+pub fn get() -> &'static BrokerConfig {
+    CONFIG.get_or_init(BrokerConfig::default)
+}
+
+fn parse_properties(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('!'))
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=').or_else(|| line.split_once(':'))?;
+            Some((key.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Takes the first `NAME://host:port` entry of a listeners list.
+fn parse_listener(listeners: &str) -> Option<(String, i32)> {
+    let first = listeners.split(',').next()?.trim();
+    let address = first.split_once("://").map_or(first, |(_, address)| address);
+    let (host, port) = address.rsplit_once(':')?;
+    Some((host.to_string(), port.parse().ok()?))
+}
+
+pub fn parse_or<T: std::str::FromStr>(props: &HashMap<String, String>, key: &str, default: T) -> T {
+    match props.get(key) {
+        Some(value) => value.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid value {:?} for {}, using default", value, key);
+            default
+        }),
+        None => default,
+    }
+}
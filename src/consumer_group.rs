@@ -0,0 +1,1062 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use kafka_protocol::messages::consumer_group_heartbeat_request::TopicPartitions;
+use kafka_protocol::messages::ConsumerGroupHeartbeatRequest;
+use kafka_protocol::ResponseError;
+use uuid::Uuid;
+use crate::controller::ApiError;
+use crate::group_coordinator::GroupOverview;
+use crate::metadata::MetadataImage;
+use crate::{config, group_coordinator, metadata};
+
+/// The member epoch a consumer sends to leave its group.
+const LEAVE_GROUP_MEMBER_EPOCH: i32 = -1;
+/// The member epoch a static member sends to leave temporarily. It keeps its
+/// assignment until its session expires, so a restarted instance can take it over.
+const LEAVE_GROUP_STATIC_MEMBER_EPOCH: i32 = -2;
+/// ConsumerGroupHeartbeat v1+ has consumers generate their own member id (KIP-1082).
+const CONSUMER_GENERATED_MEMBER_ID_VERSION: i16 = 1;
+/// OffsetCommit v9+ reports a mismatched member epoch as stale rather than as an illegal generation.
+const STALE_MEMBER_EPOCH_VERSION: i16 = 9;
+
+const UNIFORM_ASSIGNOR: &str = "uniform";
+const RANGE_ASSIGNOR: &str = "range";
+
+pub const CONSUMER_GROUP_TYPE: &str = "consumer";
+
+/// Partitions by topic id.
+pub type Assignment = BTreeMap<Uuid, BTreeSet<i32>>;
+
+static CONSUMER_GROUPS: LazyLock<Mutex<HashMap<String, ConsumerGroup>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Where a member is in reconciling its partitions with its target assignment,
+/// as in Kafka's `CurrentAssignmentBuilder`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MemberState {
+    Stable,
+    /// The member must revoke partitions before it may take new ones.
+    UnrevokedPartitions,
+    /// Some target partitions are still owned by members that have not revoked them.
+    UnreleasedPartitions,
+}
+
+#[derive(Debug, Clone)]
+struct ConsumerMember {
+    instance_id: Option<String>,
+    rack_id: Option<String>,
+    client_id: String,
+    client_host: String,
+    rebalance_timeout: Duration,
+    subscribed_topic_names: Vec<String>,
+    server_assignor: Option<String>,
+    state: MemberState,
+    member_epoch: i32,
+    previous_member_epoch: i32,
+    assigned: Assignment,
+    pending_revocation: Assignment,
+    last_heartbeat: Instant,
+    /// While the member has partitions to revoke, when it must have done so.
+    revocation_deadline: Option<Instant>,
+}
+
+impl ConsumerMember {
+    fn new(client_id: &str, client_host: &str, now: Instant) -> ConsumerMember {
+        ConsumerMember {
+            instance_id: None,
+            rack_id: None,
+            client_id: client_id.to_string(),
+            client_host: client_host.to_string(),
+            rebalance_timeout: Duration::ZERO,
+            subscribed_topic_names: Vec::new(),
+            server_assignor: None,
+            state: MemberState::Stable,
+            member_epoch: 0,
+            previous_member_epoch: 0,
+            assigned: Assignment::new(),
+            pending_revocation: Assignment::new(),
+            last_heartbeat: now,
+            revocation_deadline: None,
+        }
+    }
+
+    /// Whether the member holds the partition, including one it is still revoking.
+    fn owns(&self, topic_id: &Uuid, partition: i32) -> bool {
+        [&self.assigned, &self.pending_revocation]
+            .iter()
+            .any(|assignment| assignment.get(topic_id).is_some_and(|partitions| partitions.contains(&partition)))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HeartbeatResult {
+    pub member_id: String,
+    pub member_epoch: i32,
+    pub heartbeat_interval_ms: i32,
+    /// The member's partitions, sent when they changed or the member sent its full state.
+    pub assignment: Option<Assignment>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConsumerMemberDescription {
+    pub member_id: String,
+    pub instance_id: Option<String>,
+    pub rack_id: Option<String>,
+    pub member_epoch: i32,
+    pub client_id: String,
+    pub client_host: String,
+    pub subscribed_topic_names: Vec<String>,
+    pub assignment: Assignment,
+    pub target_assignment: Assignment,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConsumerGroupDescription {
+    pub state: &'static str,
+    pub group_epoch: i32,
+    pub assignment_epoch: i32,
+    pub assignor_name: String,
+    pub members: Vec<ConsumerMemberDescription>,
+}
+
+#[derive(Debug)]
+struct ConsumerGroup {
+    group_id: String,
+    /// Bumped whenever the members or what they subscribe to change.
+    group_epoch: i32,
+    /// The group epoch the target assignment was computed for.
+    assignment_epoch: i32,
+    members: BTreeMap<String, ConsumerMember>,
+    /// Member ids of static members by instance id.
+    static_members: HashMap<String, String>,
+    target_assignment: HashMap<String, Assignment>,
+    assignor_name: String,
+    /// Id and partition count of each subscribed topic, to notice topics
+    /// being created, deleted or growing.
+    subscribed_topics: BTreeMap<String, (Uuid, usize)>,
+}
+
+impl ConsumerGroup {
+    fn new(group_id: &str) -> ConsumerGroup {
+        ConsumerGroup {
+            group_id: group_id.to_string(),
+            group_epoch: 0,
+            assignment_epoch: 0,
+            members: BTreeMap::new(),
+            static_members: HashMap::new(),
+            target_assignment: HashMap::new(),
+            assignor_name: String::new(),
+            subscribed_topics: BTreeMap::new(),
+        }
+    }
+
+    /// The group's state as in Kafka's `ConsumerGroup`. The target assignment is
+    /// computed as soon as the group epoch moves, so the group is never seen assigning.
+    fn state(&self) -> &'static str {
+        if self.members.is_empty() {
+            "Empty"
+        } else if self
+            .members
+            .values()
+            .all(|member| member.state == MemberState::Stable && member.member_epoch == self.assignment_epoch)
+        {
+            "Stable"
+        } else {
+            "Reconciling"
+        }
+    }
+
+    fn heartbeat(
+        &mut self,
+        req: &ConsumerGroupHeartbeatRequest,
+        client_id: &str,
+        client_host: &str,
+        now: Instant,
+        image: &MetadataImage,
+    ) -> Result<HeartbeatResult, ApiError> {
+        // Only a v0 consumer joins without a member id, leaving it to the coordinator.
+        let member_id = if req.member_id.is_empty() { Uuid::new_v4().to_string() } else { req.member_id.to_string() };
+        let instance_id = req.instance_id.as_ref().map(|id| id.to_string());
+        let owned = req.topic_partitions.as_deref().map(to_assignment);
+
+        let (mut member, mut changed) = if req.member_epoch == 0 {
+            self.join_member(&member_id, instance_id.as_deref(), client_id, client_host, now)?
+        } else {
+            let Some(member) = self.members.get(&member_id) else {
+                return Err(ApiError::new(
+                    ResponseError::UnknownMemberId,
+                    format!("Member {} is not a member of group {}.", member_id, self.group_id),
+                ));
+            };
+            if instance_id.as_ref().is_some_and(|id| self.static_members.get(id) != Some(&member_id)) {
+                return Err(ApiError::new(
+                    ResponseError::FencedInstanceId,
+                    format!("Static member {} was fenced by another member with the same instance id.", member_id),
+                ));
+            }
+            check_member_epoch(member, req.member_epoch, owned.as_ref())?;
+            (member.clone(), false)
+        };
+        let previous_assignment = member.assigned.clone();
+
+        member.client_id = client_id.to_string();
+        member.client_host = client_host.to_string();
+        member.last_heartbeat = now;
+        if let Some(rack_id) = &req.rack_id {
+            member.rack_id = Some(rack_id.to_string());
+        }
+        if req.rebalance_timeout_ms >= 0 {
+            member.rebalance_timeout = Duration::from_millis(req.rebalance_timeout_ms as u64);
+        }
+        if let Some(instance_id) = &instance_id {
+            member.instance_id = Some(instance_id.clone());
+            self.static_members.insert(instance_id.clone(), member_id.clone());
+        }
+        if let Some(names) = &req.subscribed_topic_names {
+            let mut names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+            names.sort();
+            names.dedup();
+            if names != member.subscribed_topic_names {
+                member.subscribed_topic_names = names;
+                changed = true;
+            }
+        }
+        if let Some(assignor) = &req.server_assignor {
+            if member.server_assignor.as_deref() != Some(assignor.as_str()) {
+                member.server_assignor = Some(assignor.to_string());
+                changed = true;
+            }
+        }
+        self.members.insert(member_id.clone(), member);
+
+        if self.refresh_subscribed_topics(image) || changed {
+            self.bump_group_epoch(&format!("member {} joined or updated its subscription", member_id));
+        }
+
+        let mut member = self.members[&member_id].clone();
+        self.reconcile(&member_id, &mut member, owned.as_ref(), now);
+        let assignment_changed = member.assigned != previous_assignment;
+        let result = HeartbeatResult {
+            member_id: member_id.clone(),
+            member_epoch: member.member_epoch,
+            heartbeat_interval_ms: config::get().group_consumer_heartbeat_interval_ms,
+            assignment: None,
+        };
+        // A member sends its full state when it joins or recovers from an error,
+        // and then gets its full assignment back.
+        let full_request =
+            req.rebalance_timeout_ms >= 0 && req.subscribed_topic_names.is_some() && req.topic_partitions.is_some();
+        let assignment = (req.member_epoch == 0 || full_request || assignment_changed).then(|| member.assigned.clone());
+        self.members.insert(member_id, member);
+        Ok(HeartbeatResult { assignment, ..result })
+    }
+
+    /// Resolves the member a heartbeat with epoch 0 joins as. A static member
+    /// replaces the incarnation that left temporarily, taking over its assignment;
+    /// a known member rejoins after abandoning its partitions. Also returns
+    /// whether the member is new to the group.
+    fn join_member(
+        &mut self,
+        member_id: &str,
+        instance_id: Option<&str>,
+        client_id: &str,
+        client_host: &str,
+        now: Instant,
+    ) -> Result<(ConsumerMember, bool), ApiError> {
+        if let Some(instance_id) = instance_id {
+            if let Some(previous_id) = self.static_members.get(instance_id).cloned().filter(|id| id != member_id) {
+                let previous = &self.members[&previous_id];
+                if previous.member_epoch != LEAVE_GROUP_STATIC_MEMBER_EPOCH {
+                    return Err(ApiError::new(
+                        ResponseError::UnreleasedInstanceId,
+                        format!(
+                            "Static member {} with instance id {} cannot join the group because the instance id is owned by {} member.",
+                            member_id, instance_id, previous_id
+                        ),
+                    ));
+                }
+                let member = self.members.remove(&previous_id).unwrap_or_else(|| ConsumerMember::new(client_id, client_host, now));
+                if let Some(target) = self.target_assignment.remove(&previous_id) {
+                    self.target_assignment.insert(member_id.to_string(), target);
+                }
+                println!("Static member {} of group {} replaced {}", member_id, self.group_id, previous_id);
+                return Ok((member, false));
+            }
+        }
+        match self.members.get(member_id) {
+            Some(member) => {
+                let mut member = member.clone();
+                member.state = MemberState::Stable;
+                member.member_epoch = 0;
+                member.assigned.clear();
+                member.pending_revocation.clear();
+                member.revocation_deadline = None;
+                Ok((member, false))
+            }
+            None => Ok((ConsumerMember::new(client_id, client_host, now), true)),
+        }
+    }
+
+    /// A member leaves for good with epoch -1. A static member leaving with
+    /// epoch -2 keeps its partitions until its session expires.
+    fn leave(&mut self, req: &ConsumerGroupHeartbeatRequest) -> Result<HeartbeatResult, ApiError> {
+        let member_id = req.member_id.as_str();
+        if let Some(instance_id) = &req.instance_id {
+            let Some(current) = self.static_members.get(instance_id.as_str()) else {
+                return Err(ApiError::new(
+                    ResponseError::UnknownMemberId,
+                    format!("Instance id {} is unknown.", instance_id.as_str()),
+                ));
+            };
+            if current != member_id {
+                return Err(ApiError::new(
+                    ResponseError::FencedInstanceId,
+                    format!("Static member {} was fenced by another member with the same instance id.", member_id),
+                ));
+            }
+        }
+        let Some(member) = self.members.get_mut(member_id) else {
+            return Err(ApiError::new(
+                ResponseError::UnknownMemberId,
+                format!("Member {} is not a member of group {}.", member_id, self.group_id),
+            ));
+        };
+        if req.member_epoch == LEAVE_GROUP_STATIC_MEMBER_EPOCH {
+            member.member_epoch = LEAVE_GROUP_STATIC_MEMBER_EPOCH;
+            member.pending_revocation.clear();
+            member.revocation_deadline = None;
+            println!("Static member {} has temporarily left group {}", member_id, self.group_id);
+        } else {
+            println!("Member {} has left group {}", member_id, self.group_id);
+            self.remove_member(member_id);
+            self.bump_group_epoch(&format!("member {} left", member_id));
+        }
+        Ok(HeartbeatResult {
+            member_id: member_id.to_string(),
+            member_epoch: req.member_epoch,
+            heartbeat_interval_ms: 0,
+            assignment: None,
+        })
+    }
+
+    fn remove_member(&mut self, member_id: &str) {
+        let Some(member) = self.members.remove(member_id) else {
+            return;
+        };
+        if let Some(instance_id) = &member.instance_id {
+            if self.static_members.get(instance_id).is_some_and(|id| id == member_id) {
+                self.static_members.remove(instance_id);
+            }
+        }
+        self.target_assignment.remove(member_id);
+    }
+
+    /// Removes members whose session expired or that did not revoke their
+    /// partitions within their rebalance timeout.
+    fn maintain(&mut self, now: Instant) {
+        let session_timeout = Duration::from_millis(config::get().group_consumer_session_timeout_ms.max(0) as u64);
+        let expired: Vec<(String, &str)> = self
+            .members
+            .iter()
+            .filter_map(|(member_id, member)| {
+                if now >= member.last_heartbeat + session_timeout {
+                    Some((member_id.clone(), "has failed"))
+                } else if member.revocation_deadline.is_some_and(|deadline| now >= deadline) {
+                    Some((member_id.clone(), "did not revoke its partitions in time"))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if expired.is_empty() {
+            return;
+        }
+        for (member_id, reason) in &expired {
+            println!("Member {} in group {} {}, removing it from the group", member_id, self.group_id, reason);
+            self.remove_member(member_id);
+        }
+        self.bump_group_epoch("members were removed");
+    }
+
+    /// Updates the ids and partition counts of the subscribed topics, returning
+    /// whether they changed.
+    fn refresh_subscribed_topics(&mut self, image: &MetadataImage) -> bool {
+        let subscribed: BTreeMap<String, (Uuid, usize)> = self
+            .members
+            .values()
+            .flat_map(|member| member.subscribed_topic_names.iter())
+            .filter_map(|name| {
+                let topic = image.topic_by_name(name)?;
+                Some((name.clone(), (topic.topic.uuid, topic.partitions.len())))
+            })
+            .collect();
+        if subscribed == self.subscribed_topics {
+            return false;
+        }
+        self.subscribed_topics = subscribed;
+        true
+    }
+
+    /// Moves the group to a new epoch and computes the target assignment for it.
+    fn bump_group_epoch(&mut self, reason: &str) {
+        self.group_epoch += 1;
+        let assignor = self.preferred_assignor();
+        let subscriptions: BTreeMap<&String, Vec<(Uuid, usize)>> = self
+            .members
+            .iter()
+            .map(|(member_id, member)| {
+                let topics = member
+                    .subscribed_topic_names
+                    .iter()
+                    .filter_map(|name| self.subscribed_topics.get(name).copied())
+                    .collect();
+                (member_id, topics)
+            })
+            .collect();
+        let target = match assignor {
+            RANGE_ASSIGNOR => range_assign(&subscriptions),
+            _ => uniform_assign(&subscriptions, &self.target_assignment),
+        };
+        self.target_assignment = target;
+        self.assignment_epoch = self.group_epoch;
+        self.assignor_name = assignor.to_string();
+        println!(
+            "Computed target assignment of group {} for epoch {} with the {} assignor ({})",
+            self.group_id, self.group_epoch, assignor, reason
+        );
+    }
+
+    /// The assignor most members asked for, or the default one.
+    fn preferred_assignor(&self) -> &'static str {
+        let supported = supported_assignors();
+        let mut votes: BTreeMap<&'static str, usize> = BTreeMap::new();
+        for member in self.members.values() {
+            if let Some(name) = member.server_assignor.as_deref().and_then(|name| supported.iter().find(|s| **s == name)) {
+                *votes.entry(name).or_default() += 1;
+            }
+        }
+        let mut preferred: Option<(&'static str, usize)> = None;
+        for (name, count) in votes {
+            if preferred.map_or(true, |(_, best)| count > best) {
+                preferred = Some((name, count));
+            }
+        }
+        preferred.map_or_else(|| supported.first().copied().unwrap_or(UNIFORM_ASSIGNOR), |(name, _)| name)
+    }
+
+    /// Moves a member one step towards its target assignment: it first revokes
+    /// the partitions it must give up, keeping its epoch, and then moves to the
+    /// target epoch with the target partitions no other member still owns.
+    fn reconcile(&self, member_id: &str, member: &mut ConsumerMember, owned: Option<&Assignment>, now: Instant) {
+        // Without the owned partitions in the request, the member is assumed to still own them all.
+        let owns_any = |partitions: &Assignment| owned.map_or(true, |owned| intersects(owned, partitions));
+        match member.state {
+            MemberState::UnrevokedPartitions if owns_any(&member.pending_revocation) => return,
+            MemberState::Stable if member.member_epoch == self.assignment_epoch => return,
+            _ => {}
+        }
+
+        let empty = BTreeSet::new();
+        let target = self.target_assignment.get(member_id);
+        let topics: BTreeSet<Uuid> = member.assigned.keys().chain(target.into_iter().flat_map(|t| t.keys())).copied().collect();
+        let mut kept = Assignment::new();
+        let mut revoking = Assignment::new();
+        let mut unreleased = false;
+        for topic_id in topics {
+            let current = member.assigned.get(&topic_id).unwrap_or(&empty);
+            let wanted = target.and_then(|t| t.get(&topic_id)).unwrap_or(&empty);
+            for &partition in current {
+                let into = if wanted.contains(&partition) { &mut kept } else { &mut revoking };
+                into.entry(topic_id).or_default().insert(partition);
+            }
+            for &partition in wanted.difference(current) {
+                let released = !self
+                    .members
+                    .iter()
+                    .any(|(other_id, other)| other_id != member_id && other.owns(&topic_id, partition));
+                if released {
+                    kept.entry(topic_id).or_default().insert(partition);
+                } else {
+                    unreleased = true;
+                }
+            }
+        }
+
+        if !revoking.is_empty() && owns_any(&revoking) {
+            // New partitions wait until the revoked ones are released.
+            for (topic_id, partitions) in kept.iter_mut() {
+                let current = member.assigned.get(topic_id).unwrap_or(&empty);
+                partitions.retain(|partition| current.contains(partition));
+            }
+            kept.retain(|_, partitions| !partitions.is_empty());
+            member.state = MemberState::UnrevokedPartitions;
+            member.assigned = kept;
+            member.pending_revocation = revoking;
+            member.revocation_deadline = Some(now + member.rebalance_timeout);
+            return;
+        }
+
+        if member.member_epoch != self.assignment_epoch {
+            member.previous_member_epoch = member.member_epoch;
+            member.member_epoch = self.assignment_epoch;
+        }
+        member.state = if unreleased { MemberState::UnreleasedPartitions } else { MemberState::Stable };
+        member.assigned = kept;
+        member.pending_revocation.clear();
+        member.revocation_deadline = None;
+    }
+
+    fn describe(&self) -> ConsumerGroupDescription {
+        let members = self
+            .members
+            .iter()
+            .map(|(member_id, member)| ConsumerMemberDescription {
+                member_id: member_id.clone(),
+                instance_id: member.instance_id.clone(),
+                rack_id: member.rack_id.clone(),
+                member_epoch: member.member_epoch,
+                client_id: member.client_id.clone(),
+                client_host: member.client_host.clone(),
+                subscribed_topic_names: member.subscribed_topic_names.clone(),
+                assignment: member.assigned.clone(),
+                target_assignment: self.target_assignment.get(member_id).cloned().unwrap_or_default(),
+            })
+            .collect();
+        ConsumerGroupDescription {
+            state: self.state(),
+            group_epoch: self.group_epoch,
+            assignment_epoch: self.assignment_epoch,
+            assignor_name: self.assignor_name.clone(),
+            members,
+        }
+    }
+}
+
+/// A member may send the epoch it had before its last one as long as it only
+/// claims partitions it still has, since the response moving it on may have been lost.
+fn check_member_epoch(member: &ConsumerMember, epoch: i32, owned: Option<&Assignment>) -> Result<(), ApiError> {
+    if epoch > member.member_epoch {
+        return Err(ApiError::new(
+            ResponseError::FencedMemberEpoch,
+            format!(
+                "The consumer group member has a greater member epoch ({}) than the one known by the group coordinator ({}). The member must abandon all its partitions and rejoin.",
+                epoch, member.member_epoch
+            ),
+        ));
+    }
+    if epoch < member.member_epoch
+        && (epoch != member.previous_member_epoch || !owned.is_some_and(|owned| is_subset(owned, &member.assigned)))
+    {
+        return Err(ApiError::new(
+            ResponseError::FencedMemberEpoch,
+            format!(
+                "The consumer group member has a smaller member epoch ({}) than the one known by the group coordinator ({}). The member must abandon all its partitions and rejoin.",
+                epoch, member.member_epoch
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn validate_heartbeat(req: &ConsumerGroupHeartbeatRequest, version: i16) -> Result<(), ApiError> {
+    let invalid = |message: &str| Err(ApiError::new(ResponseError::InvalidRequest, message));
+    if req.group_id.is_empty() {
+        return invalid("GroupId can't be empty.");
+    }
+    if req.instance_id.as_ref().is_some_and(|id| id.is_empty()) {
+        return invalid("InstanceId can't be empty.");
+    }
+    if req.rack_id.as_ref().is_some_and(|id| id.is_empty()) {
+        return invalid("RackId can't be empty.");
+    }
+    if req.member_id.is_empty() && (version >= CONSUMER_GENERATED_MEMBER_ID_VERSION || req.member_epoch != 0) {
+        return invalid("MemberId can't be empty.");
+    }
+    match req.member_epoch {
+        0 => {
+            if req.rebalance_timeout_ms == -1 {
+                return invalid("RebalanceTimeoutMs must be provided in first request.");
+            }
+            if req.topic_partitions.as_ref().map_or(true, |partitions| !partitions.is_empty()) {
+                return invalid("TopicPartitions must be empty when (re-)joining.");
+            }
+            if req.subscribed_topic_names.is_none() && req.subscribed_topic_regex.is_none() {
+                return invalid("SubscribedTopicNames or SubscribedTopicRegex must be set in first request.");
+            }
+        }
+        LEAVE_GROUP_STATIC_MEMBER_EPOCH if req.instance_id.is_none() => return invalid("InstanceId can't be null."),
+        epoch if epoch < LEAVE_GROUP_STATIC_MEMBER_EPOCH => return invalid("MemberEpoch is invalid."),
+        _ => {}
+    }
+    if req.subscribed_topic_regex.is_some() {
+        return invalid("SubscribedTopicRegex is not supported.");
+    }
+    if let Some(assignor) = &req.server_assignor {
+        let supported = supported_assignors();
+        if !supported.contains(&assignor.as_str()) {
+            return Err(ApiError::new(
+                ResponseError::UnsupportedAssignor,
+                format!("ServerAssignor {} is not supported. Supported assignors: {}.", assignor.as_str(), supported.join(", ")),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The configured assignors we implement. Kafka's class names are accepted
+/// alongside the short names.
+fn supported_assignors() -> Vec<&'static str> {
+    config::get()
+        .group_consumer_assignors
+        .iter()
+        .filter_map(|configured| {
+            let name = configured.rsplit('.').next().unwrap_or_default().to_ascii_lowercase();
+            match name.trim_end_matches("assignor") {
+                UNIFORM_ASSIGNOR => Some(UNIFORM_ASSIGNOR),
+                RANGE_ASSIGNOR => Some(RANGE_ASSIGNOR),
+                _ => {
+                    eprintln!("Ignoring unknown consumer group assignor {}", configured);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Gives each subscriber of a topic a contiguous range of its partitions, the
+/// first members in id order taking one extra when they do not divide evenly.
+fn range_assign(subscriptions: &BTreeMap<&String, Vec<(Uuid, usize)>>) -> HashMap<String, Assignment> {
+    let mut target: HashMap<String, Assignment> =
+        subscriptions.keys().map(|member_id| (member_id.to_string(), Assignment::new())).collect();
+    let mut subscribers: BTreeMap<Uuid, (usize, Vec<&String>)> = BTreeMap::new();
+    for (member_id, topics) in subscriptions {
+        for (topic_id, num_partitions) in topics {
+            subscribers.entry(*topic_id).or_insert_with(|| (*num_partitions, Vec::new())).1.push(member_id);
+        }
+    }
+    for (topic_id, (num_partitions, members)) in subscribers {
+        let (quota, extra) = (num_partitions / members.len(), num_partitions % members.len());
+        let mut next = 0;
+        for (i, member_id) in members.into_iter().enumerate() {
+            let count = quota + usize::from(i < extra);
+            if count > 0 {
+                let partitions = (next..next + count).map(|partition| partition as i32);
+                target.entry(member_id.to_string()).or_default().entry(topic_id).or_default().extend(partitions);
+            }
+            next += count;
+        }
+    }
+    target
+}
+
+/// Spreads all subscribed partitions evenly over the members. Partitions stay
+/// with their current owner while it has room and the rest go to the least
+/// loaded subscriber. Then, going from the most loaded member down, each
+/// partition moves to its least loaded subscriber if that narrows the gap.
+fn uniform_assign(
+    subscriptions: &BTreeMap<&String, Vec<(Uuid, usize)>>,
+    current: &HashMap<String, Assignment>,
+) -> HashMap<String, Assignment> {
+    let mut candidates: BTreeMap<(Uuid, i32), Vec<&String>> = BTreeMap::new();
+    for (member_id, topics) in subscriptions {
+        for (topic_id, num_partitions) in topics {
+            for partition in 0..*num_partitions as i32 {
+                candidates.entry((*topic_id, partition)).or_default().push(member_id);
+            }
+        }
+    }
+    let mut owners: BTreeMap<(Uuid, i32), &String> = BTreeMap::new();
+    let mut load: HashMap<&String, usize> = subscriptions.keys().map(|member_id| (*member_id, 0)).collect();
+    if !subscriptions.is_empty() {
+        let max_quota = candidates.len().div_ceil(subscriptions.len());
+        let mut unassigned = Vec::new();
+        for (&(topic_id, partition), subscribers) in &candidates {
+            let owner = subscribers.iter().find(|member_id| {
+                current
+                    .get(member_id.as_str())
+                    .is_some_and(|assignment| assignment.get(&topic_id).is_some_and(|p| p.contains(&partition)))
+            });
+            match owner {
+                Some(owner) if load[owner] < max_quota => {
+                    *load.get_mut(owner).unwrap() += 1;
+                    owners.insert((topic_id, partition), owner);
+                }
+                _ => unassigned.push((topic_id, partition)),
+            }
+        }
+        for key in unassigned {
+            let Some(member_id) = candidates[&key].iter().min_by_key(|member_id| (load[*member_id], **member_id)) else {
+                continue;
+            };
+            *load.get_mut(member_id).unwrap() += 1;
+            owners.insert(key, member_id);
+        }
+        let mut owned: HashMap<&String, Vec<(Uuid, i32)>> = HashMap::new();
+        for (key, member_id) in &owners {
+            owned.entry(*member_id).or_default().push(*key);
+        }
+        let mut by_load: Vec<&String> = subscriptions.keys().copied().collect();
+        by_load.sort_by_key(|member_id| (Reverse(load[*member_id]), *member_id));
+        for from in by_load {
+            for key in owned.remove(from).unwrap_or_default() {
+                let Some(to) = candidates[&key].iter().min_by_key(|member_id| (load[*member_id], **member_id)) else {
+                    continue;
+                };
+                if load[*to] + 1 < load[from] {
+                    *load.get_mut(from).unwrap() -= 1;
+                    *load.get_mut(*to).unwrap() += 1;
+                    owners.insert(key, to);
+                }
+            }
+        }
+    }
+
+    let mut target: HashMap<String, Assignment> =
+        subscriptions.keys().map(|member_id| (member_id.to_string(), Assignment::new())).collect();
+    for ((topic_id, partition), member_id) in owners {
+        target.entry(member_id.to_string()).or_default().entry(topic_id).or_default().insert(partition);
+    }
+    target
+}
+
+fn to_assignment(topic_partitions: &[TopicPartitions]) -> Assignment {
+    let mut assignment = Assignment::new();
+    for topic in topic_partitions {
+        assignment.entry(topic.topic_id).or_default().extend(topic.partitions.iter().copied());
+    }
+    assignment
+}
+
+fn intersects(a: &Assignment, b: &Assignment) -> bool {
+    a.iter().any(|(topic_id, partitions)| b.get(topic_id).is_some_and(|other| !partitions.is_disjoint(other)))
+}
+
+fn is_subset(a: &Assignment, b: &Assignment) -> bool {
+    a.iter().all(|(topic_id, partitions)| {
+        partitions.is_empty() || b.get(topic_id).is_some_and(|other| partitions.is_subset(other))
+    })
+}
+
+/// Handles a ConsumerGroupHeartbeat: joins, leaves or keeps a member alive,
+/// moving it one step closer to its target assignment.
+pub fn heartbeat(
+    req: &ConsumerGroupHeartbeatRequest,
+    version: i16,
+    client_id: &str,
+    client_host: &str,
+) -> Result<HeartbeatResult, ApiError> {
+    validate_heartbeat(req, version)?;
+    let group_id = req.group_id.as_str();
+    let image = metadata::image();
+    group_coordinator::without_classic_group(group_id, || {
+        let mut groups = CONSUMER_GROUPS.lock().unwrap();
+        if req.member_epoch == 0 {
+            groups.entry(group_id.to_string()).or_insert_with(|| ConsumerGroup::new(group_id));
+        }
+        let Some(group) = groups.get_mut(group_id) else {
+            return Err(ApiError::new(ResponseError::GroupIdNotFound, format!("Group {} not found.", group_id)));
+        };
+        let now = Instant::now();
+        group.maintain(now);
+        if matches!(req.member_epoch, LEAVE_GROUP_MEMBER_EPOCH | LEAVE_GROUP_STATIC_MEMBER_EPOCH) {
+            group.leave(req)
+        } else {
+            group.heartbeat(req, client_id, client_host, now, &image)
+        }
+    })
+    .unwrap_or_else(|| {
+        Err(ApiError::new(ResponseError::GroupIdNotFound, format!("Group {} is not a consumer group.", group_id)))
+    })
+}
+
+pub fn describe_group(group_id: &str) -> Option<ConsumerGroupDescription> {
+    let mut groups = CONSUMER_GROUPS.lock().unwrap();
+    let group = groups.get_mut(group_id)?;
+    group.maintain(Instant::now());
+    Some(group.describe())
+}
+
+pub fn exists(group_id: &str) -> bool {
+    CONSUMER_GROUPS.lock().unwrap().contains_key(group_id)
+}
+
+pub fn list_groups() -> Vec<GroupOverview> {
+    let mut groups = CONSUMER_GROUPS.lock().unwrap();
+    let now = Instant::now();
+    groups
+        .iter_mut()
+        .map(|(group_id, group)| {
+            group.maintain(now);
+            GroupOverview {
+                group_id: group_id.clone(),
+                protocol_type: CONSUMER_GROUP_TYPE.to_string(),
+                state: group.state(),
+                group_type: CONSUMER_GROUP_TYPE,
+            }
+        })
+        .collect()
+}
+
+/// Drops the group so a classic group can take over its id, unless it has
+/// members. Returns whether the id is free.
+pub fn release(group_id: &str) -> bool {
+    let mut groups = CONSUMER_GROUPS.lock().unwrap();
+    if groups.get(group_id).is_some_and(|group| !group.members.is_empty()) {
+        return false;
+    }
+    groups.remove(group_id);
+    true
+}
+
+/// Deletes the group if it has no members, or returns `None` if there is no such group.
+pub fn delete_group(group_id: &str) -> Option<Result<(), ResponseError>> {
+    let mut groups = CONSUMER_GROUPS.lock().unwrap();
+    let group = groups.get_mut(group_id)?;
+    group.maintain(Instant::now());
+    if !group.members.is_empty() {
+        return Some(Err(ResponseError::NonEmptyGroup));
+    }
+    groups.remove(group_id);
+    Some(Ok(()))
+}
+
+/// The topics the group's members subscribe to, or `None` if there is no such group.
+pub fn subscribed_topics(group_id: &str) -> Option<HashSet<String>> {
+    let groups = CONSUMER_GROUPS.lock().unwrap();
+    let group = groups.get(group_id)?;
+    Some(group.members.values().flat_map(|member| member.subscribed_topic_names.iter().cloned()).collect())
+}
+
+/// Checks the member and epoch of an OffsetCommit to a consumer protocol group,
+/// returning `None` for other groups. Admin clients commit to empty groups
+/// with a negative epoch.
+pub fn validate_offset_commit(group_id: &str, member_id: &str, member_epoch: i32, version: i16) -> Option<Result<(), ResponseError>> {
+    let groups = CONSUMER_GROUPS.lock().unwrap();
+    let group = groups.get(group_id)?;
+    if member_epoch < 0 && group.members.is_empty() {
+        return Some(Ok(()));
+    }
+    let Some(member) = group.members.get(member_id) else {
+        return Some(Err(ResponseError::UnknownMemberId));
+    };
+    if member_epoch != member.member_epoch {
+        return Some(Err(if version >= STALE_MEMBER_EPOCH_VERSION {
+            ResponseError::StaleMemberEpoch
+        } else {
+            ResponseError::IllegalGeneration
+        }));
+    }
+    Some(Ok(()))
+}
+
+/// Checks the member and epoch of an OffsetFetch v9+. Fetches without a member
+/// id, and fetches from groups of the classic protocol, are not checked.
+pub fn validate_offset_fetch(group_id: &str, member_id: Option<&str>, member_epoch: i32) -> Result<(), ResponseError> {
+    let groups = CONSUMER_GROUPS.lock().unwrap();
+    let Some(group) = groups.get(group_id) else {
+        return Ok(());
+    };
+    let Some(member_id) = member_id else {
+        return Ok(());
+    };
+    match group.members.get(member_id) {
+        None => Err(ResponseError::UnknownMemberId),
+        Some(member) if member.member_epoch != member_epoch => Err(ResponseError::StaleMemberEpoch),
+        Some(_) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_protocol::messages::TopicName;
+    use kafka_protocol::protocol::StrBytes;
+    use crate::controller;
+    use super::*;
+
+    fn image_with(topics: &[(&str, usize)]) -> (MetadataImage, Vec<Uuid>) {
+        let mut records = Vec::new();
+        let mut ids = Vec::new();
+        for (name, num_partitions) in topics {
+            let created = controller::create_internal_topic(&MetadataImage::default(), name, *num_partitions, Vec::new());
+            ids.push(created.topic_id);
+            records.extend(created.records);
+        }
+        (MetadataImage::from_records(records), ids)
+    }
+
+    fn assignment(partitions: &[(Uuid, &[i32])]) -> Assignment {
+        partitions.iter().map(|(topic_id, p)| (*topic_id, p.iter().copied().collect())).collect()
+    }
+
+    fn owned(assignment: &Assignment) -> Vec<TopicPartitions> {
+        assignment
+            .iter()
+            .map(|(topic_id, partitions)| {
+                TopicPartitions::default().with_topic_id(*topic_id).with_partitions(partitions.iter().copied().collect())
+            })
+            .collect()
+    }
+
+    fn join(member_id: &str, instance_id: Option<&str>, topics: &[&str]) -> ConsumerGroupHeartbeatRequest {
+        ConsumerGroupHeartbeatRequest::default()
+            .with_member_id(StrBytes::from_string(member_id.to_string()))
+            .with_instance_id(instance_id.map(|id| StrBytes::from_string(id.to_string())))
+            .with_member_epoch(0)
+            .with_rebalance_timeout_ms(5000)
+            .with_subscribed_topic_names(Some(
+                topics.iter().map(|name| TopicName(StrBytes::from_string(name.to_string()))).collect(),
+            ))
+            .with_topic_partitions(Some(Vec::new()))
+    }
+
+    fn heartbeat(member_id: &str, epoch: i32, owns: &Assignment) -> ConsumerGroupHeartbeatRequest {
+        ConsumerGroupHeartbeatRequest::default()
+            .with_member_id(StrBytes::from_string(member_id.to_string()))
+            .with_member_epoch(epoch)
+            .with_topic_partitions(Some(owned(owns)))
+    }
+
+    #[test]
+    fn second_member_waits_for_revocation() {
+        let (image, ids) = image_with(&[("foo", 4)]);
+        let foo = ids[0];
+        let now = Instant::now();
+        let mut group = ConsumerGroup::new("g");
+
+        let a = group.heartbeat(&join("a", None, &["foo"]), "c", "h", now, &image).unwrap();
+        assert_eq!(a.member_epoch, 1);
+        assert_eq!(a.assignment, Some(assignment(&[(foo, &[0, 1, 2, 3])])));
+        let all = a.assignment.unwrap();
+        let a = group.heartbeat(&heartbeat("a", 1, &all), "c", "h", now, &image).unwrap();
+        assert_eq!((a.member_epoch, a.assignment), (1, None));
+        assert_eq!(group.state(), "Stable");
+
+        // B joins at the new epoch but its partitions are still A's.
+        let b = group.heartbeat(&join("b", None, &["foo"]), "c", "h", now, &image).unwrap();
+        assert_eq!(group.group_epoch, 2);
+        assert_eq!(b.member_epoch, 2);
+        assert_eq!(b.assignment, Some(Assignment::new()));
+        assert_eq!(group.members["b"].state, MemberState::UnreleasedPartitions);
+        assert_eq!(group.state(), "Reconciling");
+
+        // A must revoke half of its partitions before moving to epoch 2.
+        let a = group.heartbeat(&heartbeat("a", 1, &all), "c", "h", now, &image).unwrap();
+        assert_eq!(a.member_epoch, 1);
+        assert_eq!(a.assignment, Some(assignment(&[(foo, &[0, 1])])));
+        assert_eq!(group.members["a"].state, MemberState::UnrevokedPartitions);
+        assert_eq!(group.members["a"].pending_revocation, assignment(&[(foo, &[2, 3])]));
+
+        let b = group.heartbeat(&heartbeat("b", 2, &Assignment::new()), "c", "h", now, &image).unwrap();
+        assert_eq!((b.member_epoch, b.assignment), (2, None));
+
+        let kept = assignment(&[(foo, &[0, 1])]);
+        let a = group.heartbeat(&heartbeat("a", 1, &kept), "c", "h", now, &image).unwrap();
+        assert_eq!((a.member_epoch, a.assignment), (2, None));
+        assert_eq!(group.members["a"].state, MemberState::Stable);
+
+        let b = group.heartbeat(&heartbeat("b", 2, &Assignment::new()), "c", "h", now, &image).unwrap();
+        assert_eq!(b.assignment, Some(assignment(&[(foo, &[2, 3])])));
+        assert_eq!(group.state(), "Stable");
+    }
+
+    #[test]
+    fn reconcile_assumes_partitions_are_owned_without_the_owned_list() {
+        let (image, ids) = image_with(&[("foo", 2)]);
+        let foo = ids[0];
+        let now = Instant::now();
+        let mut group = ConsumerGroup::new("g");
+        group.heartbeat(&join("a", None, &["foo"]), "c", "h", now, &image).unwrap();
+        group.heartbeat(&join("b", None, &["foo"]), "c", "h", now, &image).unwrap();
+
+        let mut a = group.members["a"].clone();
+        group.reconcile("a", &mut a, None, now);
+        assert_eq!(a.state, MemberState::UnrevokedPartitions);
+        group.reconcile("a", &mut a, None, now);
+        assert_eq!((a.state, a.member_epoch), (MemberState::UnrevokedPartitions, 1));
+
+        group.reconcile("a", &mut a, Some(&assignment(&[(foo, &[0])])), now);
+        assert_eq!((a.state, a.member_epoch), (MemberState::Stable, 2));
+        assert_eq!(a.assigned, assignment(&[(foo, &[0])]));
+    }
+
+    #[test]
+    fn previous_member_epoch_is_tolerated_for_owned_partitions() {
+        let foo = Uuid::new_v4();
+        let mut member = ConsumerMember::new("c", "h", Instant::now());
+        member.member_epoch = 3;
+        member.previous_member_epoch = 2;
+        member.assigned = assignment(&[(foo, &[0, 1])]);
+        let fenced = |result: Result<(), ApiError>| result.unwrap_err().error == ResponseError::FencedMemberEpoch;
+
+        assert!(check_member_epoch(&member, 3, None).is_ok());
+        assert!(check_member_epoch(&member, 2, Some(&assignment(&[(foo, &[0])]))).is_ok());
+        assert!(fenced(check_member_epoch(&member, 2, Some(&assignment(&[(foo, &[0, 2])])))));
+        assert!(fenced(check_member_epoch(&member, 2, None)));
+        assert!(fenced(check_member_epoch(&member, 1, Some(&Assignment::new()))));
+        assert!(fenced(check_member_epoch(&member, 4, None)));
+    }
+
+    #[test]
+    fn static_member_takes_over_after_temporary_leave() {
+        let (image, ids) = image_with(&[("foo", 2)]);
+        let foo = ids[0];
+        let now = Instant::now();
+        let mut group = ConsumerGroup::new("g");
+        group.heartbeat(&join("s1", Some("i"), &["foo"]), "c", "h", now, &image).unwrap();
+
+        let error = group.heartbeat(&join("s2", Some("i"), &["foo"]), "c", "h", now, &image).unwrap_err();
+        assert_eq!(error.error, ResponseError::UnreleasedInstanceId);
+
+        let leave = ConsumerGroupHeartbeatRequest::default()
+            .with_member_id(StrBytes::from_static_str("s1"))
+            .with_instance_id(Some(StrBytes::from_static_str("i")))
+            .with_member_epoch(LEAVE_GROUP_STATIC_MEMBER_EPOCH);
+        group.leave(&leave).unwrap();
+        assert_eq!(group.group_epoch, 1);
+
+        let s2 = group.heartbeat(&join("s2", Some("i"), &["foo"]), "c", "h", now, &image).unwrap();
+        assert_eq!(s2.member_epoch, 1);
+        assert_eq!(s2.assignment, Some(assignment(&[(foo, &[0, 1])])));
+        assert_eq!(group.group_epoch, 1);
+        assert!(!group.members.contains_key("s1"));
+        assert_eq!(group.static_members["i"], "s2");
+    }
+
+    #[test]
+    fn range_gives_the_first_members_the_extra_partitions() {
+        let (foo, bar) = (Uuid::new_v4(), Uuid::new_v4());
+        let (a, b) = ("a".to_string(), "b".to_string());
+        let subscriptions = BTreeMap::from([(&a, vec![(foo, 5), (bar, 1)]), (&b, vec![(foo, 5)])]);
+        let target = range_assign(&subscriptions);
+        assert_eq!(target["a"], assignment(&[(foo, &[0, 1, 2]), (bar, &[0])]));
+        assert_eq!(target["b"], assignment(&[(foo, &[3, 4])]));
+    }
+
+    #[test]
+    fn uniform_balances_and_keeps_current_owners() {
+        let foo = Uuid::new_v4();
+        let members: Vec<String> = ["a", "b", "c"].iter().map(|m| m.to_string()).collect();
+        let subscriptions: BTreeMap<&String, Vec<(Uuid, usize)>> = members.iter().map(|m| (m, vec![(foo, 7)])).collect();
+        let current = HashMap::from([("a".to_string(), assignment(&[(foo, &[0, 1, 2, 3, 4, 5, 6])]))]);
+        let target = uniform_assign(&subscriptions, &current);
+        let loads: Vec<usize> = members.iter().map(|m| target[m][&foo].len()).collect();
+        assert_eq!(loads, vec![3, 2, 2]);
+        assert!(is_subset(&target["a"], &current["a"]));
+        let all: BTreeSet<i32> = target.values().flat_map(|a| a[&foo].iter().copied()).collect();
+        assert_eq!(all.len(), 7);
+    }
+
+    #[test]
+    fn uniform_moves_partitions_off_members_with_more_subscribers() {
+        let (foo, bar) = (Uuid::new_v4(), Uuid::new_v4());
+        let (a, b) = ("a".to_string(), "b".to_string());
+        let subscriptions = BTreeMap::from([(&a, vec![(foo, 2), (bar, 2)]), (&b, vec![(bar, 2)])]);
+        // A keeps bar while it has room and then has to take foo, which only it subscribes to.
+        let current = HashMap::from([("a".to_string(), assignment(&[(bar, &[0, 1])]))]);
+        let target = uniform_assign(&subscriptions, &current);
+        assert_eq!(target["a"], assignment(&[(foo, &[0, 1])]));
+        assert_eq!(target["b"], assignment(&[(bar, &[0, 1])]));
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{LazyLock, Mutex};
+use anyhow::bail;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use kafka_protocol::records::{Compression, Record, RecordBatchDecoder, RecordBatchEncoder, RecordEncodeOptions, TimestampType};
+use crate::log::{get_log, now_ms, partition_dir};
+use crate::utils::{scan_batches, write_records};
+use crate::{config, controller, metadata};
+
+pub const CONSUMER_OFFSETS_TOPIC: &str = "__consumer_offsets";
+
+/// Key versions 0 and 1 are offset commits; later versions hold group metadata.
+const OFFSET_COMMIT_KEY_VERSION: i16 = 1;
+const OFFSET_COMMIT_VALUE_VERSION: i16 = 3;
+
+/// A group's committed offsets by topic name and partition.
+pub type GroupOffsets = BTreeMap<(String, i32), CommittedOffset>;
+
+static OFFSETS: LazyLock<Mutex<HashMap<String, GroupOffsets>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone)]
+pub struct CommittedOffset {
+    pub offset: i64,
+    pub leader_epoch: i32,
+    pub metadata: String,
+    pub commit_timestamp: i64,
+}
+
+/// The `__consumer_offsets` partition holding a group's commits. Kafka takes the
+/// group id's Java `String.hashCode` modulo the partition count.
+pub fn partition_for(group_id: &str) -> u32 {
+    let hash = group_id
+        .encode_utf16()
+        .fold(0i32, |hash, unit| hash.wrapping_mul(31).wrapping_add(unit as i32));
+    (hash & i32::MAX) as u32 % config::get().offsets_topic_num_partitions.max(1) as u32
+}
+
+/// Appends a group's commits to its `__consumer_offsets` partition, then makes
+/// them visible to OffsetFetch. The cache stays locked across the append so it
+/// applies commits in log order.
+pub fn commit(group_id: &str, offsets: Vec<((String, i32), CommittedOffset)>) -> anyhow::Result<()> {
+    if offsets.is_empty() {
+        return Ok(());
+    }
+    let mut cache = OFFSETS.lock().unwrap();
+
+    let records = offsets
+        .iter()
+        .map(|((topic, partition), committed)| {
+            (encode_key(group_id, topic, *partition), Some(encode_value(committed)), committed.commit_timestamp)
+        })
+        .collect();
+    append(group_id, records)?;
+
+    cache.entry(group_id.to_string()).or_default().extend(offsets);
+    Ok(())
+}
+
+/// Removes a group's committed offsets for the given partitions, or all of
+/// them, by appending tombstones. Returns the partitions that had an offset.
+pub fn delete(group_id: &str, partitions: Option<&[(String, i32)]>) -> anyhow::Result<Vec<(String, i32)>> {
+    let mut cache = OFFSETS.lock().unwrap();
+    let Some(offsets) = cache.get_mut(group_id) else {
+        return Ok(Vec::new());
+    };
+    let deleted: Vec<(String, i32)> = match partitions {
+        Some(partitions) => partitions.iter().filter(|key| offsets.contains_key(*key)).cloned().collect(),
+        None => offsets.keys().cloned().collect(),
+    };
+    if deleted.is_empty() {
+        return Ok(deleted);
+    }
+
+    let timestamp = now_ms();
+    let records = deleted
+        .iter()
+        .map(|(topic, partition)| (encode_key(group_id, topic, *partition), None, timestamp))
+        .collect();
+    append(group_id, records)?;
+
+    for key in &deleted {
+        offsets.remove(key);
+    }
+    if offsets.is_empty() {
+        cache.remove(group_id);
+    }
+    Ok(deleted)
+}
+
+/// Writes keyed records, tombstones having no value, to the group's partition
+/// as one batch, so a request's commits are stored all or nothing.
+fn append(group_id: &str, records: Vec<(Bytes, Option<Bytes>, i64)>) -> anyhow::Result<()> {
+    let records: Vec<Record> = records
+        .into_iter()
+        .enumerate()
+        .map(|(i, (key, value, timestamp))| Record {
+            transactional: false,
+            control: false,
+            partition_leader_epoch: 0,
+            producer_id: -1,
+            producer_epoch: -1,
+            timestamp_type: TimestampType::Creation,
+            offset: i as i64,
+            // Keeps the commits in one batch, as the encoder splits wherever offset and sequence diverge.
+            sequence: i as i32,
+            timestamp,
+            key: Some(key),
+            value,
+            headers: Default::default(),
+        })
+        .collect();
+    let mut batch = BytesMut::new();
+    RecordBatchEncoder::encode(
+        &mut batch,
+        &records,
+        &RecordEncodeOptions { version: 2, compression: Compression::None },
+    )?;
+    ensure_topic()?;
+    write_records(CONSUMER_OFFSETS_TOPIC, partition_for(group_id), 0, batch.freeze())?;
+    Ok(())
+}
+
+/// Every group with at least one committed offset.
+pub fn groups() -> Vec<String> {
+    OFFSETS.lock().unwrap().keys().cloned().collect()
+}
+
+/// Every offset the group has committed.
+pub fn fetch(group_id: &str) -> GroupOffsets {
+    OFFSETS.lock().unwrap().get(group_id).cloned().unwrap_or_default()
+}
+
+/// Rebuilds the offset cache by replaying every `__consumer_offsets` partition
+/// on disk. A commit without a value is a tombstone that removes the offset.
+/// Batches and records that fail to decode are logged and skipped.
+pub fn load() -> anyhow::Result<()> {
+    let mut cache = OFFSETS.lock().unwrap();
+    let mut found = false;
+    for partition in 0..config::get().offsets_topic_num_partitions.max(1) as u32 {
+        if !partition_dir(CONSUMER_OFFSETS_TOPIC, partition).exists() {
+            continue;
+        }
+        found = true;
+        let data = {
+            let log = get_log(CONSUMER_OFFSETS_TOPIC, partition)?;
+            let log = log.lock().unwrap();
+            log.read(log.offsets().log_start_offset, usize::MAX, true)?
+        };
+        for header in scan_batches(&data) {
+            let mut batch = data.slice(header.position..header.position + header.total_size());
+            let records = match RecordBatchDecoder::decode(&mut batch) {
+                Ok(batch) => batch.records,
+                Err(e) => {
+                    eprintln!(
+                        "Skipping undecodable batch at {}-{} offset {}: {}",
+                        CONSUMER_OFFSETS_TOPIC, partition, header.base_offset, e
+                    );
+                    continue;
+                }
+            };
+            for record in records {
+                if let Err(e) = replay(&mut cache, &record) {
+                    eprintln!(
+                        "Skipping invalid record at {}-{} offset {}: {}",
+                        CONSUMER_OFFSETS_TOPIC, partition, record.offset, e
+                    );
+                }
+            }
+        }
+    }
+    if found {
+        ensure_topic()?;
+    }
+    cache.retain(|_, offsets| !offsets.is_empty());
+    println!("Loaded committed offsets of {} groups", cache.len());
+    Ok(())
+}
+
+fn replay(cache: &mut HashMap<String, GroupOffsets>, record: &Record) -> anyhow::Result<()> {
+    if record.control {
+        return Ok(());
+    }
+    let Some(key) = record.key.clone() else {
+        return Ok(());
+    };
+    let Some((group_id, topic, partition)) = decode_key(key)? else {
+        return Ok(());
+    };
+    match record.value.clone() {
+        Some(value) => {
+            cache.entry(group_id).or_default().insert((topic, partition), decode_value(value)?);
+        }
+        None => {
+            if let Some(offsets) = cache.get_mut(&group_id) {
+                offsets.remove(&(topic, partition));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Registers `__consumer_offsets` in the cluster metadata the first time a
+/// group writes to it, so clients see it as an internal topic.
+fn ensure_topic() -> anyhow::Result<()> {
+    if metadata::image().topic_by_name(CONSUMER_OFFSETS_TOPIC).is_some() {
+        return Ok(());
+    }
+    metadata::write(|image| {
+        if image.topic_by_name(CONSUMER_OFFSETS_TOPIC).is_some() {
+            return (Vec::new(), ());
+        }
+        let created = controller::create_internal_topic(
+            image,
+            CONSUMER_OFFSETS_TOPIC,
+            config::get().offsets_topic_num_partitions.max(1) as usize,
+            vec![("cleanup.policy".to_string(), "compact".to_string())],
+        );
+        println!("Created internal topic {} with id {}", CONSUMER_OFFSETS_TOPIC, created.topic_id);
+        (created.records, ())
+    })
+}
+
+fn encode_key(group_id: &str, topic: &str, partition: i32) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_i16(OFFSET_COMMIT_KEY_VERSION);
+    put_string(&mut buf, group_id);
+    put_string(&mut buf, topic);
+    buf.put_i32(partition);
+    buf.freeze()
+}
+
+fn encode_value(committed: &CommittedOffset) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_i16(OFFSET_COMMIT_VALUE_VERSION);
+    buf.put_i64(committed.offset);
+    buf.put_i32(committed.leader_epoch);
+    put_string(&mut buf, &committed.metadata);
+    buf.put_i64(committed.commit_timestamp);
+    buf.freeze()
+}
+
+/// Returns `None` for keys of other record kinds, such as group metadata.
+fn decode_key(mut buf: Bytes) -> anyhow::Result<Option<(String, String, i32)>> {
+    let version = get_i16(&mut buf)?;
+    if version > OFFSET_COMMIT_KEY_VERSION {
+        return Ok(None);
+    }
+    let group_id = get_string(&mut buf)?;
+    let topic = get_string(&mut buf)?;
+    let partition = get_i32(&mut buf)?;
+    Ok(Some((group_id, topic, partition)))
+}
+
+/// Reads every value version Kafka has written: v1 adds an expire timestamp,
+/// v2 drops it again and v3 adds the leader epoch.
+fn decode_value(mut buf: Bytes) -> anyhow::Result<CommittedOffset> {
+    let version = get_i16(&mut buf)?;
+    if !(0..=OFFSET_COMMIT_VALUE_VERSION).contains(&version) {
+        bail!("Unsupported offset commit value version {}", version);
+    }
+    let offset = get_i64(&mut buf)?;
+    let leader_epoch = if version >= 3 { get_i32(&mut buf)? } else { -1 };
+    let metadata = get_string(&mut buf)?;
+    let commit_timestamp = get_i64(&mut buf)?;
+    Ok(CommittedOffset {
+        offset,
+        leader_epoch,
+        metadata,
+        commit_timestamp,
+    })
+}
+
+fn put_string(buf: &mut BytesMut, value: &str) {
+    buf.put_i16(value.len() as i16);
+    buf.put_slice(value.as_bytes());
+}
+
+fn get_i16(buf: &mut Bytes) -> anyhow::Result<i16> {
+    if buf.remaining() < 2 {
+        bail!("Truncated record");
+    }
+    Ok(buf.get_i16())
+}
+
+fn get_i32(buf: &mut Bytes) -> anyhow::Result<i32> {
+    if buf.remaining() < 4 {
+        bail!("Truncated record");
+    }
+    Ok(buf.get_i32())
+}
+
+fn get_i64(buf: &mut Bytes) -> anyhow::Result<i64> {
+    if buf.remaining() < 8 {
+        bail!("Truncated record");
+    }
+    Ok(buf.get_i64())
+}
+
+fn get_string(buf: &mut Bytes) -> anyhow::Result<String> {
+    let len = get_i16(buf)?;
+    if len < 0 || buf.remaining() < len as usize {
+        bail!("Truncated record");
+    }
+    Ok(String::from_utf8(buf.split_to(len as usize).to_vec())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn committed(offset: i64) -> CommittedOffset {
+        CommittedOffset { offset, leader_epoch: 3, metadata: "meta".to_string(), commit_timestamp: 1000 }
+    }
+
+    fn record(key: Bytes, value: Option<Bytes>) -> Record {
+        Record {
+            transactional: false,
+            control: false,
+            partition_leader_epoch: 0,
+            producer_id: -1,
+            producer_epoch: -1,
+            timestamp_type: TimestampType::Creation,
+            offset: 0,
+            sequence: -1,
+            timestamp: 0,
+            key: Some(key),
+            value,
+            headers: Default::default(),
+        }
+    }
+
+    #[test]
+    fn key_v1_and_value_v3_use_kafka_layout() {
+        let key = encode_key("g", "foo", 2);
+        assert_eq!(&key[..], &[0, 1, 0, 1, b'g', 0, 3, b'f', b'o', b'o', 0, 0, 0, 2]);
+        assert_eq!(decode_key(key).unwrap(), Some(("g".to_string(), "foo".to_string(), 2)));
+
+        let value = encode_value(&committed(42));
+        let mut expected = vec![0, 3];
+        expected.extend_from_slice(&42i64.to_be_bytes());
+        expected.extend_from_slice(&3i32.to_be_bytes());
+        expected.extend_from_slice(&[0, 4, b'm', b'e', b't', b'a']);
+        expected.extend_from_slice(&1000i64.to_be_bytes());
+        assert_eq!(&value[..], &expected[..]);
+        let decoded = decode_value(value).unwrap();
+        assert_eq!((decoded.offset, decoded.leader_epoch, decoded.metadata.as_str(), decoded.commit_timestamp), (42, 3, "meta", 1000));
+    }
+
+    #[test]
+    fn older_values_and_other_keys_are_understood() {
+        // v1 carries an expire timestamp after the commit timestamp and no leader epoch.
+        let mut value = BytesMut::new();
+        value.put_i16(1);
+        value.put_i64(7);
+        put_string(&mut value, "");
+        value.put_i64(1000);
+        value.put_i64(2000);
+        let decoded = decode_value(value.freeze()).unwrap();
+        assert_eq!((decoded.offset, decoded.leader_epoch, decoded.commit_timestamp), (7, -1, 1000));
+
+        // Key v2 is group metadata, which the offset cache does not hold.
+        let mut key = BytesMut::new();
+        key.put_i16(2);
+        put_string(&mut key, "g");
+        assert_eq!(decode_key(key.freeze()).unwrap(), None);
+
+        assert!(decode_value(Bytes::from_static(&[0, 4, 0, 0])).is_err());
+        assert!(decode_key(Bytes::from_static(&[0, 1, 0, 5, b'g'])).is_err());
+    }
+
+    #[test]
+    fn replay_applies_commits_and_tombstones() {
+        let mut cache = HashMap::new();
+        replay(&mut cache, &record(encode_key("g", "foo", 0), Some(encode_value(&committed(1))))).unwrap();
+        replay(&mut cache, &record(encode_key("g", "foo", 1), Some(encode_value(&committed(2))))).unwrap();
+        replay(&mut cache, &record(encode_key("g", "foo", 0), Some(encode_value(&committed(5))))).unwrap();
+        replay(&mut cache, &record(encode_key("g", "foo", 1), None)).unwrap();
+        assert!(replay(&mut cache, &record(encode_key("g", "bar", 0), Some(Bytes::from_static(&[0, 3])))).is_err());
+
+        let offsets = &cache["g"];
+        assert_eq!(offsets.len(), 1);
+        assert_eq!(offsets[&("foo".to_string(), 0)].offset, 5);
+    }
+}
@@ -0,0 +1,814 @@
+use std::collections::{HashMap, HashSet};
+use kafka_protocol::messages::create_partitions_request::CreatePartitionsTopic;
+use kafka_protocol::messages::create_topics_request::CreatableTopic;
+use kafka_protocol::messages::TopicName;
+use kafka_protocol::ResponseError;
+use uuid::Uuid;
+use crate::config;
+use crate::consumer_offsets::CONSUMER_OFFSETS_TOPIC;
+use crate::meta_parser::{ConfigRecord, Header, MetadataRecord, PartitionRecord, RecordType, RemoveTopicRecord, TopicRecord, METADATA_TOPIC};
+use crate::metadata::MetadataImage;
+
+/// `ConfigResource.Type.TOPIC`, the resource type of per-topic ConfigRecords.
+pub const TOPIC_RESOURCE_TYPE: i8 = 2;
+
+const MAX_TOPIC_NAME_LENGTH: usize = 249;
+
+/// Topics Kafka manages itself, as in its `Topic.isInternal`.
+const INTERNAL_TOPICS: &[&str] = &[CONSUMER_OFFSETS_TOPIC, "__transaction_state", "__share_group_state"];
+
+/// Per-topic configs this broker applies to a topic's log, with their type and
+/// the smallest value Kafka's `LogConfig` accepts.
+const APPLIED_TOPIC_CONFIGS: &[(&str, ConfigType, i64)] = &[
+    ("index.interval.bytes", ConfigType::Int, 0),
+    ("segment.bytes", ConfigType::Int, 14),
+    ("segment.ms", ConfigType::Long, 1),
+];
+
+#[derive(Debug, Clone, Copy)]
+enum ConfigType {
+    Int,
+    Long,
+}
+
+/// Per-topic configs Kafka's `LogConfig` accepts.
+const TOPIC_CONFIGS: &[&str] = &[
+    "cleanup.policy",
+    "compression.type",
+    "compression.gzip.level",
+    "compression.lz4.level",
+    "compression.zstd.level",
+    "delete.retention.ms",
+    "file.delete.delay.ms",
+    "flush.messages",
+    "flush.ms",
+    "follower.replication.throttled.replicas",
+    "index.interval.bytes",
+    "leader.replication.throttled.replicas",
+    "local.retention.bytes",
+    "local.retention.ms",
+    "max.compaction.lag.ms",
+    "max.message.bytes",
+    "message.downconversion.enable",
+    "message.timestamp.after.max.ms",
+    "message.timestamp.before.max.ms",
+    "message.timestamp.difference.max.ms",
+    "message.timestamp.type",
+    "min.cleanable.dirty.ratio",
+    "min.compaction.lag.ms",
+    "min.insync.replicas",
+    "preallocate",
+    "remote.storage.enable",
+    "retention.bytes",
+    "retention.ms",
+    "segment.bytes",
+    "segment.index.bytes",
+    "segment.jitter.ms",
+    "segment.ms",
+    "unclean.leader.election.enable",
+];
+
+/// An error for one topic of an admin request, with the message sent to the client.
+#[derive(Debug)]
+pub struct ApiError {
+    pub error: ResponseError,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(error: ResponseError, message: impl Into<String>) -> Self {
+        ApiError { error, message: message.into() }
+    }
+}
+
+/// The outcome of planning a topic creation, before anything is written.
+#[derive(Debug)]
+pub struct CreatedTopic {
+    pub topic_id: Uuid,
+    pub num_partitions: i32,
+    pub replication_factor: i16,
+    pub records: Vec<RecordType>,
+}
+
+/// A topic a DeleteTopics request removed, with the partitions whose logs go with it.
+#[derive(Debug)]
+pub struct DeletedTopic {
+    pub name: String,
+    pub topic_id: Uuid,
+    pub partitions: Vec<u32>,
+}
+
+pub fn is_internal_topic(name: &str) -> bool {
+    INTERNAL_TOPICS.contains(&name)
+}
+
+/// Topics only the broker itself may create, write to or delete: the internal
+/// topics and the KRaft metadata log.
+pub fn is_reserved_topic(name: &str) -> bool {
+    is_internal_topic(name) || name == METADATA_TOPIC
+}
+
+/// Validates a topic name the way Kafka's `Topic.validate` does.
+pub fn validate_topic_name(name: &str) -> Result<(), ApiError> {
+    let invalid = |reason: String| Err(ApiError::new(ResponseError::InvalidTopicException, reason));
+    if name.is_empty() {
+        return invalid("Topic name is illegal, it can't be empty".to_string());
+    }
+    if name == "." || name == ".." {
+        return invalid("Topic name cannot be \".\" or \"..\"".to_string());
+    }
+    if name.len() > MAX_TOPIC_NAME_LENGTH {
+        return invalid(format!(
+            "Topic name is illegal, it can't be longer than {} characters, topic name: {}",
+            MAX_TOPIC_NAME_LENGTH, name
+        ));
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-') {
+        return invalid(format!(
+            "Topic name {} is illegal, it contains a character other than ASCII alphanumerics, '.', '_' and '-'",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Brokers partitions can be placed on. This broker serves every partition
+/// itself, so it is the only one.
+fn usable_brokers() -> Vec<i32> {
+    vec![config::get().node_id]
+}
+
+/// Plans a CreateTopics request against one image, so all of its new topics are
+/// written in one batch. Returns no records when `validate_only` is set.
+pub fn create_topics(
+    image: &MetadataImage,
+    topics: &[CreatableTopic],
+    validate_only: bool,
+) -> (Vec<RecordType>, Vec<Result<CreatedTopic, ApiError>>) {
+    let mut name_counts: HashMap<&str, usize> = HashMap::new();
+    for topic in topics {
+        *name_counts.entry(topic.name.as_str()).or_default() += 1;
+    }
+
+    let mut records = Vec::new();
+    let mut accepted = Vec::new();
+    let outcomes = topics
+        .iter()
+        .map(|topic| {
+            if name_counts[topic.name.as_str()] > 1 {
+                return Err(ApiError::new(
+                    ResponseError::InvalidRequest,
+                    format!("Create topics request contains duplicate topic name {}", topic.name.as_str()),
+                ));
+            }
+            let mut created = create_topic(image, topic, &accepted)?;
+            accepted.push(topic.name.to_string());
+            if !validate_only {
+                records.append(&mut created.records);
+            }
+            Ok(created)
+        })
+        .collect();
+    (records, outcomes)
+}
+
+/// Plans the records that create `topic`, validating it against `image` and
+/// against the topics `accepted` earlier in the same request.
+pub fn create_topic(image: &MetadataImage, topic: &CreatableTopic, accepted: &[String]) -> Result<CreatedTopic, ApiError> {
+    let name = topic.name.as_str();
+    validate_topic_name(name)?;
+    if is_reserved_topic(name) {
+        return Err(ApiError::new(
+            ResponseError::InvalidRequest,
+            format!("Creation of internal topic {} is prohibited.", name),
+        ));
+    }
+    if image.topic_by_name(name).is_some() {
+        return Err(ApiError::new(
+            ResponseError::TopicAlreadyExists,
+            format!("Topic '{}' already exists.", name),
+        ));
+    }
+    // '.' and '_' collide in metric names, so Kafka refuses names differing only in them.
+    let normalized = name.replace('.', "_");
+    let existing = image.topics().map(|tp| &tp.topic.name).chain(accepted);
+    if let Some(existing) = existing.into_iter().find(|existing| existing.replace('.', "_") == normalized) {
+        return Err(ApiError::new(
+            ResponseError::InvalidTopicException,
+            format!("Topic '{}' collides with existing topic: {}", name, existing),
+        ));
+    }
+
+    let assignments = assign_replicas(topic)?;
+    let configs = validate_configs(topic)?;
+    Ok(topic_records(image, name, assignments, configs))
+}
+
+/// Plans the records that create an internal topic on this broker's behalf,
+/// skipping the checks that keep clients from creating one.
+pub fn create_internal_topic(
+    image: &MetadataImage,
+    name: &str,
+    num_partitions: usize,
+    configs: Vec<(String, String)>,
+) -> CreatedTopic {
+    let assignments = place_replicas(&usable_brokers(), 0, num_partitions, 1);
+    topic_records(image, name, assignments, configs)
+}
+
+fn topic_records(
+    image: &MetadataImage,
+    name: &str,
+    assignments: Vec<Vec<i32>>,
+    configs: Vec<(String, String)>,
+) -> CreatedTopic {
+    let topic_id = loop {
+        let candidate = Uuid::new_v4();
+        if image.topic_by_id(candidate).is_none() {
+            break candidate;
+        }
+    };
+
+    let mut records = vec![RecordType::TopicValue(TopicRecord {
+        header: Header::new(TopicRecord::TYPE, 0),
+        name: name.to_string(),
+        uuid: topic_id,
+    })];
+    records.extend(configs.into_iter().map(|(config_name, value)| {
+        RecordType::ConfigValue(ConfigRecord {
+            header: Header::new(ConfigRecord::TYPE, 0),
+            resource_type: TOPIC_RESOURCE_TYPE,
+            resource_name: name.to_string(),
+            name: config_name,
+            value: Some(value),
+        })
+    }));
+    let replication_factor = assignments.first().map_or(0, |replicas| replicas.len() as i16);
+    let num_partitions = assignments.len() as i32;
+    records.extend(
+        assignments
+            .into_iter()
+            .enumerate()
+            .map(|(partition_id, replicas)| RecordType::PartitionValue(new_partition(topic_id, partition_id as u32, replicas))),
+    );
+
+    CreatedTopic { topic_id, num_partitions, replication_factor, records }
+}
+
+/// The record deleting a topic. Replaying it drops the topic's partitions and
+/// configs along with it.
+pub fn delete_topic(topic_id: Uuid) -> RecordType {
+    RecordType::RemoveTopicValue(RemoveTopicRecord {
+        header: Header::new(RemoveTopicRecord::TYPE, 0),
+        topic_id,
+    })
+}
+
+/// Plans a DeleteTopics request. Each topic is given by name or, from v6, by
+/// id (a nil id meaning none); the deletions are written in one batch.
+pub fn delete_topics(
+    image: &MetadataImage,
+    requested: &[(Option<TopicName>, Uuid)],
+) -> (Vec<RecordType>, Vec<Result<DeletedTopic, ApiError>>) {
+    let mut name_counts: HashMap<&str, usize> = HashMap::new();
+    let mut id_counts: HashMap<Uuid, usize> = HashMap::new();
+    for (name, topic_id) in requested {
+        match name {
+            Some(name) => *name_counts.entry(name.as_str()).or_default() += 1,
+            None => *id_counts.entry(*topic_id).or_default() += 1,
+        }
+    }
+
+    let mut records = Vec::new();
+    let mut deleting = HashSet::new();
+    let outcomes = requested
+        .iter()
+        .map(|(name, topic_id)| {
+            let matched_topic = match (name, topic_id.is_nil()) {
+                (Some(_), false) => {
+                    return Err(ApiError::new(ResponseError::InvalidRequest, "You may not specify both topic name and topic id."));
+                }
+                (None, true) => {
+                    return Err(ApiError::new(ResponseError::InvalidRequest, "Neither topic name nor id were specified."));
+                }
+                (Some(name), true) => {
+                    if name_counts[name.as_str()] > 1 {
+                        return Err(ApiError::new(ResponseError::InvalidRequest, "Duplicate topic name."));
+                    }
+                    image.topic_by_name(name).ok_or_else(|| {
+                        ApiError::new(ResponseError::UnknownTopicOrPartition, "This server does not host this topic-partition.")
+                    })?
+                }
+                (None, false) => {
+                    if id_counts[topic_id] > 1 {
+                        return Err(ApiError::new(ResponseError::InvalidRequest, "Duplicate topic id."));
+                    }
+                    image.topic_by_id(*topic_id).ok_or_else(|| {
+                        ApiError::new(ResponseError::UnknownTopicId, "This server does not host this topic ID.")
+                    })?
+                }
+            };
+            // The same topic named once by name and once by id.
+            if !deleting.insert(matched_topic.topic.uuid) {
+                return Err(ApiError::new(ResponseError::InvalidRequest, "Duplicate topic."));
+            }
+            if is_reserved_topic(&matched_topic.topic.name) {
+                return Err(ApiError::new(
+                    ResponseError::InvalidRequest,
+                    format!("Deletion of internal topic {} is prohibited.", matched_topic.topic.name),
+                ));
+            }
+            records.push(delete_topic(matched_topic.topic.uuid));
+            Ok(DeletedTopic {
+                name: matched_topic.topic.name.clone(),
+                topic_id: matched_topic.topic.uuid,
+                partitions: matched_topic.partitions.iter().map(|p| p.partition_id).collect(),
+            })
+        })
+        .collect();
+    (records, outcomes)
+}
+
+/// Plans the partitions that grow `topic` to `count` partitions. New partitions
+/// keep the topic's replication factor and are placed round-robin after the
+/// existing ones unless the request assigns their replicas.
+pub fn create_partitions(image: &MetadataImage, topic: &CreatePartitionsTopic) -> Result<Vec<PartitionRecord>, ApiError> {
+    let name = topic.name.as_str();
+    let Some(existing) = image.topic_by_name(name) else {
+        return Err(ApiError::new(
+            ResponseError::UnknownTopicOrPartition,
+            "This server does not host this topic-partition.",
+        ));
+    };
+    let current = existing.partitions.len();
+    if topic.count < 0 || (topic.count as usize) < current {
+        return Err(ApiError::new(
+            ResponseError::InvalidPartitions,
+            format!("The topic {} currently has {} partition(s); {} would not be an increase.", name, current, topic.count),
+        ));
+    }
+    if topic.count as usize == current {
+        return Err(ApiError::new(
+            ResponseError::InvalidPartitions,
+            format!("Topic already has {} partition(s).", current),
+        ));
+    }
+    let additional = topic.count as usize - current;
+    let replication_factor = existing.partitions.first().map_or(1, |p| p.replicas.len());
+    let brokers = usable_brokers();
+
+    let assignments = match &topic.assignments {
+        Some(assignments) => {
+            if assignments.len() != additional {
+                return Err(ApiError::new(
+                    ResponseError::InvalidReplicaAssignment,
+                    format!(
+                        "Attempted to add {} additional partition(s), but only {} assignment(s) were specified.",
+                        additional,
+                        assignments.len()
+                    ),
+                ));
+            }
+            assignments
+                .iter()
+                .zip(current..)
+                .map(|(assignment, partition)| {
+                    let replicas: Vec<i32> = assignment.broker_ids.iter().map(|id| id.0).collect();
+                    validate_replicas(&replicas, &brokers, partition as i32)?;
+                    if replicas.len() != replication_factor {
+                        return Err(ApiError::new(
+                            ResponseError::InvalidReplicaAssignment,
+                            format!(
+                                "The manual partition assignment includes a partition with {} replica(s), but this is not consistent with previous partitions, which have {} replica(s).",
+                                replicas.len(),
+                                replication_factor
+                            ),
+                        ));
+                    }
+                    Ok(replicas)
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        }
+        None => {
+            if replication_factor > brokers.len() {
+                return Err(ApiError::new(
+                    ResponseError::InvalidReplicationFactor,
+                    format!(
+                        "Unable to replicate the partition {} time(s): The target replication factor of {} cannot be reached because only {} broker(s) are registered.",
+                        replication_factor,
+                        replication_factor,
+                        brokers.len()
+                    ),
+                ));
+            }
+            place_replicas(&brokers, current, additional, replication_factor)
+        }
+    };
+
+    Ok(assignments
+        .into_iter()
+        .zip(current..)
+        .map(|(replicas, partition_id)| new_partition(existing.topic.uuid, partition_id as u32, replicas))
+        .collect())
+}
+
+/// A PartitionRecord for a new partition led by its first replica.
+pub fn new_partition(topic_id: Uuid, partition_id: u32, replicas: Vec<i32>) -> PartitionRecord {
+    PartitionRecord {
+        header: Header::new(PartitionRecord::TYPE, 0),
+        partition_id,
+        topic_uuid: topic_id,
+        leader: replicas[0],
+        isr: replicas.clone(),
+        replicas,
+        removing_replicas: Vec::new(),
+        adding_replicas: Vec::new(),
+        leader_recovery_state: 0,
+        leader_epoch: 0,
+        partition_epoch: 0,
+        directories: Vec::new(),
+        eligible_leader_replicas: None,
+        last_known_elr: None,
+    }
+}
+
+/// Returns the replicas of every partition, either the manual assignment from
+/// the request or `num_partitions` partitions of `replication_factor` replicas.
+fn assign_replicas(topic: &CreatableTopic) -> Result<Vec<Vec<i32>>, ApiError> {
+    let brokers = usable_brokers();
+
+    if !topic.assignments.is_empty() {
+        if topic.num_partitions != -1 || topic.replication_factor != -1 {
+            return Err(ApiError::new(
+                ResponseError::InvalidRequest,
+                "A manual partition assignment was specified, but numPartitions or replicationFactor was not set to -1.",
+            ));
+        }
+        let mut assignments = topic.assignments.clone();
+        assignments.sort_by_key(|a| a.partition_index);
+        let mut replication_factor = None;
+        return assignments
+            .iter()
+            .enumerate()
+            .map(|(expected, assignment)| {
+                if assignment.partition_index != expected as i32 {
+                    return Err(ApiError::new(
+                        ResponseError::InvalidReplicaAssignment,
+                        format!("Partitions should be a consecutive 0-based integer sequence, but got partition {}.", assignment.partition_index),
+                    ));
+                }
+                let replicas: Vec<i32> = assignment.broker_ids.iter().map(|id| id.0).collect();
+                validate_replicas(&replicas, &brokers, assignment.partition_index)?;
+                if *replication_factor.get_or_insert(replicas.len()) != replicas.len() {
+                    return Err(ApiError::new(
+                        ResponseError::InvalidReplicaAssignment,
+                        "All partitions should have the same number of replicas.",
+                    ));
+                }
+                Ok(replicas)
+            })
+            .collect();
+    }
+
+    let num_partitions = match topic.num_partitions {
+        -1 => config::get().num_partitions,
+        n => n,
+    };
+    let replication_factor = match topic.replication_factor {
+        -1 => config::get().default_replication_factor,
+        n => n,
+    };
+    if num_partitions <= 0 {
+        return Err(ApiError::new(
+            ResponseError::InvalidPartitions,
+            "Number of partitions was set to an invalid non-positive value.",
+        ));
+    }
+    if replication_factor <= 0 {
+        return Err(ApiError::new(
+            ResponseError::InvalidReplicationFactor,
+            "Replication factor must be larger than 0, or -1 to use the default value.",
+        ));
+    }
+    if replication_factor as usize > brokers.len() {
+        return Err(ApiError::new(
+            ResponseError::InvalidReplicationFactor,
+            format!(
+                "Unable to replicate the partition {} time(s): The target replication factor of {} cannot be reached because only {} broker(s) are registered.",
+                replication_factor,
+                replication_factor,
+                brokers.len()
+            ),
+        ));
+    }
+    Ok(place_replicas(&brokers, 0, num_partitions as usize, replication_factor as usize))
+}
+
+/// Round-robin placement: partition `p` starts on broker `p mod n` and takes the
+/// following brokers, so leadership spreads evenly.
+pub fn place_replicas(brokers: &[i32], first_partition: usize, count: usize, replication_factor: usize) -> Vec<Vec<i32>> {
+    (first_partition..first_partition + count)
+        .map(|partition| {
+            (0..replication_factor)
+                .map(|replica| brokers[(partition + replica) % brokers.len()])
+                .collect()
+        })
+        .collect()
+}
+
+pub fn validate_replicas(replicas: &[i32], brokers: &[i32], partition: i32) -> Result<(), ApiError> {
+    if replicas.is_empty() {
+        return Err(ApiError::new(
+            ResponseError::InvalidReplicaAssignment,
+            format!("The manual partition assignment includes an empty replica list for partition {}.", partition),
+        ));
+    }
+    let mut seen = HashSet::new();
+    for replica in replicas {
+        if !seen.insert(replica) {
+            return Err(ApiError::new(
+                ResponseError::InvalidReplicaAssignment,
+                format!("The manual partition assignment includes the broker {} more than once.", replica),
+            ));
+        }
+        if !brokers.contains(replica) {
+            return Err(ApiError::new(
+                ResponseError::InvalidReplicaAssignment,
+                format!("The manual partition assignment includes broker {}, but no such broker is registered.", replica),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks a topic's configs. Only the configs in [`APPLIED_TOPIC_CONFIGS`] are
+/// accepted; other Kafka topic configs are refused rather than stored without effect.
+fn validate_configs(topic: &CreatableTopic) -> Result<Vec<(String, String)>, ApiError> {
+    topic
+        .configs
+        .iter()
+        .map(|config| {
+            let name = config.name.to_string();
+            let invalid = |message: String| Err(ApiError::new(ResponseError::InvalidConfig, message));
+            let Some(value) = &config.value else {
+                return invalid(format!("Null value not supported for topic configs: {}", name));
+            };
+            let Some((_, config_type, min)) = APPLIED_TOPIC_CONFIGS.iter().find(|(applied, _, _)| *applied == name) else {
+                if TOPIC_CONFIGS.contains(&name.as_str()) {
+                    return invalid(format!("Topic config {} is not supported by this broker", name));
+                }
+                return invalid(format!("Unknown topic config name: {}", name));
+            };
+            let parsed = match config_type {
+                ConfigType::Int => value.trim().parse::<i32>().map(i64::from).ok(),
+                ConfigType::Long => value.trim().parse::<i64>().ok(),
+            };
+            let Some(parsed) = parsed else {
+                let type_name = match config_type {
+                    ConfigType::Int => "INT",
+                    ConfigType::Long => "LONG",
+                };
+                return invalid(format!(
+                    "Invalid value {} for configuration {}: Not a number of type {}",
+                    value, name, type_name
+                ));
+            };
+            if parsed < *min {
+                return invalid(format!("Invalid value {} for configuration {}: Value must be at least {}", value, name, min));
+            }
+            Ok((name, value.trim().to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_protocol::messages::create_partitions_request::CreatePartitionsAssignment;
+    use kafka_protocol::messages::create_topics_request::{CreatableReplicaAssignment, CreatableTopicConfig};
+    use kafka_protocol::messages::BrokerId;
+    use kafka_protocol::protocol::StrBytes;
+    use super::*;
+
+    fn topic(name: &str, num_partitions: i32, replication_factor: i16) -> CreatableTopic {
+        CreatableTopic::default()
+            .with_name(TopicName(StrBytes::from(name.to_string())))
+            .with_num_partitions(num_partitions)
+            .with_replication_factor(replication_factor)
+    }
+
+    fn with_config(topic: CreatableTopic, name: &str, value: &str) -> CreatableTopic {
+        topic.with_configs(vec![CreatableTopicConfig::default()
+            .with_name(StrBytes::from(name.to_string()))
+            .with_value(Some(StrBytes::from(value.to_string())))])
+    }
+
+    /// An image holding the given topics, each with one partition.
+    fn image_with(names: &[&str]) -> MetadataImage {
+        let records = names
+            .iter()
+            .flat_map(|name| create_internal_topic(&MetadataImage::default(), name, 1, Vec::new()).records);
+        MetadataImage::from_records(records.collect())
+    }
+
+    fn error<T: std::fmt::Debug>(result: Result<T, ApiError>) -> ResponseError {
+        result.unwrap_err().error
+    }
+
+    #[test]
+    fn invalid_and_reserved_names_are_refused() {
+        let image = MetadataImage::default();
+        let long_name = "a".repeat(MAX_TOPIC_NAME_LENGTH + 1);
+        for name in ["", ".", "..", "a/b", "ä", long_name.as_str()] {
+            assert_eq!(error(create_topic(&image, &topic(name, 1, 1), &[])), ResponseError::InvalidTopicException, "{:?}", name);
+        }
+        for name in [CONSUMER_OFFSETS_TOPIC, "__transaction_state", METADATA_TOPIC] {
+            assert_eq!(error(create_topic(&image, &topic(name, 1, 1), &[])), ResponseError::InvalidRequest, "{:?}", name);
+        }
+        let max_length = "a".repeat(MAX_TOPIC_NAME_LENGTH);
+        assert!(create_topic(&image, &topic(&max_length, 1, 1), &[]).is_ok());
+    }
+
+    #[test]
+    fn existing_colliding_and_duplicate_names_are_refused() {
+        let image = image_with(&["foo.bar"]);
+        assert_eq!(error(create_topic(&image, &topic("foo.bar", 1, 1), &[])), ResponseError::TopicAlreadyExists);
+        assert_eq!(error(create_topic(&image, &topic("foo_bar", 1, 1), &[])), ResponseError::InvalidTopicException);
+
+        let topics = [topic("dup", 1, 1), topic("a.b", 1, 1), topic("dup", 1, 1), topic("a_b", 1, 1)];
+        let (records, outcomes) = create_topics(&image, &topics, false);
+        let errors: Vec<Option<ResponseError>> = outcomes.into_iter().map(|o| o.err().map(|e| e.error)).collect();
+        assert_eq!(
+            errors,
+            vec![
+                Some(ResponseError::InvalidRequest),
+                None,
+                Some(ResponseError::InvalidRequest),
+                Some(ResponseError::InvalidTopicException),
+            ]
+        );
+        let created = MetadataImage::from_records(records);
+        assert_eq!(created.topics().map(|tp| tp.topic.name.as_str()).collect::<Vec<_>>(), vec!["a.b"]);
+    }
+
+    #[test]
+    fn invalid_partition_counts_and_replication_factors_are_refused() {
+        let image = MetadataImage::default();
+        assert_eq!(error(create_topic(&image, &topic("foo", 0, 1), &[])), ResponseError::InvalidPartitions);
+        assert_eq!(error(create_topic(&image, &topic("foo", -2, 1), &[])), ResponseError::InvalidPartitions);
+        assert_eq!(error(create_topic(&image, &topic("foo", 1, 0), &[])), ResponseError::InvalidReplicationFactor);
+        // This broker is the only one, so a second replica cannot be placed.
+        assert_eq!(error(create_topic(&image, &topic("foo", 1, 2), &[])), ResponseError::InvalidReplicationFactor);
+
+        let assignment = |partition: i32| {
+            CreatableReplicaAssignment::default().with_partition_index(partition).with_broker_ids(vec![BrokerId(1)])
+        };
+        let manual = topic("foo", 1, -1).with_assignments(vec![assignment(0)]);
+        assert_eq!(error(create_topic(&image, &manual, &[])), ResponseError::InvalidRequest);
+        let gap = topic("foo", -1, -1).with_assignments(vec![assignment(0), assignment(2)]);
+        assert_eq!(error(create_topic(&image, &gap, &[])), ResponseError::InvalidReplicaAssignment);
+
+        let defaults = create_topic(&image, &topic("foo", -1, -1), &[]).unwrap();
+        assert_eq!((defaults.num_partitions, defaults.replication_factor), (config::get().num_partitions, 1));
+    }
+
+    #[test]
+    fn unknown_unapplied_and_invalid_configs_are_refused() {
+        let image = MetadataImage::default();
+        for (name, value) in [("nope", "1"), ("retention.ms", "1000"), ("segment.bytes", "abc"), ("segment.bytes", "13"), ("segment.ms", "0")] {
+            let refused = create_topic(&image, &with_config(topic("foo", 1, 1), name, value), &[]);
+            assert_eq!(error(refused), ResponseError::InvalidConfig, "{}={}", name, value);
+        }
+
+        let created = create_topic(&image, &with_config(topic("foo", 1, 1), "segment.bytes", "1024"), &[]).unwrap();
+        let image = MetadataImage::from_records(created.records);
+        assert_eq!(image.topic_configs("foo").unwrap()["segment.bytes"], "1024");
+    }
+
+    #[test]
+    fn validate_only_plans_topics_without_records() {
+        let image = MetadataImage::default();
+        let (records, outcomes) = create_topics(&image, &[topic("foo", 3, 1), topic("bar", 0, 1)], true);
+        assert!(records.is_empty());
+        assert_eq!(outcomes[0].as_ref().unwrap().num_partitions, 3);
+        assert_eq!(error(outcomes.into_iter().nth(1).unwrap()), ResponseError::InvalidPartitions);
+
+        let (records, outcomes) = create_topics(&image, &[topic("foo", 3, 1)], false);
+        let created = outcomes.into_iter().next().unwrap().unwrap();
+        let image = MetadataImage::from_records(records);
+        assert_eq!(image.topic_by_id(created.topic_id).unwrap().partitions.len(), 3);
+    }
+
+    fn name(name: &str) -> Option<TopicName> {
+        Some(TopicName(StrBytes::from(name.to_string())))
+    }
+
+    #[test]
+    fn topics_are_deleted_by_name_or_by_id() {
+        let image = image_with(&["foo", "bar", "baz"]);
+        let bar_id = image.topic_by_name("bar").unwrap().topic.uuid;
+        let (records, outcomes) = delete_topics(&image, &[(name("foo"), Uuid::nil()), (None, bar_id)]);
+        let deleted: Vec<(String, Uuid, Vec<u32>)> = outcomes
+            .into_iter()
+            .map(|outcome| outcome.map(|d| (d.name, d.topic_id, d.partitions)).unwrap())
+            .collect();
+        let foo_id = image.topic_by_name("foo").unwrap().topic.uuid;
+        assert_eq!(deleted, vec![("foo".to_string(), foo_id, vec![0]), ("bar".to_string(), bar_id, vec![0])]);
+
+        let remaining = image.with_records(records);
+        assert!(remaining.topic_by_name("foo").is_none());
+        assert!(remaining.topic_by_id(bar_id).is_none());
+        assert_eq!(remaining.topics().map(|tp| tp.topic.name.as_str()).collect::<Vec<_>>(), vec!["baz"]);
+    }
+
+    #[test]
+    fn unknown_ambiguous_and_internal_topics_are_not_deleted() {
+        let offsets = create_internal_topic(&MetadataImage::default(), CONSUMER_OFFSETS_TOPIC, 1, Vec::new());
+        let foo = create_internal_topic(&MetadataImage::default(), "foo", 1, Vec::new());
+        let foo_id = foo.topic_id;
+        let image = MetadataImage::from_records(offsets.records.into_iter().chain(foo.records).collect());
+
+        let requested = [
+            (None, Uuid::new_v4()),
+            (name("missing"), Uuid::nil()),
+            (name("foo"), foo_id),
+            (None, Uuid::nil()),
+            (name(CONSUMER_OFFSETS_TOPIC), Uuid::nil()),
+            (None, offsets.topic_id),
+        ];
+        let (records, outcomes) = delete_topics(&image, &requested);
+        assert!(records.is_empty());
+        let errors: Vec<ResponseError> = outcomes.into_iter().map(error).collect();
+        assert_eq!(
+            errors,
+            vec![
+                ResponseError::UnknownTopicId,
+                ResponseError::UnknownTopicOrPartition,
+                ResponseError::InvalidRequest,
+                ResponseError::InvalidRequest,
+                ResponseError::InvalidRequest,
+                ResponseError::InvalidRequest,
+            ]
+        );
+
+        // Naming one topic twice, by name and by id, deletes it once.
+        let (records, outcomes) = delete_topics(&image, &[(name("foo"), Uuid::nil()), (None, foo_id)]);
+        assert_eq!(records.len(), 1);
+        assert!(outcomes[0].is_ok());
+        assert_eq!(error(outcomes.into_iter().nth(1).unwrap()), ResponseError::InvalidRequest);
+    }
+
+    fn grow(name: &str, count: i32) -> CreatePartitionsTopic {
+        CreatePartitionsTopic::default()
+            .with_name(TopicName(StrBytes::from(name.to_string())))
+            .with_count(count)
+            .with_assignments(None)
+    }
+
+    #[test]
+    fn partitions_are_only_added_to_known_topics() {
+        let created = create_internal_topic(&MetadataImage::default(), "foo", 2, Vec::new());
+        let image = MetadataImage::from_records(created.records);
+        assert_eq!(error(create_partitions(&image, &grow("foo", 1))), ResponseError::InvalidPartitions);
+        assert_eq!(error(create_partitions(&image, &grow("foo", 2))), ResponseError::InvalidPartitions);
+        assert_eq!(error(create_partitions(&image, &grow("foo", -1))), ResponseError::InvalidPartitions);
+        assert_eq!(error(create_partitions(&image, &grow("missing", 3))), ResponseError::UnknownTopicOrPartition);
+
+        let assignment = |brokers: Vec<i32>| {
+            CreatePartitionsAssignment::default().with_broker_ids(brokers.into_iter().map(BrokerId).collect())
+        };
+        let too_few = grow("foo", 4).with_assignments(Some(vec![assignment(vec![1])]));
+        assert_eq!(error(create_partitions(&image, &too_few)), ResponseError::InvalidReplicaAssignment);
+        let unknown_broker = grow("foo", 3).with_assignments(Some(vec![assignment(vec![7])]));
+        assert_eq!(error(create_partitions(&image, &unknown_broker)), ResponseError::InvalidReplicaAssignment);
+    }
+
+    #[test]
+    fn new_partitions_follow_the_existing_ones() {
+        let created = create_internal_topic(&MetadataImage::default(), "foo", 2, Vec::new());
+        let image = MetadataImage::from_records(created.records);
+
+        let partitions = create_partitions(&image, &grow("foo", 5)).unwrap();
+        let placed: Vec<(u32, Vec<i32>, i32)> = partitions
+            .iter()
+            .map(|p| (p.partition_id, p.replicas.clone(), p.leader))
+            .collect();
+        let node_id = config::get().node_id;
+        assert_eq!(placed, (2..5).map(|id| (id, vec![node_id], node_id)).collect::<Vec<_>>());
+        assert!(partitions.iter().all(|p| p.topic_uuid == created.topic_id));
+
+        let grown = image.with_records(partitions.into_iter().map(RecordType::PartitionValue).collect());
+        assert_eq!(grown.topic_by_name("foo").unwrap().partitions.len(), 5);
+
+        let manual = grow("foo", 3).with_assignments(Some(vec![
+            CreatePartitionsAssignment::default().with_broker_ids(vec![BrokerId(node_id)]),
+        ]));
+        let partitions = create_partitions(&image, &manual).unwrap();
+        assert_eq!(partitions.iter().map(|p| p.partition_id).collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[test]
+    fn replicas_are_placed_round_robin() {
+        assert_eq!(place_replicas(&[1, 2, 3], 0, 3, 2), vec![vec![1, 2], vec![2, 3], vec![3, 1]]);
+        assert_eq!(place_replicas(&[1, 2, 3], 4, 2, 3), vec![vec![2, 3, 1], vec![3, 1, 2]]);
+    }
+}
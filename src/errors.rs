@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+use bytes::BytesMut;
+use kafka_protocol::messages::{ApiKey, RequestHeader, ResponseHeader, ResponseKind};
+use kafka_protocol::protocol::Encodable;
+
+/// Expands to a default response for every listed API key, plus a setter that
+/// fills in the top-level `error_code` for the responses that carry one.
+macro_rules! error_responses {
+    (
+        with_error_code: [$($with:ident),* $(,)?],
+        without_error_code: [$($without:ident),* $(,)?] $(,)?
+    ) => {
+        fn default_response(api_key: ApiKey) -> Option<ResponseKind> {
+            match api_key {
+                $(ApiKey::$with => Some(ResponseKind::$with(Default::default())),)*
+                $(ApiKey::$without => Some(ResponseKind::$without(Default::default())),)*
+                #[allow(unreachable_patterns)]
+                _ => None,
+            }
+        }
+
+        fn set_error_code(response: &mut ResponseKind, error_code: i16) {
+            match response {
+                $(ResponseKind::$with(r) => r.error_code = error_code,)*
+                _ => {}
+            }
+        }
+    };
+}
+
+error_responses! {
+    with_error_code: [
+        AddOffsetsToTxn, AddPartitionsToTxn, AddRaftVoter, AllocateProducerIds,
+        AlterPartitionReassignments, AlterPartition, AlterShareGroupOffsets, ApiVersions,
+        AssignReplicasToDirs, BeginQuorumEpoch, BrokerHeartbeat, BrokerRegistration,
+        ConsumerGroupHeartbeat, ControllerRegistration, CreateDelegationToken,
+        DeleteShareGroupOffsets, DescribeAcls, DescribeClientQuotas, DescribeCluster,
+        DescribeDelegationToken, DescribeLogDirs, DescribeQuorum, DescribeUserScramCredentials,
+        ElectLeaders, EndQuorumEpoch, EndTxn, Envelope, ExpireDelegationToken, Fetch,
+        FetchSnapshot, FindCoordinator, GetTelemetrySubscriptions, Heartbeat, InitProducerId,
+        JoinGroup, LeaveGroup, ListConfigResources, ListGroups, ListPartitionReassignments,
+        ListTransactions, Metadata, OffsetDelete, OffsetFetch, PushTelemetry, RemoveRaftVoter,
+        RenewDelegationToken, SaslAuthenticate, SaslHandshake, ShareAcknowledge, ShareFetch,
+        ShareGroupHeartbeat, SyncGroup, UnregisterBroker, UpdateFeatures, UpdateRaftVoter, Vote,
+    ],
+    without_error_code: [
+        AlterClientQuotas, AlterConfigs, AlterReplicaLogDirs, AlterUserScramCredentials,
+        ConsumerGroupDescribe, CreateAcls, CreatePartitions, CreateTopics, DeleteAcls,
+        DeleteGroups, DeleteRecords, DeleteShareGroupState, DeleteTopics, DescribeConfigs,
+        DescribeGroups, DescribeProducers, DescribeShareGroupOffsets, DescribeTopicPartitions,
+        DescribeTransactions, IncrementalAlterConfigs, InitializeShareGroupState, ListOffsets,
+        OffsetCommit, OffsetForLeaderEpoch, Produce, ReadShareGroupState,
+        ReadShareGroupStateSummary, ShareGroupDescribe, TxnOffsetCommit, WriteShareGroupState,
+        WriteTxnMarkers,
+    ],
+}
+
+/// Builds a response of the type matching `api_key` that carries `error_code`.
+///
+/// Responses without a top-level error code (or versions that predate it) are
+/// sent empty. Returns `None` when the requested version cannot be encoded at
+/// all, in which case the caller should drop the connection like Kafka does.
+pub fn error_response(api_key: ApiKey, header: &RequestHeader, error_code: i16) -> Option<BytesMut> {
+    let version = header.request_api_version;
+    let mut response = default_response(api_key)?;
+
+    let mut response_buf = BytesMut::new();
+    ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(&mut response_buf, api_key.response_header_version(version))
+        .ok()?;
+    let body_start = response_buf.len();
+
+    set_error_code(&mut response, error_code);
+    if response.encode(&mut response_buf, version).is_ok() {
+        return Some(response_buf);
+    }
+
+    response_buf.truncate(body_start);
+    default_response(api_key)?.encode(&mut response_buf, version).ok()?;
+    Some(response_buf)
+}
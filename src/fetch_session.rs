@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Instant;
+use indexmap::IndexMap;
+use kafka_protocol::messages::fetch_request::{FetchPartition, FetchTopic};
+use kafka_protocol::messages::fetch_response::{FetchableTopicResponse, PartitionData};
+use kafka_protocol::messages::{FetchRequest, TopicName};
+use kafka_protocol::ResponseError;
+use uuid::Uuid;
+use crate::config;
+
+const INITIAL_EPOCH: i32 = 0;
+const FINAL_EPOCH: i32 = -1;
+
+static FETCH_SESSIONS: LazyLock<Mutex<FetchSessionCache>> = LazyLock::new(|| {
+    Mutex::new(FetchSessionCache::new(
+        config::get().max_incremental_fetch_session_cache_slots,
+    ))
+});
+
+/// A partition is identified by topic id (v13+) or topic name (older versions).
+type PartitionKey = (Uuid, TopicName, i32);
+
+#[derive(Debug, Clone)]
+struct CachedPartition {
+    fetch_offset: i64,
+    partition_max_bytes: i32,
+    high_watermark: i64,
+    log_start_offset: i64,
+}
+
+#[derive(Debug)]
+struct FetchSession {
+    /// The epoch the next incremental request must carry.
+    next_epoch: i32,
+    partitions: IndexMap<PartitionKey, CachedPartition>,
+    last_used: Instant,
+}
+
+struct FetchSessionCache {
+    max_slots: usize,
+    sessions: HashMap<i32, FetchSession>,
+}
+
+impl FetchSessionCache {
+    fn new(max_slots: usize) -> Self {
+        FetchSessionCache {
+            max_slots,
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// Creates a session, evicting the least recently used one when the cache is full.
+    /// Returns `None` when sessions are disabled.
+    fn create(&mut self, partitions: IndexMap<PartitionKey, CachedPartition>) -> Option<i32> {
+        if self.max_slots == 0 {
+            return None;
+        }
+        if self.sessions.len() >= self.max_slots {
+            let evicted = self
+                .sessions
+                .iter()
+                .min_by_key(|(_, session)| session.last_used)
+                .map(|(id, _)| *id);
+            if let Some(id) = evicted {
+                println!("Evicting fetch session {}", id);
+                self.sessions.remove(&id);
+            }
+        }
+        let session_id = loop {
+            let candidate = (Uuid::new_v4().as_u128() as i32) & i32::MAX;
+            if candidate != 0 && !self.sessions.contains_key(&candidate) {
+                break candidate;
+            }
+        };
+        self.sessions.insert(
+            session_id,
+            FetchSession {
+                next_epoch: INITIAL_EPOCH + 1,
+                partitions,
+                last_used: Instant::now(),
+            },
+        );
+        Some(session_id)
+    }
+}
+
+/// How a Fetch request relates to the session cache, decided before reading logs.
+#[derive(Debug, Clone, Copy)]
+pub enum FetchContext {
+    /// A full fetch without a session (session epoch -1).
+    Sessionless,
+    /// A full fetch that opened a new session.
+    Full { session_id: i32 },
+    /// An incremental fetch against an existing session.
+    Incremental { session_id: i32 },
+}
+
+impl FetchContext {
+    pub fn session_id(&self) -> i32 {
+        match self {
+            FetchContext::Sessionless => 0,
+            FetchContext::Full { session_id } | FetchContext::Incremental { session_id } => *session_id,
+        }
+    }
+}
+
+fn requested_partitions(topics: &[FetchTopic]) -> IndexMap<PartitionKey, CachedPartition> {
+    topics
+        .iter()
+        .flat_map(|topic| {
+            topic.partitions.iter().map(|p| {
+                (
+                    (topic.topic_id, topic.topic.clone(), p.partition),
+                    CachedPartition {
+                        fetch_offset: p.fetch_offset,
+                        partition_max_bytes: p.partition_max_bytes,
+                        high_watermark: -1,
+                        log_start_offset: -1,
+                    },
+                )
+            })
+        })
+        .collect()
+}
+
+fn to_fetch_topics(partitions: &IndexMap<PartitionKey, CachedPartition>) -> Vec<FetchTopic> {
+    let mut topics: Vec<FetchTopic> = Vec::new();
+    for ((topic_id, topic_name, partition), cached) in partitions {
+        let fetch_partition = FetchPartition::default()
+            .with_partition(*partition)
+            .with_fetch_offset(cached.fetch_offset)
+            .with_partition_max_bytes(cached.partition_max_bytes);
+        match topics
+            .iter_mut()
+            .find(|t| t.topic_id == *topic_id && t.topic == *topic_name)
+        {
+            Some(topic) => topic.partitions.push(fetch_partition),
+            None => topics.push(
+                FetchTopic::default()
+                    .with_topic_id(*topic_id)
+                    .with_topic(topic_name.clone())
+                    .with_partitions(vec![fetch_partition]),
+            ),
+        }
+    }
+    topics
+}
+
+/// Resolves the session a request belongs to and the partitions it should read.
+/// Incremental requests only list changed partitions, so the returned topics are
+/// the session's full partition set after applying additions and removals.
+pub fn new_context(req: &FetchRequest) -> Result<(FetchContext, Vec<FetchTopic>), ResponseError> {
+    let mut cache = FETCH_SESSIONS.lock().unwrap();
+
+    if req.session_epoch == FINAL_EPOCH {
+        if req.session_id != 0 {
+            cache.sessions.remove(&req.session_id);
+        }
+        return Ok((FetchContext::Sessionless, req.topics.clone()));
+    }
+
+    if req.session_epoch == INITIAL_EPOCH {
+        if req.session_id != 0 {
+            cache.sessions.remove(&req.session_id);
+        }
+        let context = match cache.create(requested_partitions(&req.topics)) {
+            Some(session_id) => FetchContext::Full { session_id },
+            None => FetchContext::Sessionless,
+        };
+        return Ok((context, req.topics.clone()));
+    }
+
+    if req.session_id == 0 {
+        return Err(ResponseError::InvalidFetchSessionEpoch);
+    }
+    let Some(session) = cache.sessions.get_mut(&req.session_id) else {
+        return Err(ResponseError::FetchSessionIdNotFound);
+    };
+    if session.next_epoch != req.session_epoch {
+        return Err(ResponseError::InvalidFetchSessionEpoch);
+    }
+
+    for (key, requested) in requested_partitions(&req.topics) {
+        match session.partitions.get_mut(&key) {
+            Some(cached) => {
+                cached.fetch_offset = requested.fetch_offset;
+                cached.partition_max_bytes = requested.partition_max_bytes;
+            }
+            None => {
+                session.partitions.insert(key, requested);
+            }
+        }
+    }
+    for forgotten in &req.forgotten_topics_data {
+        for partition in &forgotten.partitions {
+            session
+                .partitions
+                .shift_remove(&(forgotten.topic_id, forgotten.topic.clone(), *partition));
+        }
+    }
+
+    session.next_epoch = if session.next_epoch == i32::MAX { 1 } else { session.next_epoch + 1 };
+    session.last_used = Instant::now();
+    Ok((
+        FetchContext::Incremental { session_id: req.session_id },
+        to_fetch_topics(&session.partitions),
+    ))
+}
+
+/// Records what each partition returned and, for incremental fetches, drops the
+/// partitions with nothing new: no records, no error and unchanged offsets.
+pub fn update_and_filter(context: FetchContext, topics: Vec<FetchableTopicResponse>) -> Vec<FetchableTopicResponse> {
+    let session_id = match context {
+        FetchContext::Sessionless => return topics,
+        FetchContext::Full { session_id } | FetchContext::Incremental { session_id } => session_id,
+    };
+    let incremental = matches!(context, FetchContext::Incremental { .. });
+
+    let mut cache = FETCH_SESSIONS.lock().unwrap();
+    let Some(session) = cache.sessions.get_mut(&session_id) else {
+        return topics;
+    };
+
+    topics
+        .into_iter()
+        .filter_map(|mut topic| {
+            let topic_key = (topic.topic_id, topic.topic.clone());
+            topic.partitions.retain(|partition: &PartitionData| {
+                let key = (topic_key.0, topic_key.1.clone(), partition.partition_index);
+                let Some(cached) = session.partitions.get_mut(&key) else {
+                    return true;
+                };
+                let changed = partition.error_code != 0
+                    || partition.records.as_ref().is_some_and(|r| !r.is_empty())
+                    || partition.high_watermark != cached.high_watermark
+                    || partition.log_start_offset != cached.log_start_offset;
+                cached.high_watermark = partition.high_watermark;
+                cached.log_start_offset = partition.log_start_offset;
+                !incremental || changed
+            });
+            if incremental && topic.partitions.is_empty() {
+                None
+            } else {
+                Some(topic)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use kafka_protocol::messages::fetch_request::ForgottenTopic;
+    use super::*;
+
+    fn request(session_id: i32, session_epoch: i32, topic_id: Uuid, partitions: &[i32]) -> FetchRequest {
+        let partitions = partitions
+            .iter()
+            .map(|p| FetchPartition::default().with_partition(*p).with_fetch_offset(10 * *p as i64))
+            .collect();
+        FetchRequest::default()
+            .with_session_id(session_id)
+            .with_session_epoch(session_epoch)
+            .with_topics(vec![FetchTopic::default().with_topic_id(topic_id).with_partitions(partitions)])
+    }
+
+    fn partitions(topics: &[FetchTopic]) -> Vec<(i32, i64)> {
+        topics.iter().flat_map(|t| t.partitions.iter().map(|p| (p.partition, p.fetch_offset))).collect()
+    }
+
+    fn response(topic_id: Uuid, partitions: &[(i32, i64)]) -> Vec<FetchableTopicResponse> {
+        let partitions = partitions
+            .iter()
+            .map(|(p, high_watermark)| PartitionData::default().with_partition_index(*p).with_high_watermark(*high_watermark))
+            .collect();
+        vec![FetchableTopicResponse::default().with_topic_id(topic_id).with_partitions(partitions)]
+    }
+
+    #[test]
+    fn incremental_fetches_must_carry_the_next_epoch() {
+        let topic_id = Uuid::new_v4();
+        let (context, _) = new_context(&request(0, INITIAL_EPOCH, topic_id, &[0])).unwrap();
+        let FetchContext::Full { session_id } = context else {
+            panic!("no session was created");
+        };
+
+        assert!(matches!(new_context(&request(session_id, 1, topic_id, &[])), Ok((FetchContext::Incremental { .. }, _))));
+        assert_eq!(new_context(&request(session_id, 1, topic_id, &[])).unwrap_err(), ResponseError::InvalidFetchSessionEpoch);
+        assert_eq!(new_context(&request(session_id, 3, topic_id, &[])).unwrap_err(), ResponseError::InvalidFetchSessionEpoch);
+        assert!(new_context(&request(session_id, 2, topic_id, &[])).is_ok());
+        assert_eq!(new_context(&request(0, 3, topic_id, &[])).unwrap_err(), ResponseError::InvalidFetchSessionEpoch);
+        assert_eq!(new_context(&request(session_id ^ 1, 3, topic_id, &[])).unwrap_err(), ResponseError::FetchSessionIdNotFound);
+
+        // The final epoch closes the session and fetches without one.
+        assert!(matches!(new_context(&request(session_id, FINAL_EPOCH, topic_id, &[0])), Ok((FetchContext::Sessionless, _))));
+        assert_eq!(new_context(&request(session_id, 3, topic_id, &[])).unwrap_err(), ResponseError::FetchSessionIdNotFound);
+    }
+
+    #[test]
+    fn a_new_full_fetch_replaces_the_session() {
+        let topic_id = Uuid::new_v4();
+        let (first, _) = new_context(&request(0, INITIAL_EPOCH, topic_id, &[0])).unwrap();
+        let (second, _) = new_context(&request(first.session_id(), INITIAL_EPOCH, topic_id, &[0])).unwrap();
+        assert_ne!(first.session_id(), second.session_id());
+        assert_eq!(new_context(&request(first.session_id(), 1, topic_id, &[])).unwrap_err(), ResponseError::FetchSessionIdNotFound);
+        assert!(new_context(&request(second.session_id(), 1, topic_id, &[])).is_ok());
+    }
+
+    #[test]
+    fn incremental_fetches_update_the_session_partitions() {
+        let topic_id = Uuid::new_v4();
+        let (context, topics) = new_context(&request(0, INITIAL_EPOCH, topic_id, &[0, 1])).unwrap();
+        assert_eq!(partitions(&topics), vec![(0, 0), (1, 10)]);
+        let session_id = context.session_id();
+
+        let mut req = request(session_id, 1, topic_id, &[2]);
+        req.topics[0].partitions[0].fetch_offset = 25;
+        req.forgotten_topics_data = vec![ForgottenTopic::default().with_topic_id(topic_id).with_partitions(vec![0])];
+        let (_, topics) = new_context(&req).unwrap();
+        assert_eq!(partitions(&topics), vec![(1, 10), (2, 25)]);
+    }
+
+    #[test]
+    fn incremental_responses_only_carry_changed_partitions() {
+        let topic_id = Uuid::new_v4();
+        let (full, _) = new_context(&request(0, INITIAL_EPOCH, topic_id, &[0, 1])).unwrap();
+        let topics = update_and_filter(full, response(topic_id, &[(0, 5), (1, 7)]));
+        assert_eq!(topics[0].partitions.len(), 2);
+
+        let (incremental, _) = new_context(&request(full.session_id(), 1, topic_id, &[])).unwrap();
+        let topics = update_and_filter(incremental, response(topic_id, &[(0, 5), (1, 8)]));
+        let changed: Vec<i32> = topics[0].partitions.iter().map(|p| p.partition_index).collect();
+        assert_eq!(changed, vec![1]);
+
+        let (incremental, _) = new_context(&request(full.session_id(), 2, topic_id, &[])).unwrap();
+        assert!(update_and_filter(incremental, response(topic_id, &[(0, 5), (1, 8)])).is_empty());
+    }
+
+    #[test]
+    fn a_full_cache_evicts_the_least_recently_used_session() {
+        let mut cache = FetchSessionCache::new(2);
+        let first = cache.create(IndexMap::new()).unwrap();
+        let second = cache.create(IndexMap::new()).unwrap();
+        cache.sessions.get_mut(&first).unwrap().last_used += Duration::from_secs(1);
+
+        let third = cache.create(IndexMap::new()).unwrap();
+        assert!(cache.sessions.contains_key(&first));
+        assert!(!cache.sessions.contains_key(&second));
+        assert!(cache.sessions.contains_key(&third));
+
+        assert_eq!(FetchSessionCache::new(0).create(IndexMap::new()), None);
+    }
+}
@@ -0,0 +1,165 @@
+use std::io;
+use std::io::Read;
+use anyhow::bail;
+use bytes::{Buf, Bytes, BytesMut};
+
+const SIZE_PREFIX_LEN: usize = 4;
+const READ_CHUNK: usize = 8 * 1024;
+
+/// Splits a byte stream into size-prefixed Kafka request frames.
+///
+/// Bytes read past the end of a frame are kept for the next call, so pipelined
+/// requests and requests split across TCP segments are both handled.
+pub struct FrameReader<R> {
+    inner: R,
+    buf: BytesMut,
+    max_request_bytes: usize,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(inner: R, max_request_bytes: usize) -> Self {
+        FrameReader {
+            inner,
+            buf: BytesMut::with_capacity(READ_CHUNK),
+            max_request_bytes,
+        }
+    }
+
+    /// Returns the next frame without its size prefix, or `None` when the peer
+    /// closed the connection between requests.
+    pub fn read_frame(&mut self) -> anyhow::Result<Option<Bytes>> {
+        if !self.fill_to(SIZE_PREFIX_LEN)? {
+            return Ok(None);
+        }
+
+        let size = i32::from_be_bytes(self.buf[..SIZE_PREFIX_LEN].try_into()?);
+        if size < 0 {
+            bail!("Invalid negative request size {}", size);
+        }
+        let size = size as usize;
+        if size > self.max_request_bytes {
+            bail!(
+                "Request size {} exceeds socket.request.max.bytes {}",
+                size,
+                self.max_request_bytes
+            );
+        }
+
+        if !self.fill_to(SIZE_PREFIX_LEN + size)? {
+            bail!("Connection closed in the middle of a request");
+        }
+
+        self.buf.advance(SIZE_PREFIX_LEN);
+        Ok(Some(self.buf.split_to(size).freeze()))
+    }
+
+    /// Reads until at least `len` bytes are buffered. Returns `false` on a clean
+    /// EOF with nothing buffered.
+    fn fill_to(&mut self, len: usize) -> anyhow::Result<bool> {
+        while self.buf.len() < len {
+            let start = self.buf.len();
+            let want = (len - start).max(READ_CHUNK);
+            self.buf.resize(start + want, 0);
+            let read = match self.inner.read(&mut self.buf[start..]) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                    self.buf.truncate(start);
+                    continue;
+                }
+                Err(e) => {
+                    self.buf.truncate(start);
+                    return Err(e.into());
+                }
+            };
+            self.buf.truncate(start + read);
+            if read == 0 {
+                if self.buf.is_empty() {
+                    return Ok(false);
+                }
+                bail!("Connection closed with {} unprocessed bytes", self.buf.len());
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use super::*;
+
+    /// A stream whose reads return the given chunks one at a time, as TCP
+    /// segments would arrive.
+    struct Chunks(VecDeque<Vec<u8>>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some(chunk) = self.0.front_mut() else {
+                return Ok(0);
+            };
+            let n = chunk.len().min(buf.len());
+            buf[..n].copy_from_slice(&chunk[..n]);
+            chunk.drain(..n);
+            if chunk.is_empty() {
+                self.0.pop_front();
+            }
+            Ok(n)
+        }
+    }
+
+    fn frame(body: &[u8]) -> Vec<u8> {
+        let mut frame = (body.len() as i32).to_be_bytes().to_vec();
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    fn reader(chunks: Vec<Vec<u8>>, max_request_bytes: usize) -> FrameReader<Chunks> {
+        FrameReader::new(Chunks(chunks.into()), max_request_bytes)
+    }
+
+    #[test]
+    fn a_frame_split_across_reads_is_reassembled() {
+        let data = frame(b"hello world");
+        let chunks = vec![data[..6].to_vec(), data[6..9].to_vec(), data[9..].to_vec()];
+        let mut frames = reader(chunks, 100);
+        assert_eq!(frames.read_frame().unwrap().unwrap(), Bytes::from_static(b"hello world"));
+        assert_eq!(frames.read_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn pipelined_frames_in_one_read_are_returned_in_order() {
+        let mut data = frame(b"first");
+        data.extend(frame(b"second"));
+        data.extend(frame(b""));
+        let mut frames = reader(vec![data], 100);
+        assert_eq!(frames.read_frame().unwrap().unwrap(), Bytes::from_static(b"first"));
+        assert_eq!(frames.read_frame().unwrap().unwrap(), Bytes::from_static(b"second"));
+        assert_eq!(frames.read_frame().unwrap().unwrap(), Bytes::new());
+        assert_eq!(frames.read_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn a_size_prefix_split_across_reads_is_reassembled() {
+        let mut data = frame(b"first");
+        data.extend(frame(b"second"));
+        // The second prefix ends up split between the first and the last read.
+        let split = SIZE_PREFIX_LEN + 5 + 2;
+        let chunks = vec![data[..1].to_vec(), data[1..split].to_vec(), data[split..].to_vec()];
+        let mut frames = reader(chunks, 100);
+        assert_eq!(frames.read_frame().unwrap().unwrap(), Bytes::from_static(b"first"));
+        assert_eq!(frames.read_frame().unwrap().unwrap(), Bytes::from_static(b"second"));
+        assert_eq!(frames.read_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn oversized_negative_and_truncated_frames_are_rejected() {
+        assert!(reader(vec![frame(&[0; 10])], 10).read_frame().unwrap().is_some());
+        let error = reader(vec![frame(&[0; 11])], 10).read_frame().unwrap_err();
+        assert!(error.to_string().contains("exceeds socket.request.max.bytes"), "{}", error);
+        assert!(reader(vec![(-1i32).to_be_bytes().to_vec()], 10).read_frame().is_err());
+
+        let data = frame(b"hello");
+        assert!(reader(vec![data[..7].to_vec()], 10).read_frame().is_err());
+        assert!(reader(vec![data[..2].to_vec()], 10).read_frame().is_err());
+    }
+}
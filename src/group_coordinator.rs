@@ -0,0 +1,1260 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use bytes::{Buf, Bytes};
+use indexmap::IndexMap;
+use kafka_protocol::messages::leave_group_request::MemberIdentity;
+use kafka_protocol::messages::{ConsumerProtocolSubscription, HeartbeatRequest, JoinGroupRequest, SyncGroupRequest};
+use kafka_protocol::protocol::{Decodable, Message};
+use kafka_protocol::ResponseError;
+use uuid::Uuid;
+use crate::{config, consumer_group, consumer_offsets};
+use crate::purgatory::GROUP_PURGATORY;
+
+/// JoinGroup v4+ makes new dynamic members rejoin with a member id issued by the coordinator.
+const MEMBER_ID_REQUIRED_VERSION: i16 = 4;
+
+static GROUPS: LazyLock<Mutex<HashMap<String, Group>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// The classic rebalance protocol's group states, as in Kafka's `GroupMetadata`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GroupState {
+    Empty,
+    PreparingRebalance,
+    CompletingRebalance,
+    Stable,
+}
+
+impl GroupState {
+    fn name(self) -> &'static str {
+        match self {
+            GroupState::Empty => "Empty",
+            GroupState::PreparingRebalance => "PreparingRebalance",
+            GroupState::CompletingRebalance => "CompletingRebalance",
+            GroupState::Stable => "Stable",
+        }
+    }
+}
+
+/// The state reported for groups the coordinator does not know.
+pub const DEAD_STATE: &str = "Dead";
+
+/// The protocol type of groups whose members use the Java consumer's subscription format.
+const CONSUMER_PROTOCOL_TYPE: &str = "consumer";
+
+/// The ListGroups type of groups running the JoinGroup/SyncGroup protocol.
+const CLASSIC_GROUP_TYPE: &str = "classic";
+
+#[derive(Debug)]
+struct Member {
+    group_instance_id: Option<String>,
+    client_id: String,
+    client_host: String,
+    session_timeout: Duration,
+    rebalance_timeout: Duration,
+    /// Supported protocols in preference order, with their subscription metadata.
+    protocols: Vec<(String, Bytes)>,
+    assignment: Bytes,
+    last_heartbeat: Instant,
+    /// A JoinGroup of this member is parked until the rebalance completes.
+    awaiting_join: bool,
+    /// A SyncGroup of this member is parked until the leader sends the assignment.
+    awaiting_sync: bool,
+}
+
+impl Member {
+    fn metadata(&self, protocol: &str) -> Bytes {
+        self.protocols
+            .iter()
+            .find(|(name, _)| name == protocol)
+            .map(|(_, metadata)| metadata.clone())
+            .unwrap_or_default()
+    }
+
+    /// Members taking part in a rebalance are kept alive by their parked request.
+    fn session_expiry(&self) -> Option<Instant> {
+        if self.awaiting_join || self.awaiting_sync {
+            None
+        } else {
+            Some(self.last_heartbeat + self.session_timeout)
+        }
+    }
+}
+
+/// A JoinGroup request with its timeouts resolved.
+struct JoinParams {
+    member_id: String,
+    group_instance_id: Option<String>,
+    client_id: String,
+    client_host: String,
+    session_timeout: Duration,
+    rebalance_timeout: Duration,
+    protocol_type: String,
+    protocols: Vec<(String, Bytes)>,
+    require_known_member_id: bool,
+}
+
+enum Joined {
+    Done(JoinResult),
+    /// The member's join is parked until the rebalance completes.
+    Waiting(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct JoinedMember {
+    pub member_id: String,
+    pub group_instance_id: Option<String>,
+    pub metadata: Bytes,
+}
+
+#[derive(Debug, Clone)]
+pub struct JoinResult {
+    pub error: Option<ResponseError>,
+    pub member_id: String,
+    pub generation_id: i32,
+    pub protocol_type: Option<String>,
+    pub protocol_name: Option<String>,
+    pub leader_id: String,
+    pub skip_assignment: bool,
+    /// Every member's subscription, sent to the leader only.
+    pub members: Vec<JoinedMember>,
+}
+
+impl JoinResult {
+    fn error(member_id: &str, error: ResponseError) -> JoinResult {
+        JoinResult {
+            error: Some(error),
+            member_id: member_id.to_string(),
+            generation_id: -1,
+            protocol_type: None,
+            protocol_name: None,
+            leader_id: String::new(),
+            skip_assignment: false,
+            members: Vec::new(),
+        }
+    }
+}
+
+/// A group as listed by ListGroups.
+#[derive(Debug, Clone)]
+pub struct GroupOverview {
+    pub group_id: String,
+    pub protocol_type: String,
+    pub state: &'static str,
+    pub group_type: &'static str,
+}
+
+#[derive(Debug, Clone)]
+pub struct MemberDescription {
+    pub member_id: String,
+    pub group_instance_id: Option<String>,
+    pub client_id: String,
+    pub client_host: String,
+    pub metadata: Bytes,
+    pub assignment: Bytes,
+}
+
+/// A group as described by DescribeGroups. Member subscriptions and
+/// assignments, like the protocol, are only known while the group is stable.
+#[derive(Debug, Clone)]
+pub struct GroupDescription {
+    pub state: &'static str,
+    pub protocol_type: String,
+    pub protocol_name: String,
+    pub members: Vec<MemberDescription>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncResult {
+    pub protocol_type: Option<String>,
+    pub protocol_name: Option<String>,
+    pub assignment: Bytes,
+}
+
+#[derive(Debug)]
+struct Group {
+    group_id: String,
+    state: GroupState,
+    generation_id: i32,
+    protocol_type: Option<String>,
+    protocol_name: Option<String>,
+    leader_id: Option<String>,
+    members: IndexMap<String, Member>,
+    /// Member ids of static members by `group.instance.id`.
+    static_members: HashMap<String, String>,
+    /// Member ids handed out with MEMBER_ID_REQUIRED and when they lapse if unused.
+    pending_members: HashMap<String, Instant>,
+    /// Members of the current generation that have not sent SyncGroup yet.
+    pending_sync_members: HashSet<String>,
+    rebalance_deadline: Option<Instant>,
+    /// While set, the first rebalance of an empty group waits for more members to join.
+    initial_delay_until: Option<Instant>,
+    sync_deadline: Option<Instant>,
+    /// Responses for parked joins and syncs, collected by the waiting request threads.
+    join_results: HashMap<String, JoinResult>,
+    sync_results: HashMap<String, Result<SyncResult, ResponseError>>,
+}
+
+impl Group {
+    fn new(group_id: &str) -> Group {
+        Group {
+            group_id: group_id.to_string(),
+            state: GroupState::Empty,
+            generation_id: 0,
+            protocol_type: None,
+            protocol_name: None,
+            leader_id: None,
+            members: IndexMap::new(),
+            static_members: HashMap::new(),
+            pending_members: HashMap::new(),
+            pending_sync_members: HashSet::new(),
+            rebalance_deadline: None,
+            initial_delay_until: None,
+            sync_deadline: None,
+            join_results: HashMap::new(),
+            sync_results: HashMap::new(),
+        }
+    }
+
+    fn is_leader(&self, member_id: &str) -> bool {
+        self.leader_id.as_deref() == Some(member_id)
+    }
+
+    fn is_static_member_fenced(&self, group_instance_id: Option<&str>, member_id: &str) -> bool {
+        group_instance_id
+            .and_then(|instance_id| self.static_members.get(instance_id))
+            .is_some_and(|current| current != member_id)
+    }
+
+    /// Checks the member and generation a SyncGroup or Heartbeat claims to belong to.
+    fn validate_member(&self, member_id: &str, group_instance_id: Option<&str>, generation_id: i32) -> Result<(), ResponseError> {
+        if self.is_static_member_fenced(group_instance_id, member_id) {
+            return Err(ResponseError::FencedInstanceId);
+        }
+        if !self.members.contains_key(member_id) {
+            return Err(ResponseError::UnknownMemberId);
+        }
+        if generation_id != self.generation_id {
+            return Err(ResponseError::IllegalGeneration);
+        }
+        Ok(())
+    }
+
+    /// Whether a joining member can take part in the group's protocol.
+    fn supports(&self, protocol_type: &str, protocols: &[(String, Bytes)]) -> bool {
+        if self.members.is_empty() {
+            return true;
+        }
+        self.protocol_type.as_deref() == Some(protocol_type)
+            && protocols.iter().any(|(name, _)| {
+                self.members
+                    .values()
+                    .all(|member| member.protocols.iter().any(|(supported, _)| supported == name))
+            })
+    }
+
+    /// Each member votes for its most preferred protocol that every member supports.
+    fn select_protocol(&self) -> Option<String> {
+        let first = self.members.values().next()?;
+        let candidates: Vec<&String> = first
+            .protocols
+            .iter()
+            .map(|(name, _)| name)
+            .filter(|name| {
+                self.members
+                    .values()
+                    .all(|member| member.protocols.iter().any(|(supported, _)| &supported == name))
+            })
+            .collect();
+        let mut votes: HashMap<&String, usize> = HashMap::new();
+        for member in self.members.values() {
+            if let Some((name, _)) = member.protocols.iter().find(|(name, _)| candidates.contains(&name)) {
+                *votes.entry(name).or_default() += 1;
+            }
+        }
+        let mut selected: Option<(&String, usize)> = None;
+        for candidate in candidates {
+            let count = votes.get(candidate).copied().unwrap_or_default();
+            if selected.map_or(true, |(_, best)| count > best) {
+                selected = Some((candidate, count));
+            }
+        }
+        selected.map(|(name, _)| name.clone())
+    }
+
+    /// The current generation as seen by `member_id`, for joins answered without a rebalance.
+    fn current_join_result(&self, member_id: &str, skip_assignment: bool) -> JoinResult {
+        JoinResult {
+            error: None,
+            member_id: member_id.to_string(),
+            generation_id: self.generation_id,
+            protocol_type: self.protocol_type.clone(),
+            protocol_name: self.protocol_name.clone(),
+            leader_id: self.leader_id.clone().unwrap_or_default(),
+            skip_assignment,
+            members: if self.is_leader(member_id) { self.joined_members() } else { Vec::new() },
+        }
+    }
+
+    fn joined_members(&self) -> Vec<JoinedMember> {
+        let protocol = self.protocol_name.as_deref().unwrap_or_default();
+        self.members
+            .iter()
+            .map(|(member_id, member)| JoinedMember {
+                member_id: member_id.clone(),
+                group_instance_id: member.group_instance_id.clone(),
+                metadata: member.metadata(protocol),
+            })
+            .collect()
+    }
+
+    fn join(&mut self, params: JoinParams, now: Instant) -> Joined {
+        let member_id = params.member_id.clone();
+        if !self.supports(&params.protocol_type, &params.protocols) {
+            return Joined::Done(JoinResult::error(&member_id, ResponseError::InconsistentGroupProtocol));
+        }
+
+        if member_id.is_empty() {
+            let existing = params
+                .group_instance_id
+                .as_ref()
+                .and_then(|instance_id| self.static_members.get(instance_id))
+                .cloned();
+            if let Some(old_member_id) = existing {
+                return self.rejoin_static_member(&old_member_id, params, now);
+            }
+            let prefix = params.group_instance_id.as_deref().unwrap_or(&params.client_id);
+            let new_member_id = format!("{}-{}", prefix, Uuid::new_v4());
+            if params.require_known_member_id && params.group_instance_id.is_none() {
+                self.pending_members.insert(new_member_id.clone(), now + params.session_timeout);
+                return Joined::Done(JoinResult::error(&new_member_id, ResponseError::MemberIdRequired));
+            }
+            self.add_member(new_member_id.clone(), params, now);
+            return Joined::Waiting(new_member_id);
+        }
+
+        if self.pending_members.remove(&member_id).is_some() {
+            self.add_member(member_id.clone(), params, now);
+            return Joined::Waiting(member_id);
+        }
+        if self.is_static_member_fenced(params.group_instance_id.as_deref(), &member_id) {
+            return Joined::Done(JoinResult::error(&member_id, ResponseError::FencedInstanceId));
+        }
+        let Some(member) = self.members.get(&member_id) else {
+            return Joined::Done(JoinResult::error(&member_id, ResponseError::UnknownMemberId));
+        };
+
+        let unchanged = member.protocols == params.protocols;
+        match self.state {
+            GroupState::Empty => Joined::Done(JoinResult::error(&member_id, ResponseError::UnknownMemberId)),
+            // A member that missed its JoinGroup response gets the current generation again.
+            GroupState::CompletingRebalance if unchanged => Joined::Done(self.current_join_result(&member_id, false)),
+            GroupState::Stable if unchanged && !self.is_leader(&member_id) => {
+                Joined::Done(self.current_join_result(&member_id, false))
+            }
+            _ => {
+                self.update_member(&member_id, params, now);
+                if self.state != GroupState::PreparingRebalance {
+                    self.prepare_rebalance(now, &format!("member {} rejoined with new metadata", member_id));
+                }
+                Joined::Waiting(member_id)
+            }
+        }
+    }
+
+    /// A static member rejoining without its member id replaces its previous
+    /// incarnation, which is fenced. A stable group only rebalances when the
+    /// new subscription changes the selected protocol.
+    fn rejoin_static_member(&mut self, old_member_id: &str, params: JoinParams, now: Instant) -> Joined {
+        let instance_id = params.group_instance_id.clone().unwrap_or_default();
+        let new_member_id = format!("{}-{}", instance_id, Uuid::new_v4());
+        let Some(mut member) = self.members.shift_remove(old_member_id) else {
+            return Joined::Done(JoinResult::error(old_member_id, ResponseError::UnknownMemberId));
+        };
+        if member.awaiting_join {
+            self.join_results
+                .insert(old_member_id.to_string(), JoinResult::error(old_member_id, ResponseError::FencedInstanceId));
+        }
+        if member.awaiting_sync {
+            self.sync_results.insert(old_member_id.to_string(), Err(ResponseError::FencedInstanceId));
+        }
+        if self.pending_sync_members.remove(old_member_id) {
+            self.pending_sync_members.insert(new_member_id.clone());
+        }
+        member.awaiting_join = false;
+        member.awaiting_sync = false;
+        self.members.insert(new_member_id.clone(), member);
+        self.static_members.insert(instance_id, new_member_id.clone());
+        if self.is_leader(old_member_id) {
+            self.leader_id = Some(new_member_id.clone());
+        }
+        self.update_member(&new_member_id, params, now);
+
+        match self.state {
+            GroupState::Stable if self.select_protocol() == self.protocol_name => {
+                if let Some(member) = self.members.get_mut(&new_member_id) {
+                    member.awaiting_join = false;
+                }
+                let is_leader = self.is_leader(&new_member_id);
+                Joined::Done(self.current_join_result(&new_member_id, is_leader))
+            }
+            GroupState::PreparingRebalance => Joined::Waiting(new_member_id),
+            _ => {
+                self.prepare_rebalance(now, &format!("static member {} was replaced by {}", old_member_id, new_member_id));
+                Joined::Waiting(new_member_id)
+            }
+        }
+    }
+
+    fn add_member(&mut self, member_id: String, params: JoinParams, now: Instant) {
+        if self.members.is_empty() {
+            self.protocol_type = Some(params.protocol_type.clone());
+        }
+        if self.leader_id.is_none() {
+            self.leader_id = Some(member_id.clone());
+        }
+        if let Some(instance_id) = &params.group_instance_id {
+            self.static_members.insert(instance_id.clone(), member_id.clone());
+        }
+        self.members.insert(
+            member_id.clone(),
+            Member {
+                group_instance_id: params.group_instance_id,
+                client_id: params.client_id,
+                client_host: params.client_host,
+                session_timeout: params.session_timeout,
+                rebalance_timeout: params.rebalance_timeout,
+                protocols: params.protocols,
+                assignment: Bytes::new(),
+                last_heartbeat: now,
+                awaiting_join: true,
+                awaiting_sync: false,
+            },
+        );
+        self.join_results.remove(&member_id);
+
+        if self.state == GroupState::PreparingRebalance {
+            // Each member joining during the initial delay extends it, up to the rebalance timeout.
+            if let (Some(_), Some(deadline)) = (self.initial_delay_until, self.rebalance_deadline) {
+                let delay = Duration::from_millis(config::get().group_initial_rebalance_delay_ms.max(0) as u64);
+                self.initial_delay_until = Some((now + delay).min(deadline));
+            }
+        } else {
+            self.prepare_rebalance(now, &format!("adding new member {}", member_id));
+        }
+    }
+
+    fn update_member(&mut self, member_id: &str, params: JoinParams, now: Instant) {
+        if let Some(member) = self.members.get_mut(member_id) {
+            member.client_id = params.client_id;
+            member.client_host = params.client_host;
+            member.session_timeout = params.session_timeout;
+            member.rebalance_timeout = params.rebalance_timeout;
+            member.protocols = params.protocols;
+            member.last_heartbeat = now;
+            member.awaiting_join = true;
+        }
+        self.join_results.remove(member_id);
+    }
+
+    fn remove_member(&mut self, member_id: &str, error: ResponseError, now: Instant) {
+        let Some(member) = self.members.shift_remove(member_id) else {
+            return;
+        };
+        if let Some(instance_id) = &member.group_instance_id {
+            if self.static_members.get(instance_id).is_some_and(|id| id == member_id) {
+                self.static_members.remove(instance_id);
+            }
+        }
+        if member.awaiting_join {
+            self.join_results.insert(member_id.to_string(), JoinResult::error(member_id, error));
+        }
+        if member.awaiting_sync {
+            self.sync_results.insert(member_id.to_string(), Err(error));
+        }
+        self.pending_sync_members.remove(member_id);
+        if self.is_leader(member_id) {
+            self.leader_id = self.members.keys().next().cloned();
+        }
+        match self.state {
+            GroupState::Stable | GroupState::CompletingRebalance => {
+                self.prepare_rebalance(now, &format!("removing member {}", member_id));
+            }
+            GroupState::Empty | GroupState::PreparingRebalance => {}
+        }
+    }
+
+    fn prepare_rebalance(&mut self, now: Instant, reason: &str) {
+        // Syncs parked on the old generation can no longer get an assignment.
+        for (member_id, member) in self.members.iter_mut() {
+            if member.awaiting_sync {
+                member.awaiting_sync = false;
+                self.sync_results.insert(member_id.clone(), Err(ResponseError::RebalanceInProgress));
+            }
+        }
+        self.pending_sync_members.clear();
+        self.sync_deadline = None;
+
+        let rebalance_timeout = self.members.values().map(|m| m.rebalance_timeout).max().unwrap_or_default();
+        let initial_delay = Duration::from_millis(config::get().group_initial_rebalance_delay_ms.max(0) as u64);
+        self.initial_delay_until = (self.state == GroupState::Empty && !initial_delay.is_zero())
+            .then(|| now + initial_delay.min(rebalance_timeout));
+        self.rebalance_deadline = Some(now + rebalance_timeout);
+        self.state = GroupState::PreparingRebalance;
+        println!(
+            "Preparing to rebalance group {} in state PreparingRebalance with old generation {} ({})",
+            self.group_id, self.generation_id, reason
+        );
+    }
+
+    /// Completes the join phase once every member has rejoined, or when the
+    /// rebalance timeout passes, dropping the dynamic members that did not rejoin.
+    fn try_complete_join(&mut self, now: Instant) -> bool {
+        if self.state != GroupState::PreparingRebalance {
+            return false;
+        }
+        let all_joined = self.pending_members.is_empty() && self.members.values().all(|m| m.awaiting_join);
+        let delayed = self.initial_delay_until.is_some_and(|until| now < until);
+        let expired = self.rebalance_deadline.is_some_and(|deadline| now >= deadline);
+        if !expired && (!all_joined || delayed) {
+            return false;
+        }
+
+        let not_rejoined: Vec<String> = self
+            .members
+            .iter()
+            .filter(|(_, member)| !member.awaiting_join && member.group_instance_id.is_none())
+            .map(|(member_id, _)| member_id.clone())
+            .collect();
+        for member_id in not_rejoined {
+            self.remove_member(&member_id, ResponseError::UnknownMemberId, now);
+        }
+
+        self.initial_delay_until = None;
+        self.rebalance_deadline = None;
+        self.generation_id += 1;
+        if self.members.is_empty() {
+            self.state = GroupState::Empty;
+            self.protocol_name = None;
+            println!("Group {} with generation {} is now empty", self.group_id, self.generation_id);
+            return true;
+        }
+
+        self.protocol_name = self.select_protocol();
+        self.state = GroupState::CompletingRebalance;
+        let rebalance_timeout = self.members.values().map(|m| m.rebalance_timeout).max().unwrap_or_default();
+        self.sync_deadline = Some(now + rebalance_timeout);
+        self.pending_sync_members = self.members.keys().cloned().collect();
+        let joined: Vec<String> = self
+            .members
+            .iter()
+            .filter(|(_, member)| member.awaiting_join)
+            .map(|(member_id, _)| member_id.clone())
+            .collect();
+        for member_id in joined {
+            let result = self.current_join_result(&member_id, false);
+            self.join_results.insert(member_id.clone(), result);
+            if let Some(member) = self.members.get_mut(&member_id) {
+                member.awaiting_join = false;
+                member.last_heartbeat = now;
+            }
+        }
+        println!(
+            "Stabilized group {} generation {} with {} members",
+            self.group_id,
+            self.generation_id,
+            self.members.len()
+        );
+        true
+    }
+
+    fn complete_sync(&mut self, assignments: HashMap<String, Bytes>) {
+        for (member_id, member) in self.members.iter_mut() {
+            member.assignment = assignments.get(member_id).cloned().unwrap_or_default();
+            if member.awaiting_sync {
+                member.awaiting_sync = false;
+                let result = SyncResult {
+                    protocol_type: self.protocol_type.clone(),
+                    protocol_name: self.protocol_name.clone(),
+                    assignment: member.assignment.clone(),
+                };
+                self.sync_results.insert(member_id.clone(), Ok(result));
+            }
+        }
+        self.state = GroupState::Stable;
+        println!("Assignment received from leader for group {} for generation {}", self.group_id, self.generation_id);
+    }
+
+    /// Expires lapsed members and completes whatever phase their absence was
+    /// holding up. Returns whether the group changed.
+    fn maintain(&mut self, now: Instant) -> bool {
+        let mut changed = false;
+
+        let lapsed: Vec<String> = self
+            .pending_members
+            .iter()
+            .filter(|(_, deadline)| now >= **deadline)
+            .map(|(member_id, _)| member_id.clone())
+            .collect();
+        for member_id in lapsed {
+            self.pending_members.remove(&member_id);
+            changed = true;
+        }
+
+        let expired: Vec<String> = self
+            .members
+            .iter()
+            .filter(|(_, member)| member.session_expiry().is_some_and(|expiry| now >= expiry))
+            .map(|(member_id, _)| member_id.clone())
+            .collect();
+        for member_id in expired {
+            println!("Member {} in group {} has failed, removing it from the group", member_id, self.group_id);
+            self.remove_member(&member_id, ResponseError::UnknownMemberId, now);
+            changed = true;
+        }
+
+        if self.sync_deadline.is_some_and(|deadline| now >= deadline) {
+            self.sync_deadline = None;
+            let unsynced: Vec<String> = self.pending_sync_members.drain().collect();
+            for member_id in unsynced {
+                println!("Member {} in group {} did not sync in time, removing it from the group", member_id, self.group_id);
+                self.remove_member(&member_id, ResponseError::UnknownMemberId, now);
+                changed = true;
+            }
+        }
+
+        changed | self.try_complete_join(now)
+    }
+
+    fn describe(&self) -> GroupDescription {
+        let protocol = match self.state {
+            GroupState::Stable => self.protocol_name.clone().unwrap_or_default(),
+            _ => String::new(),
+        };
+        let members = self
+            .members
+            .iter()
+            .map(|(member_id, member)| {
+                let (metadata, assignment) = if protocol.is_empty() {
+                    (Bytes::new(), Bytes::new())
+                } else {
+                    (member.metadata(&protocol), member.assignment.clone())
+                };
+                MemberDescription {
+                    member_id: member_id.clone(),
+                    group_instance_id: member.group_instance_id.clone(),
+                    client_id: member.client_id.clone(),
+                    client_host: member.client_host.clone(),
+                    metadata,
+                    assignment,
+                }
+            })
+            .collect();
+        GroupDescription {
+            state: self.state.name(),
+            protocol_type: self.protocol_type.clone().unwrap_or_default(),
+            protocol_name: protocol,
+            members,
+        }
+    }
+
+    /// Whether a member's subscription in the selected protocol names the topic.
+    /// Subscriptions that cannot be read count as subscribed, keeping their offsets.
+    fn is_subscribed_to(&self, topic: &str) -> bool {
+        let Some(protocol) = self.protocol_name.as_deref() else {
+            return true;
+        };
+        self.members
+            .values()
+            .any(|member| subscribed_topics(member.metadata(protocol)).map_or(true, |topics| topics.iter().any(|t| t == topic)))
+    }
+
+    /// The next time `maintain` may have something to do.
+    fn next_deadline(&self) -> Option<Instant> {
+        let rebalance = match self.state {
+            GroupState::PreparingRebalance => self.initial_delay_until.or(self.rebalance_deadline),
+            _ => None,
+        };
+        rebalance
+            .into_iter()
+            .chain(self.sync_deadline)
+            .chain(self.pending_members.values().copied())
+            .chain(self.members.values().filter_map(Member::session_expiry))
+            .min()
+    }
+}
+
+/// Reads the topics of a consumer protocol subscription, which starts with its version.
+fn subscribed_topics(mut metadata: Bytes) -> Option<Vec<String>> {
+    if metadata.remaining() < 2 {
+        return None;
+    }
+    let version = metadata.get_i16();
+    if version < 0 {
+        return None;
+    }
+    // Newer subscription versions only append fields, so read them with the latest we know.
+    let version = version.min(ConsumerProtocolSubscription::VERSIONS.max);
+    let subscription = ConsumerProtocolSubscription::decode(&mut metadata, version).ok()?;
+    Some(subscription.topics.iter().map(|topic| topic.to_string()).collect())
+}
+
+/// Parks a join or sync until `take` finds its response, running the group's
+/// timers meanwhile so a rebalance completes even when no other request arrives.
+fn await_result<T>(group_id: &str, mut take: impl FnMut(&mut Group) -> Option<T>, missing: impl Fn() -> T) -> T {
+    let keys = [group_id.to_string()];
+    loop {
+        let watch = GROUP_PURGATORY.watch(&keys);
+        let (result, changed, deadline) = {
+            let mut groups = GROUPS.lock().unwrap();
+            let Some(group) = groups.get_mut(group_id) else {
+                return missing();
+            };
+            let changed = group.maintain(Instant::now());
+            (take(group), changed, group.next_deadline())
+        };
+        if changed {
+            GROUP_PURGATORY.check_and_complete(&keys[0]);
+        }
+        if let Some(result) = result {
+            return result;
+        }
+        // Every parked request has a deadline; the fallback only guards against a missed wake-up.
+        watch.wait_until(deadline.unwrap_or_else(|| Instant::now() + Duration::from_secs(1)));
+    }
+}
+
+/// Runs `operation` against a group after expiring its lapsed members, waking
+/// the requests parked on it afterwards.
+fn with_group<T>(group_id: &str, create: bool, operation: impl FnOnce(&mut Group, Instant) -> T) -> Option<T> {
+    let result = {
+        let mut groups = GROUPS.lock().unwrap();
+        let group = match groups.get_mut(group_id) {
+            Some(group) => group,
+            // A consumer protocol group only gives up its id once it is empty.
+            None if create && consumer_group::release(group_id) => {
+                groups.entry(group_id.to_string()).or_insert_with(|| Group::new(group_id))
+            }
+            None => return None,
+        };
+        let now = Instant::now();
+        group.maintain(now);
+        let result = operation(group, now);
+        group.maintain(now);
+        result
+    };
+    GROUP_PURGATORY.check_and_complete(&group_id.to_string());
+    Some(result)
+}
+
+/// Runs `operation` unless a classic group with members holds the id. An empty
+/// classic group is dropped so a consumer protocol group can take the id over.
+pub fn without_classic_group<T>(group_id: &str, operation: impl FnOnce() -> T) -> Option<T> {
+    let mut groups = GROUPS.lock().unwrap();
+    if let Some(group) = groups.get_mut(group_id) {
+        group.maintain(Instant::now());
+        if group.state != GroupState::Empty || !group.pending_members.is_empty() {
+            return None;
+        }
+        groups.remove(group_id);
+    }
+    Some(operation())
+}
+
+/// Handles a JoinGroup, blocking until the rebalance it takes part in completes.
+pub fn join_group(req: &JoinGroupRequest, version: i16, client_id: &str, client_host: &str) -> JoinResult {
+    let group_id = req.group_id.as_str();
+    let member_id = req.member_id.as_str();
+    let broker_config = config::get();
+
+    if group_id.is_empty() {
+        return JoinResult::error(member_id, ResponseError::InvalidGroupId);
+    }
+    if req.session_timeout_ms < broker_config.group_min_session_timeout_ms
+        || req.session_timeout_ms > broker_config.group_max_session_timeout_ms
+    {
+        return JoinResult::error(member_id, ResponseError::InvalidSessionTimeout);
+    }
+    if req.protocol_type.is_empty() || req.protocols.is_empty() {
+        return JoinResult::error(member_id, ResponseError::InconsistentGroupProtocol);
+    }
+
+    // JoinGroup v0 has no rebalance timeout and uses the session timeout instead.
+    let rebalance_timeout_ms = if req.rebalance_timeout_ms < 0 { req.session_timeout_ms } else { req.rebalance_timeout_ms };
+    let params = JoinParams {
+        member_id: member_id.to_string(),
+        group_instance_id: req.group_instance_id.as_ref().map(|id| id.to_string()),
+        client_id: client_id.to_string(),
+        client_host: client_host.to_string(),
+        session_timeout: Duration::from_millis(req.session_timeout_ms as u64),
+        rebalance_timeout: Duration::from_millis(rebalance_timeout_ms.max(0) as u64),
+        protocol_type: req.protocol_type.to_string(),
+        protocols: req
+            .protocols
+            .iter()
+            .map(|protocol| (protocol.name.to_string(), protocol.metadata.clone()))
+            .collect(),
+        require_known_member_id: version >= MEMBER_ID_REQUIRED_VERSION,
+    };
+
+    let joined = with_group(group_id, member_id.is_empty(), |group, now| group.join(params, now));
+    match joined {
+        // The id belongs to a consumer protocol group with members.
+        None if member_id.is_empty() => JoinResult::error(member_id, ResponseError::InconsistentGroupProtocol),
+        None => JoinResult::error(member_id, ResponseError::UnknownMemberId),
+        Some(Joined::Done(result)) => result,
+        Some(Joined::Waiting(member_id)) => await_result(
+            group_id,
+            |group| group.join_results.remove(&member_id),
+            || JoinResult::error(&member_id, ResponseError::UnknownMemberId),
+        ),
+    }
+}
+
+/// Handles a SyncGroup. Followers block until the leader sends the assignment.
+pub fn sync_group(req: &SyncGroupRequest) -> Result<SyncResult, ResponseError> {
+    let group_id = req.group_id.as_str();
+    let member_id = req.member_id.as_str();
+    let synced = with_group(group_id, false, |group, _| {
+        group.validate_member(member_id, req.group_instance_id.as_deref(), req.generation_id)?;
+        if req.protocol_type.as_deref().is_some_and(|t| group.protocol_type.as_deref() != Some(t))
+            || req.protocol_name.as_deref().is_some_and(|n| group.protocol_name.as_deref() != Some(n))
+        {
+            return Err(ResponseError::InconsistentGroupProtocol);
+        }
+        match group.state {
+            GroupState::Empty => Err(ResponseError::UnknownMemberId),
+            GroupState::PreparingRebalance => Err(ResponseError::RebalanceInProgress),
+            GroupState::CompletingRebalance => {
+                group.pending_sync_members.remove(member_id);
+                group.sync_results.remove(member_id);
+                if let Some(member) = group.members.get_mut(member_id) {
+                    member.awaiting_sync = true;
+                }
+                if group.is_leader(member_id) {
+                    let assignments = req
+                        .assignments
+                        .iter()
+                        .map(|a| (a.member_id.to_string(), a.assignment.clone()))
+                        .collect();
+                    group.complete_sync(assignments);
+                }
+                Ok(None)
+            }
+            GroupState::Stable => {
+                group.pending_sync_members.remove(member_id);
+                Ok(Some(SyncResult {
+                    protocol_type: group.protocol_type.clone(),
+                    protocol_name: group.protocol_name.clone(),
+                    assignment: group.members[member_id].assignment.clone(),
+                }))
+            }
+        }
+    });
+    match synced {
+        None => Err(ResponseError::UnknownMemberId),
+        Some(Err(e)) => Err(e),
+        Some(Ok(Some(result))) => Ok(result),
+        Some(Ok(None)) => await_result(
+            group_id,
+            |group| group.sync_results.remove(member_id),
+            || Err(ResponseError::UnknownMemberId),
+        ),
+    }
+}
+
+pub fn heartbeat(req: &HeartbeatRequest) -> Result<(), ResponseError> {
+    let member_id = req.member_id.as_str();
+    with_group(req.group_id.as_str(), false, |group, now| {
+        group.validate_member(member_id, req.group_instance_id.as_deref(), req.generation_id)?;
+        if group.state == GroupState::Empty {
+            return Err(ResponseError::UnknownMemberId);
+        }
+        if let Some(member) = group.members.get_mut(member_id) {
+            member.last_heartbeat = now;
+        }
+        match group.state {
+            GroupState::PreparingRebalance => Err(ResponseError::RebalanceInProgress),
+            _ => Ok(()),
+        }
+    })
+    .unwrap_or(Err(ResponseError::UnknownMemberId))
+}
+
+/// Checks that an OffsetCommit comes from a member of the current generation or,
+/// with a negative generation, from a client that only stores offsets in an
+/// empty group, which is created on first use. A commit counts as a heartbeat.
+/// Members of consumer protocol groups commit with their member epoch instead.
+pub fn validate_offset_commit(
+    group_id: &str,
+    member_id: &str,
+    group_instance_id: Option<&str>,
+    generation_id: i32,
+    version: i16,
+) -> Result<(), ResponseError> {
+    if let Some(result) = consumer_group::validate_offset_commit(group_id, member_id, generation_id, version) {
+        return result;
+    }
+    with_group(group_id, generation_id < 0, |group, now| {
+        if group.is_static_member_fenced(group_instance_id, member_id) {
+            return Err(ResponseError::FencedInstanceId);
+        }
+        if generation_id < 0 && group.state == GroupState::Empty {
+            return Ok(());
+        }
+        // The member already has the new generation from its JoinGroup response,
+        // but must not commit until the assignment is known.
+        if group.state == GroupState::CompletingRebalance {
+            return Err(ResponseError::RebalanceInProgress);
+        }
+        let current_generation = group.generation_id;
+        let Some(member) = group.members.get_mut(member_id) else {
+            return Err(ResponseError::UnknownMemberId);
+        };
+        if generation_id != current_generation {
+            return Err(ResponseError::IllegalGeneration);
+        }
+        member.last_heartbeat = now;
+        Ok(())
+    })
+    .unwrap_or(Err(ResponseError::IllegalGeneration))
+}
+
+/// Removes each leaving member, returning a result per member. Static members
+/// leave by `group.instance.id`, optionally checked against their member id.
+pub fn leave_group(group_id: &str, members: &[MemberIdentity]) -> Vec<Result<(), ResponseError>> {
+    with_group(group_id, false, |group, now| {
+        members
+            .iter()
+            .map(|identity| {
+                let member_id = match &identity.group_instance_id {
+                    Some(instance_id) => {
+                        let Some(current) = group.static_members.get(instance_id.as_str()).cloned() else {
+                            return Err(ResponseError::UnknownMemberId);
+                        };
+                        if !identity.member_id.is_empty() && identity.member_id.as_str() != current {
+                            return Err(ResponseError::FencedInstanceId);
+                        }
+                        current
+                    }
+                    None => identity.member_id.to_string(),
+                };
+                if group.pending_members.remove(&member_id).is_some() {
+                    return Ok(());
+                }
+                if !group.members.contains_key(&member_id) {
+                    return Err(ResponseError::UnknownMemberId);
+                }
+                println!("Member {} has left group {}", member_id, group_id);
+                group.remove_member(&member_id, ResponseError::UnknownMemberId, now);
+                Ok(())
+            })
+            .collect()
+    })
+    .unwrap_or_else(|| members.iter().map(|_| Err(ResponseError::UnknownMemberId)).collect())
+}
+
+/// Every group the coordinator knows, sorted by id. Groups that only have
+/// committed offsets are listed as empty classic groups.
+pub fn list_groups() -> Vec<GroupOverview> {
+    let with_offsets = consumer_offsets::groups();
+    let consumer_groups = consumer_group::list_groups();
+    let (mut overviews, changed) = {
+        let mut groups = GROUPS.lock().unwrap();
+        let now = Instant::now();
+        let mut overviews = Vec::new();
+        let mut changed = Vec::new();
+        for (group_id, group) in groups.iter_mut() {
+            if group.maintain(now) {
+                changed.push(group_id.clone());
+            }
+            overviews.push(GroupOverview {
+                group_id: group_id.clone(),
+                protocol_type: group.protocol_type.clone().unwrap_or_default(),
+                state: group.state.name(),
+                group_type: CLASSIC_GROUP_TYPE,
+            });
+        }
+        for group_id in with_offsets {
+            if !groups.contains_key(&group_id) && !consumer_groups.iter().any(|group| group.group_id == group_id) {
+                overviews.push(GroupOverview {
+                    group_id,
+                    protocol_type: String::new(),
+                    state: GroupState::Empty.name(),
+                    group_type: CLASSIC_GROUP_TYPE,
+                });
+            }
+        }
+        overviews.extend(consumer_groups);
+        (overviews, changed)
+    };
+    for group_id in changed {
+        GROUP_PURGATORY.check_and_complete(&group_id);
+    }
+    overviews.sort_by(|a, b| a.group_id.cmp(&b.group_id));
+    overviews
+}
+
+/// Describes a group, or returns `None` when it has neither members nor offsets
+/// or runs the consumer protocol.
+pub fn describe_group(group_id: &str) -> Option<GroupDescription> {
+    if consumer_group::exists(group_id) {
+        return None;
+    }
+    if let Some(description) = with_group(group_id, false, |group, _| group.describe()) {
+        return Some(description);
+    }
+    (!consumer_offsets::fetch(group_id).is_empty()).then(|| GroupDescription {
+        state: GroupState::Empty.name(),
+        protocol_type: String::new(),
+        protocol_name: String::new(),
+        members: Vec::new(),
+    })
+}
+
+/// Deletes a group without members together with its committed offsets.
+pub fn delete_group(group_id: &str) -> Result<(), ResponseError> {
+    if group_id.is_empty() {
+        return Err(ResponseError::InvalidGroupId);
+    }
+    let result = {
+        // The group stays locked until its offsets are gone so nobody joins meanwhile.
+        let mut groups = GROUPS.lock().unwrap();
+        let state = groups.get_mut(group_id).map(|group| {
+            group.maintain(Instant::now());
+            group.state
+        });
+        let consumer_group = consumer_group::delete_group(group_id);
+        match (state, consumer_group) {
+            (Some(state), _) if state != GroupState::Empty => Err(ResponseError::NonEmptyGroup),
+            (_, Some(Err(e))) => Err(e),
+            _ => match consumer_offsets::delete(group_id, None) {
+                Err(e) => {
+                    eprintln!("Failed to delete offsets of group {}: {}", group_id, e);
+                    Err(ResponseError::UnknownServerError)
+                }
+                Ok(deleted) if state.is_none() && consumer_group.is_none() && deleted.is_empty() => {
+                    Err(ResponseError::GroupIdNotFound)
+                }
+                Ok(deleted) => {
+                    groups.remove(group_id);
+                    println!("Deleted group {} and {} committed offsets", group_id, deleted.len());
+                    Ok(())
+                }
+            },
+        }
+    };
+    GROUP_PURGATORY.check_and_complete(&group_id.to_string());
+    result
+}
+
+/// Deletes a group's committed offsets for the given partitions, returning an
+/// error per partition. While the group has members, offsets of topics they
+/// consume are kept, and groups outside the consumer protocol keep them all.
+pub fn delete_offsets(group_id: &str, partitions: &[(String, i32)]) -> Result<Vec<Option<ResponseError>>, ResponseError> {
+    if group_id.is_empty() {
+        return Err(ResponseError::InvalidGroupId);
+    }
+    let result = {
+        let mut groups = GROUPS.lock().unwrap();
+        let errors = match groups.get_mut(group_id) {
+            Some(group) => {
+                group.maintain(Instant::now());
+                if group.state == GroupState::Empty {
+                    Ok(vec![None; partitions.len()])
+                } else if group.protocol_type.as_deref() == Some(CONSUMER_PROTOCOL_TYPE) {
+                    Ok(partitions
+                        .iter()
+                        .map(|(topic, _)| group.is_subscribed_to(topic).then_some(ResponseError::GroupSubscribedToTopic))
+                        .collect())
+                } else {
+                    Err(ResponseError::NonEmptyGroup)
+                }
+            }
+            None => match consumer_group::subscribed_topics(group_id) {
+                Some(topics) => Ok(partitions
+                    .iter()
+                    .map(|(topic, _)| topics.contains(topic).then_some(ResponseError::GroupSubscribedToTopic))
+                    .collect()),
+                None if consumer_offsets::fetch(group_id).is_empty() => Err(ResponseError::GroupIdNotFound),
+                None => Ok(vec![None; partitions.len()]),
+            },
+        };
+        errors.and_then(|errors| {
+            let deletable: Vec<(String, i32)> = partitions
+                .iter()
+                .zip(&errors)
+                .filter(|(_, error)| error.is_none())
+                .map(|(partition, _)| partition.clone())
+                .collect();
+            match consumer_offsets::delete(group_id, Some(&deletable)) {
+                Ok(_) => Ok(errors),
+                Err(e) => {
+                    eprintln!("Failed to delete offsets of group {}: {}", group_id, e);
+                    Err(ResponseError::UnknownServerError)
+                }
+            }
+        })
+    };
+    GROUP_PURGATORY.check_and_complete(&group_id.to_string());
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SESSION_TIMEOUT: Duration = Duration::from_secs(10);
+    const REBALANCE_TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn params(member_id: &str, protocols: &[&str]) -> JoinParams {
+        JoinParams {
+            member_id: member_id.to_string(),
+            group_instance_id: None,
+            client_id: "client".to_string(),
+            client_host: "/127.0.0.1".to_string(),
+            session_timeout: SESSION_TIMEOUT,
+            rebalance_timeout: REBALANCE_TIMEOUT,
+            protocol_type: CONSUMER_PROTOCOL_TYPE.to_string(),
+            protocols: protocols.iter().map(|name| (name.to_string(), Bytes::from(name.to_string()))).collect(),
+            require_known_member_id: false,
+        }
+    }
+
+    fn waiting(joined: Joined) -> String {
+        match joined {
+            Joined::Waiting(member_id) => member_id,
+            Joined::Done(result) => panic!("join was answered right away: {:?}", result),
+        }
+    }
+
+    fn done(joined: Joined) -> JoinResult {
+        match joined {
+            Joined::Done(result) => result,
+            Joined::Waiting(member_id) => panic!("join of {} was parked", member_id),
+        }
+    }
+
+    fn initial_delay() -> Duration {
+        Duration::from_millis(config::get().group_initial_rebalance_delay_ms as u64)
+    }
+
+    /// A group whose two members joined and synced generation 1.
+    fn stable_group(now: Instant) -> (Group, String, String) {
+        let mut group = Group::new("g");
+        let a = waiting(group.join(params("", &["range"]), now));
+        let b = waiting(group.join(params("", &["range"]), now));
+        assert!(group.try_complete_join(now + initial_delay()));
+        group.complete_sync(HashMap::from([(a.clone(), Bytes::from_static(b"a")), (b.clone(), Bytes::from_static(b"b"))]));
+        assert_eq!(group.state, GroupState::Stable);
+        (group, a, b)
+    }
+
+    #[test]
+    fn members_join_after_the_initial_delay_and_sync_the_leader_assignment() {
+        let now = Instant::now();
+        let mut group = Group::new("g");
+
+        let mut first = params("", &["range"]);
+        first.require_known_member_id = true;
+        let required = done(group.join(first, now));
+        assert_eq!(required.error, Some(ResponseError::MemberIdRequired));
+        assert_eq!(group.state, GroupState::Empty);
+
+        let a = waiting(group.join(params(&required.member_id, &["range"]), now));
+        assert_eq!(group.state, GroupState::PreparingRebalance);
+        let b = waiting(group.join(params("", &["range"]), now));
+        assert!(!group.try_complete_join(now));
+
+        assert!(group.try_complete_join(now + initial_delay()));
+        assert_eq!((group.state, group.generation_id), (GroupState::CompletingRebalance, 1));
+        assert_eq!(group.protocol_name.as_deref(), Some("range"));
+        let leader = &group.join_results[&a];
+        assert_eq!((leader.leader_id.as_str(), leader.members.len()), (a.as_str(), 2));
+        assert!(group.join_results[&b].members.is_empty());
+
+        assert_eq!(group.validate_member(&b, None, 0), Err(ResponseError::IllegalGeneration));
+        assert_eq!(group.validate_member("other", None, 1), Err(ResponseError::UnknownMemberId));
+        assert_eq!(group.validate_member(&b, None, 1), Ok(()));
+
+        group.complete_sync(HashMap::from([(b.clone(), Bytes::from_static(b"assigned"))]));
+        assert_eq!(group.state, GroupState::Stable);
+        assert_eq!(group.members[&b].assignment, Bytes::from_static(b"assigned"));
+        assert!(group.members[&a].assignment.is_empty());
+    }
+
+    #[test]
+    fn members_vote_for_a_protocol_all_of_them_support() {
+        let now = Instant::now();
+        let mut group = Group::new("g");
+        waiting(group.join(params("", &["range", "roundrobin"]), now));
+        waiting(group.join(params("", &["roundrobin", "range"]), now));
+        waiting(group.join(params("", &["roundrobin", "sticky", "range"]), now));
+        assert_eq!(group.select_protocol().as_deref(), Some("roundrobin"));
+
+        let rejected = done(group.join(params("", &["sticky"]), now));
+        assert_eq!(rejected.error, Some(ResponseError::InconsistentGroupProtocol));
+        let mut other_type = params("", &["range"]);
+        other_type.protocol_type = "connect".to_string();
+        assert_eq!(done(group.join(other_type, now)).error, Some(ResponseError::InconsistentGroupProtocol));
+    }
+
+    #[test]
+    fn members_that_do_not_rejoin_are_dropped_at_the_rebalance_timeout() {
+        let now = Instant::now();
+        let (mut group, a, b) = stable_group(now);
+
+        // The follower rejoining with its metadata unchanged just gets the current generation.
+        let current = done(group.join(params(&b, &["range"]), now));
+        assert_eq!((current.generation_id, current.leader_id.as_str()), (1, a.as_str()));
+        assert_eq!(group.state, GroupState::Stable);
+
+        waiting(group.join(params(&a, &["range", "roundrobin"]), now));
+        assert_eq!(group.state, GroupState::PreparingRebalance);
+        assert!(!group.try_complete_join(now + REBALANCE_TIMEOUT / 2));
+        assert!(group.try_complete_join(now + REBALANCE_TIMEOUT));
+
+        assert_eq!((group.state, group.generation_id), (GroupState::CompletingRebalance, 2));
+        assert_eq!(group.members.keys().collect::<Vec<_>>(), vec![&a]);
+        assert_eq!(group.validate_member(&b, None, 2), Err(ResponseError::UnknownMemberId));
+    }
+
+    #[test]
+    fn an_expired_session_starts_a_rebalance() {
+        let now = Instant::now();
+        let (mut group, a, b) = stable_group(now);
+        let later = now + initial_delay() + SESSION_TIMEOUT;
+        group.members[&a].last_heartbeat = later;
+
+        assert!(group.maintain(later));
+        assert_eq!(group.state, GroupState::PreparingRebalance);
+        assert!(!group.members.contains_key(&b));
+
+        waiting(group.join(params(&a, &["range"]), later));
+        assert!(group.maintain(later));
+        assert_eq!((group.state, group.generation_id), (GroupState::CompletingRebalance, 2));
+        assert_eq!(group.leader_id.as_deref(), Some(a.as_str()));
+    }
+
+    #[test]
+    fn a_restarted_static_member_fences_its_previous_incarnation() {
+        let now = Instant::now();
+        let mut group = Group::new("g");
+        let static_params = || {
+            let mut p = params("", &["range"]);
+            p.group_instance_id = Some("instance".to_string());
+            p
+        };
+        let old = waiting(group.join(static_params(), now));
+        assert!(group.try_complete_join(now + initial_delay()));
+        group.complete_sync(HashMap::from([(old.clone(), Bytes::from_static(b"kept"))]));
+
+        let rejoined = done(group.join(static_params(), now));
+        assert_ne!(rejoined.member_id, old);
+        assert_eq!((rejoined.generation_id, rejoined.skip_assignment), (1, true));
+        assert_eq!(group.state, GroupState::Stable);
+        assert_eq!(group.members[&rejoined.member_id].assignment, Bytes::from_static(b"kept"));
+        assert_eq!(group.validate_member(&old, Some("instance"), 1), Err(ResponseError::FencedInstanceId));
+        assert_eq!(group.validate_member(&rejoined.member_id, Some("instance"), 1), Ok(()));
+    }
+}
@@ -1,60 +1,76 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
 use bytes::{BytesMut};
 use kafka_protocol::messages::api_versions_response::ApiVersion;
-use kafka_protocol::messages::{ApiKey, ApiVersionsRequest, ApiVersionsResponse, BrokerId, DescribeTopicPartitionsRequest, DescribeTopicPartitionsResponse, FetchRequest, FetchResponse, ProduceRequest, ProduceResponse, RequestHeader, ResponseHeader, TopicName};
+use kafka_protocol::messages::{ApiKey, ApiVersionsRequest, ApiVersionsResponse, BrokerId, ConsumerGroupDescribeRequest, ConsumerGroupDescribeResponse, ConsumerGroupHeartbeatRequest, ConsumerGroupHeartbeatResponse, CreatePartitionsRequest, CreatePartitionsResponse, CreateTopicsRequest, CreateTopicsResponse, DeleteGroupsRequest, DeleteGroupsResponse, DeleteTopicsRequest, DeleteTopicsResponse, DescribeGroupsRequest, DescribeGroupsResponse, DescribeTopicPartitionsRequest, DescribeTopicPartitionsResponse, FetchRequest, FetchResponse, FindCoordinatorRequest, FindCoordinatorResponse, HeartbeatRequest, HeartbeatResponse, JoinGroupRequest, JoinGroupResponse, LeaveGroupRequest, LeaveGroupResponse, ListGroupsRequest, ListGroupsResponse, ListOffsetsRequest, ListOffsetsResponse, MetadataRequest, MetadataResponse, OffsetCommitRequest, OffsetCommitResponse, OffsetDeleteRequest, OffsetDeleteResponse, OffsetFetchRequest, OffsetFetchResponse, ProduceRequest, ProduceResponse, RequestHeader, ResponseHeader, SyncGroupRequest, SyncGroupResponse, TopicName};
+use kafka_protocol::messages::{consumer_group_describe_response, consumer_group_heartbeat_response};
+use kafka_protocol::messages::create_partitions_response::CreatePartitionsTopicResult;
+use kafka_protocol::messages::create_topics_request::CreatableTopic;
+use kafka_protocol::messages::create_topics_response::{CreatableTopicConfigs, CreatableTopicResult};
+use kafka_protocol::messages::delete_groups_response::DeletableGroupResult;
+use kafka_protocol::messages::delete_topics_response::DeletableTopicResult;
+use kafka_protocol::messages::describe_groups_response::{DescribedGroup, DescribedGroupMember};
 use kafka_protocol::messages::describe_topic_partitions_response::{DescribeTopicPartitionsResponsePartition, DescribeTopicPartitionsResponseTopic};
+use kafka_protocol::messages::fetch_request::{FetchPartition, FetchTopic};
 use kafka_protocol::messages::fetch_response::{FetchableTopicResponse, PartitionData};
+use kafka_protocol::messages::find_coordinator_response::Coordinator;
+use kafka_protocol::messages::join_group_response::JoinGroupResponseMember;
+use kafka_protocol::messages::leave_group_request::MemberIdentity;
+use kafka_protocol::messages::leave_group_response::MemberResponse;
+use kafka_protocol::messages::list_groups_response::ListedGroup;
+use kafka_protocol::messages::list_offsets_response::{ListOffsetsPartitionResponse, ListOffsetsTopicResponse};
+use kafka_protocol::messages::metadata_response::{MetadataResponseBroker, MetadataResponsePartition, MetadataResponseTopic};
+use kafka_protocol::messages::offset_commit_response::{OffsetCommitResponsePartition, OffsetCommitResponseTopic};
+use kafka_protocol::messages::offset_delete_response::{OffsetDeleteResponsePartition, OffsetDeleteResponseTopic};
+use kafka_protocol::messages::offset_fetch_response::{OffsetFetchResponseGroup, OffsetFetchResponsePartition, OffsetFetchResponsePartitions, OffsetFetchResponseTopic, OffsetFetchResponseTopics};
+use kafka_protocol::messages::produce_request::TopicProduceData;
 use kafka_protocol::messages::produce_response::{PartitionProduceResponse, TopicProduceResponse};
-use kafka_protocol::protocol::Encodable;
+use kafka_protocol::protocol::{Encodable, StrBytes};
 use kafka_protocol::ResponseError;
-use crate::meta_parser::{decode, Partition};
-use crate::utils::{group_topics, read_records, write_records};
+use crate::{config, consumer_group, consumer_offsets, controller, fetch_session, group_coordinator, metadata};
+use crate::consumer_offsets::CommittedOffset;
+use crate::controller::ApiError;
+use crate::meta_parser::{PartitionRecord, RecordType};
+use crate::metadata::MetadataImage;
+use crate::registry::HANDLERS;
+use crate::log::{delete_log, get_log, now_ms};
+use crate::purgatory::{PartitionKey, FETCH_PURGATORY};
+use crate::utils::{read_cluster_id, write_records, TopicWithPartitions};
 
-pub fn process_api_version(header: RequestHeader, _req: ApiVersionsRequest) -> BytesMut{
+pub fn process_api_version(_api_key: ApiKey, header: RequestHeader, _req: ApiVersionsRequest) -> BytesMut{
     let mut response_buf = BytesMut::new();
 
     // Encode correlation_id
     let correlation_id = header.correlation_id;
     response_buf.extend_from_slice(&correlation_id.to_be_bytes());
 
-    let api_version_resp = ApiVersionsResponse::default()
-        .with_api_keys(vec!(
-            ApiVersion::default()
-                .with_api_key(18)
-                .with_min_version(0)
-                .with_max_version(4),
-            ApiVersion::default()
-                .with_api_key(75)
-                .with_min_version(0)
-                .with_max_version(0),
-            ApiVersion::default()
-                .with_api_key(1)
-                .with_min_version(0)
-                .with_max_version(16),
+    let api_keys: Vec<ApiVersion> = HANDLERS
+        .iter()
+        .map(|entry| {
             ApiVersion::default()
-                .with_api_key(0)
-                .with_min_version(0)
-                .with_max_version(11)
-        ));
-
-    // Encode the response
-    let _ = match api_version_resp.encode(&mut response_buf, header.request_api_version) {
-        Ok(_) => {}
-        Err(_) => {
-            ApiVersionsResponse::default()
-                .with_error_code(35)
-                .encode(&mut response_buf, 0).unwrap();
-        }
-    };
+                .with_api_key(entry.api_key as i16)
+                .with_min_version(entry.versions.min)
+                .with_max_version(entry.versions.max)
+        })
+        .collect();
+
+    let api_version_resp = ApiVersionsResponse::default()
+        .with_api_keys(api_keys.clone());
+
+    // Encode the response; unknown versions get a v0 response so the client can retry
+    if api_version_resp.encode(&mut response_buf, header.request_api_version).is_err() {
+        response_buf.truncate(4);
+        ApiVersionsResponse::default()
+            .with_error_code(ResponseError::UnsupportedVersion.code())
+            .with_api_keys(api_keys)
+            .encode(&mut response_buf, 0).unwrap();
+    }
 
     response_buf
 }
 
 pub fn process_produce(api_key : ApiKey, header: RequestHeader, req: ProduceRequest) -> BytesMut {
-    let res = decode().unwrap_or_else(|_| Vec::new());
-    println!(" +++++ {:#?}", res);
-
-    let grouped = group_topics(res);
+    let mut image = metadata::image();
 
     let mut response_buf = BytesMut::new();
 
@@ -68,12 +84,20 @@ pub fn process_produce(api_key : ApiKey, header: RequestHeader, req: ProduceRequ
 
     let mut response_topics = Vec::with_capacity(req.topic_data.len());
     for topic in req.topic_data {
-        let requested_name = topic.name.to_string();
+        // Only the broker itself appends to internal topics and the metadata log.
+        if controller::is_reserved_topic(&topic.name) {
+            response_topics.push(produce_topic_error(topic, ResponseError::InvalidTopicException));
+            continue;
+        }
+        if image.topic_by_name(&topic.name).is_none() && config::get().auto_create_topics_enable {
+            match auto_create_topic(&topic.name) {
+                Ok(()) => image = metadata::image(),
+                Err(e) => eprintln!("Failed to auto-create topic {}: {}", topic.name.as_str(), e.message),
+            }
+        }
 
         // Match only by topic name; we will validate each requested partition below.
-        let matched_topic = grouped
-            .iter()
-            .find(|tp| tp.topic.name.to_string() == requested_name);
+        let matched_topic = image.topic_by_name(&topic.name);
 
         let mut partition_responses = Vec::with_capacity(topic.partition_data.len());
 
@@ -84,12 +108,7 @@ pub fn process_produce(api_key : ApiKey, header: RequestHeader, req: ProduceRequ
                 for partition_data in &topic.partition_data {
                     let partition_id_u32 = partition_data.index as u32;
 
-                    let partition_exists = tp
-                        .partitions
-                        .iter()
-                        .any(|p| p.partition_id == partition_id_u32);
-
-                    if !partition_exists {
+                    let Some(partition) = tp.partition(partition_id_u32) else {
                         partition_responses.push(
                             PartitionProduceResponse::default()
                                 .with_error_code(ResponseError::UnknownTopicOrPartition.code())
@@ -99,19 +118,34 @@ pub fn process_produce(api_key : ApiKey, header: RequestHeader, req: ProduceRequ
                                 .with_log_start_offset(-1),
                         );
                         continue;
-                    }
+                    };
+                    let leader_epoch = partition.leader_epoch;
 
-                    if let Some(records) = partition_data.records.clone() {
-                        write_records(topic_name, partition_id_u32, records);
-                    }
+                    let appended = match partition_data.records.clone() {
+                        Some(records) => write_records(topic_name, partition_id_u32, leader_epoch, records),
+                        None => Err(anyhow::anyhow!("Produce request without records")),
+                    };
 
-                    partition_responses.push(
-                        PartitionProduceResponse::default()
-                            .with_index(partition_data.index)
-                            .with_base_offset(0)
-                            .with_log_append_time_ms(-1)
-                            .with_log_start_offset(0),
-                    );
+                    match appended {
+                        Ok(offsets) => partition_responses.push(
+                            PartitionProduceResponse::default()
+                                .with_index(partition_data.index)
+                                .with_base_offset(offsets.log_end_offset)
+                                .with_log_append_time_ms(-1)
+                                .with_log_start_offset(offsets.log_start_offset),
+                        ),
+                        Err(e) => {
+                            eprintln!("Failed to append to {}-{}: {}", topic_name, partition_id_u32, e);
+                            partition_responses.push(
+                                PartitionProduceResponse::default()
+                                    .with_error_code(ResponseError::CorruptMessage.code())
+                                    .with_index(partition_data.index)
+                                    .with_base_offset(-1)
+                                    .with_log_append_time_ms(-1)
+                                    .with_log_start_offset(-1),
+                            );
+                        }
+                    }
                 }
 
                 response_topics.push(
@@ -122,22 +156,7 @@ pub fn process_produce(api_key : ApiKey, header: RequestHeader, req: ProduceRequ
             }
             None => {
                 // Topic doesn't exist: return an error for each partition in the request.
-                for partition_data in &topic.partition_data {
-                    partition_responses.push(
-                        PartitionProduceResponse::default()
-                            .with_error_code(ResponseError::UnknownTopicOrPartition.code())
-                            .with_index(partition_data.index)
-                            .with_base_offset(-1)
-                            .with_log_append_time_ms(-1)
-                            .with_log_start_offset(-1),
-                    );
-                }
-
-                response_topics.push(
-                    TopicProduceResponse::default()
-                        .with_name(topic.name)
-                        .with_partition_responses(partition_responses),
-                );
+                response_topics.push(produce_topic_error(topic, ResponseError::UnknownTopicOrPartition));
             }
         }
     }
@@ -149,11 +168,27 @@ pub fn process_produce(api_key : ApiKey, header: RequestHeader, req: ProduceRequ
     response_buf
 }
 
-pub fn process_fetch(api_key : ApiKey, header: RequestHeader, req: FetchRequest) -> BytesMut {
-    let res = decode().unwrap_or_else(|_| Vec::new());
-    println!(" +++++ {:#?}", res);
+/// Fails every partition of a produced topic with `error`.
+fn produce_topic_error(topic: TopicProduceData, error: ResponseError) -> TopicProduceResponse {
+    let partition_responses = topic
+        .partition_data
+        .iter()
+        .map(|partition_data| {
+            PartitionProduceResponse::default()
+                .with_error_code(error.code())
+                .with_index(partition_data.index)
+                .with_base_offset(-1)
+                .with_log_append_time_ms(-1)
+                .with_log_start_offset(-1)
+        })
+        .collect();
+    TopicProduceResponse::default()
+        .with_name(topic.name)
+        .with_partition_responses(partition_responses)
+}
 
-    let grouped = group_topics(res);
+pub fn process_fetch(api_key : ApiKey, header: RequestHeader, req: FetchRequest) -> BytesMut {
+    let image = metadata::image();
 
     let mut response_buf = BytesMut::new();
 
@@ -165,47 +200,241 @@ pub fn process_fetch(api_key : ApiKey, header: RequestHeader, req: FetchRequest)
             api_key.response_header_version(header.request_api_version),
         );
 
-    let mut response_topics = Vec::with_capacity(req.topics.len());
-    for topic in req.topics {
+    let (context, session_topics) = match fetch_session::new_context(&req) {
+        Ok(resolved) => resolved,
+        Err(error) => {
+            let _ = FetchResponse::default()
+                .with_error_code(error.code())
+                .encode(&mut response_buf, header.request_api_version);
+            return response_buf;
+        }
+    };
+    let req = req.with_topics(session_topics);
+
+    // Park the request until enough data arrives, as Kafka's DelayedFetch does.
+    let mut watched: Vec<PartitionKey> = Vec::new();
+    for topic in &req.topics {
+        if let Some(tp) = find_fetch_topic(&image, topic, header.request_api_version) {
+            watched.extend(topic.partitions.iter().map(|p| (tp.topic.name.clone(), p.partition as u32)));
+        }
+    }
+    let deadline = Instant::now() + Duration::from_millis(req.max_wait_ms.max(0) as u64);
+    let response_topics = loop {
+        let watch = FETCH_PURGATORY.watch(&watched);
+        let (response_topics, fetched_bytes, has_error) = read_fetch_topics(&req, &image, header.request_api_version);
+        let satisfied = has_error || fetched_bytes >= req.min_bytes.max(0) as usize;
+        if satisfied || !watch.wait_until(deadline) {
+            break response_topics;
+        }
+    };
 
-        let requested_name = topic.topic_id.to_string();
-        //HashMap ?
-        let matched_topic = grouped.iter().find(|tp| tp.topic.uuid.to_string() == requested_name);
+    let _ = FetchResponse::default()
+        .with_session_id(context.session_id())
+        .with_responses(fetch_session::update_and_filter(context, response_topics))
+        .encode(&mut response_buf, header.request_api_version);
+    response_buf
+}
 
-        let response_topic = if let Some(tp) = matched_topic {
-            FetchableTopicResponse::default()
-                .with_topic(topic.topic)
-                .with_topic_id(topic.topic_id)
-                .with_partitions(vec![PartitionData::default()
-                    //.with_error_code(ResponseError::UnknownTopicId.code())
-                    .with_partition_index(0)
-                    .with_records(Some(read_records(tp.topic.name.as_str(),tp.partitions.first().unwrap().partition_id)))
-                ])
+fn find_fetch_topic<'a>(image: &'a MetadataImage, topic: &FetchTopic, version: i16) -> Option<&'a TopicWithPartitions> {
+    if version >= 13 {
+        image.topic_by_id(topic.topic_id)
+    } else {
+        image.topic_by_name(&topic.topic)
+    }
+}
+
+/// Reads every requested partition once. Returns the topic responses, the number
+/// of record bytes read and whether any partition failed.
+fn read_fetch_topics(req: &FetchRequest, image: &MetadataImage, version: i16) -> (Vec<FetchableTopicResponse>, usize, bool) {
+    // Bytes still allowed in this response by the request-level max_bytes.
+    let mut remaining_bytes = req.max_bytes.max(0) as usize;
+    let mut has_error = false;
+
+    let mut response_topics = Vec::with_capacity(req.topics.len());
+    for topic in &req.topics {
+        let matched_topic = find_fetch_topic(image, topic, version);
+        // Topic ids replaced names in v13, and each has its own "unknown" error.
+        let unknown_topic = if version >= 13 {
+            ResponseError::UnknownTopicId
         } else {
+            ResponseError::UnknownTopicOrPartition
+        };
+
+        let partitions = topic
+            .partitions
+            .iter()
+            .map(|requested| {
+                let Some(tp) = matched_topic else {
+                    has_error = true;
+                    return PartitionData::default()
+                        .with_partition_index(requested.partition)
+                        .with_high_watermark(-1)
+                        .with_error_code(unknown_topic.code());
+                };
+                if tp.partition(requested.partition as u32).is_none() {
+                    has_error = true;
+                    return PartitionData::default()
+                        .with_partition_index(requested.partition)
+                        .with_high_watermark(-1)
+                        .with_error_code(ResponseError::UnknownTopicOrPartition.code());
+                }
+
+                let limit = remaining_bytes.min(requested.partition_max_bytes.max(0) as usize);
+                // Like Kafka, the first partition with data may exceed the limits by
+                // one batch so a consumer never gets stuck behind a large batch.
+                let min_one_batch = remaining_bytes == req.max_bytes.max(0) as usize;
+                let partition_data = fetch_partition(&tp.topic.name, requested, limit, min_one_batch);
+                has_error |= partition_data.error_code != 0;
+                let fetched = partition_data.records.as_ref().map_or(0, |r| r.len());
+                remaining_bytes = remaining_bytes.saturating_sub(fetched);
+                partition_data
+            })
+            .collect();
+
+        response_topics.push(
             FetchableTopicResponse::default()
-                .with_topic(topic.topic)
+                .with_topic(topic.topic.clone())
                 .with_topic_id(topic.topic_id)
-                .with_partitions(vec![PartitionData::default()
-                    .with_error_code(ResponseError::UnknownTopicId.code())
-                    .with_partition_index(0)
-                ])
-        };
-        response_topics.push(response_topic);
+                .with_partitions(partitions),
+        );
     }
 
-    let _ = FetchResponse::default()
-        //.with_error_code(ResponseError::UnknownTopicId.code())
-        .with_responses(response_topics)
+    let fetched_bytes = req.max_bytes.max(0) as usize - remaining_bytes;
+    (response_topics, fetched_bytes, has_error)
+}
+
+fn fetch_partition(topic_name: &str, requested: &FetchPartition, max_bytes: usize, min_one_batch: bool) -> PartitionData {
+    let response = PartitionData::default().with_partition_index(requested.partition);
+    let partition_id = requested.partition as u32;
+
+    let log = match get_log(topic_name, partition_id) {
+        Ok(log) => log,
+        Err(e) => {
+            eprintln!("Failed to open log {}-{}: {}", topic_name, partition_id, e);
+            return response.with_error_code(ResponseError::KafkaStorageError.code());
+        }
+    };
+    let log = log.lock().unwrap();
+    let offsets = log.offsets();
+    // Single replica and no transactions: everything appended is committed and stable.
+    let response = response
+        .with_high_watermark(offsets.log_end_offset)
+        .with_last_stable_offset(offsets.log_end_offset)
+        .with_log_start_offset(offsets.log_start_offset);
+
+    if requested.fetch_offset < offsets.log_start_offset || requested.fetch_offset > offsets.log_end_offset {
+        return response.with_error_code(ResponseError::OffsetOutOfRange.code());
+    }
+
+    match log.read(requested.fetch_offset, max_bytes, min_one_batch) {
+        Ok(records) => response.with_records(Some(records)),
+        Err(e) => {
+            eprintln!("Failed to read log {}-{}: {}", topic_name, partition_id, e);
+            response.with_error_code(ResponseError::KafkaStorageError.code())
+        }
+    }
+}
+
+/// `ConfigSource.DYNAMIC_TOPIC_CONFIG` in describe-style config listings.
+const DYNAMIC_TOPIC_CONFIG: i8 = 1;
+
+const LATEST_TIMESTAMP: i64 = -1;
+const EARLIEST_TIMESTAMP: i64 = -2;
+const MAX_TIMESTAMP: i64 = -3;
+const EARLIEST_LOCAL_TIMESTAMP: i64 = -4;
+const LATEST_TIERED_TIMESTAMP: i64 = -5;
+
+pub fn process_list_offsets(api_key : ApiKey, header: RequestHeader, req: ListOffsetsRequest) -> BytesMut {
+    let image = metadata::image();
+
+    let mut response_buf = BytesMut::new();
+
+    let _ = ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(
+            &mut response_buf,
+            api_key.response_header_version(header.request_api_version),
+        );
+
+    let mut response_topics = Vec::with_capacity(req.topics.len());
+    for topic in req.topics {
+        let matched_topic = image.topic_by_name(&topic.name);
+
+        let partitions = topic
+            .partitions
+            .iter()
+            .map(|requested| {
+                let partition = matched_topic.and_then(|tp| tp.partition(requested.partition_index as u32));
+                let response = match (matched_topic, partition) {
+                    (Some(tp), Some(p)) => list_partition_offset(&tp.topic.name, p, requested.timestamp)
+                        .with_partition_index(requested.partition_index),
+                    _ => ListOffsetsPartitionResponse::default()
+                        .with_partition_index(requested.partition_index)
+                        .with_error_code(ResponseError::UnknownTopicOrPartition.code()),
+                };
+                // leader_epoch only exists from v4 on
+                if header.request_api_version < 4 {
+                    response.with_leader_epoch(-1)
+                } else {
+                    response
+                }
+            })
+            .collect();
+
+        response_topics.push(
+            ListOffsetsTopicResponse::default()
+                .with_name(topic.name)
+                .with_partitions(partitions),
+        );
+    }
+
+    let _ = ListOffsetsResponse::default()
+        .with_topics(response_topics)
         .encode(&mut response_buf, header.request_api_version);
+
     response_buf
 }
 
-pub fn process_describe_topic_partitions(api_key : ApiKey, header: RequestHeader, req: DescribeTopicPartitionsRequest) -> BytesMut {
+/// Resolves one ListOffsets lookup against a partition log. Timestamps that no
+/// record reaches yield offset -1, like Kafka does.
+fn list_partition_offset(topic_name: &str, partition: &PartitionRecord, timestamp: i64) -> ListOffsetsPartitionResponse {
+    let log = match get_log(topic_name, partition.partition_id) {
+        Ok(log) => log,
+        Err(e) => {
+            eprintln!("Failed to open log {}-{}: {}", topic_name, partition.partition_id, e);
+            return ListOffsetsPartitionResponse::default()
+                .with_error_code(ResponseError::KafkaStorageError.code());
+        }
+    };
+    let log = log.lock().unwrap();
+    let offsets = log.offsets();
+    let response = ListOffsetsPartitionResponse::default()
+        .with_leader_epoch(partition.leader_epoch);
 
-    let res = decode().unwrap();
-    println!(" +++++ {:#?}", res);
+    let found = match timestamp {
+        EARLIEST_TIMESTAMP | EARLIEST_LOCAL_TIMESTAMP => return response.with_offset(offsets.log_start_offset),
+        LATEST_TIMESTAMP | LATEST_TIERED_TIMESTAMP => return response.with_offset(offsets.log_end_offset),
+        MAX_TIMESTAMP => log.max_timestamp_offset(),
+        target => log.offset_for_timestamp(target),
+    };
 
-    let grouped = group_topics(res);
+    match found {
+        Ok(Some(found)) => response
+            .with_timestamp(found.timestamp)
+            .with_offset(found.offset)
+            .with_leader_epoch(found.leader_epoch),
+        Ok(None) => response.with_leader_epoch(-1),
+        Err(e) => {
+            eprintln!("Failed to search log {}-{}: {}", topic_name, partition.partition_id, e);
+            ListOffsetsPartitionResponse::default()
+                .with_error_code(ResponseError::KafkaStorageError.code())
+        }
+    }
+}
+
+pub fn process_describe_topic_partitions(api_key : ApiKey, header: RequestHeader, req: DescribeTopicPartitionsRequest) -> BytesMut {
+    let image = metadata::image();
 
     let mut response_buf = BytesMut::new();
 
@@ -220,8 +449,7 @@ pub fn process_describe_topic_partitions(api_key : ApiKey, header: RequestHeader
     let mut response_topics = Vec::with_capacity(req.topics.len());
 
     for topic in req.topics {
-        let requested_name = topic.name.to_string();
-        let matched_topic = grouped.iter().find(|tp| tp.topic.name == requested_name);
+        let matched_topic = image.topic_by_name(&topic.name);
 
         let response_topic = if let Some(tp) = matched_topic {
             let partitions_response = build_partiotions_response(tp.partitions.clone());
@@ -229,7 +457,7 @@ pub fn process_describe_topic_partitions(api_key : ApiKey, header: RequestHeader
                 .with_name(Some(TopicName::from(kafka_protocol::protocol::StrBytes::from(
                     tp.topic.name.clone(),
                 ))))
-                .with_is_internal(false)
+                .with_is_internal(controller::is_internal_topic(&tp.topic.name))
                 .with_topic_id(tp.topic.uuid)
                 .with_partitions(partitions_response)
         } else {
@@ -255,30 +483,1108 @@ pub fn process_describe_topic_partitions(api_key : ApiKey, header: RequestHeader
     response_buf
 }
 
-fn build_partiotions_response (mut partitions: Vec<Partition>) -> Vec<DescribeTopicPartitionsResponsePartition>{
+pub fn process_metadata(api_key : ApiKey, header: RequestHeader, req: MetadataRequest) -> BytesMut {
+    let image = metadata::image();
+    let broker_config = config::get();
+
+    let mut response_buf = BytesMut::new();
+
+    let _ = ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(
+            &mut response_buf,
+            api_key.response_header_version(header.request_api_version),
+        );
+
+    // A null topic list (or an empty one on v0) asks for every topic.
+    let requested = match req.topics {
+        Some(topics) if !(topics.is_empty() && header.request_api_version == 0) => Some(topics),
+        _ => None,
+    };
+
+    let auto_create = broker_config.auto_create_topics_enable && req.allow_auto_topic_creation;
+    let response_topics = match requested {
+        None => image.topics().map(build_metadata_topic).collect(),
+        Some(topics) => topics
+            .into_iter()
+            .map(|topic| {
+                let matched_topic = match &topic.name {
+                    Some(name) => image.topic_by_name(name),
+                    None => image.topic_by_id(topic.topic_id),
+                };
+                match matched_topic {
+                    Some(tp) => build_metadata_topic(tp),
+                    None => {
+                        let error = match &topic.name {
+                            Some(name) if auto_create => match auto_create_topic(name) {
+                                Ok(()) => {
+                                    if let Some(tp) = metadata::image().topic_by_name(name) {
+                                        return build_metadata_topic(tp);
+                                    }
+                                    ResponseError::UnknownTopicOrPartition
+                                }
+                                Err(e) => e.error,
+                            },
+                            Some(_) => ResponseError::UnknownTopicOrPartition,
+                            None => ResponseError::UnknownTopicId,
+                        };
+                        MetadataResponseTopic::default()
+                            .with_error_code(error.code())
+                            .with_name(topic.name)
+                            .with_topic_id(topic.topic_id)
+                    }
+                }
+            })
+            .collect(),
+    };
+
+    let _ = MetadataResponse::default()
+        .with_brokers(metadata_brokers(&image))
+        .with_cluster_id(read_cluster_id().map(StrBytes::from))
+        .with_controller_id(BrokerId::from(broker_config.node_id))
+        .with_topics(response_topics)
+        .encode(&mut response_buf, header.request_api_version);
+
+    response_buf
+}
+
+/// The live brokers registered in the metadata log, each at its first listener.
+/// This broker is always included, at the address it advertises.
+fn metadata_brokers(image: &MetadataImage) -> Vec<MetadataResponseBroker> {
+    let broker_config = config::get();
+    let mut brokers: Vec<MetadataResponseBroker> = image
+        .live_brokers()
+        .filter(|broker| broker.broker_id != broker_config.node_id)
+        .filter_map(|broker| {
+            let endpoint = broker.end_points.first()?;
+            Some(
+                MetadataResponseBroker::default()
+                    .with_node_id(BrokerId::from(broker.broker_id))
+                    .with_host(StrBytes::from(endpoint.host.clone()))
+                    .with_port(endpoint.port as i32)
+                    .with_rack(broker.rack.clone().map(StrBytes::from)),
+            )
+        })
+        .collect();
+    brokers.push(
+        MetadataResponseBroker::default()
+            .with_node_id(BrokerId::from(broker_config.node_id))
+            .with_host(StrBytes::from(broker_config.advertised_host.clone()))
+            .with_port(broker_config.advertised_port),
+    );
+    brokers.sort_by_key(|broker| broker.node_id);
+    brokers
+}
+
+fn build_metadata_topic(tp: &TopicWithPartitions) -> MetadataResponseTopic {
+    let mut partitions = tp.partitions.clone();
     partitions.sort_by_key(|p| p.partition_id);
-    partitions.into_iter().map(|p | {
+    let partitions = partitions
+        .into_iter()
+        .map(|p| {
+            MetadataResponsePartition::default()
+                .with_partition_index(p.partition_id as i32)
+                .with_leader_id(BrokerId::from(p.leader))
+                .with_leader_epoch(p.leader_epoch)
+                .with_replica_nodes(broker_ids(&p.replicas))
+                .with_isr_nodes(broker_ids(&p.isr))
+        })
+        .collect();
 
-        let replica_nodes = match p.rep_array_length {
-            0 => Vec::new(),
-            _ => vec![BrokerId::from(p.rep_array)],
-        };
+    MetadataResponseTopic::default()
+        .with_name(Some(TopicName::from(StrBytes::from(tp.topic.name.clone()))))
+        .with_topic_id(tp.topic.uuid)
+        .with_is_internal(controller::is_internal_topic(&tp.topic.name))
+        .with_partitions(partitions)
+}
 
-        let isr_nodes = match p.in_sync_rep_arr_length {
-            0 => Vec::new(),
-            _ => vec![BrokerId::from(p.in_sync_rep_arr)],
-        };
+fn build_partiotions_response (mut partitions: Vec<PartitionRecord>) -> Vec<DescribeTopicPartitionsResponsePartition>{
+    partitions.sort_by_key(|p| p.partition_id);
+    partitions.into_iter().map(|p | {
 
         DescribeTopicPartitionsResponsePartition::default()
             //.with_error_code(ResponseError::None.code())
             .with_partition_index(p.partition_id as i32)
             .with_leader_id(BrokerId::from(p.leader))
-            .with_leader_epoch(p.leader_eponch)
-            .with_replica_nodes(replica_nodes)
-            .with_isr_nodes(isr_nodes)
-            .with_eligible_leader_replicas(Some(Vec::new()))
-            .with_last_known_elr(Some(Vec::new()))
+            .with_leader_epoch(p.leader_epoch)
+            .with_replica_nodes(broker_ids(&p.replicas))
+            .with_isr_nodes(broker_ids(&p.isr))
+            .with_eligible_leader_replicas(Some(broker_ids(p.eligible_leader_replicas.as_deref().unwrap_or_default())))
+            .with_last_known_elr(Some(broker_ids(p.last_known_elr.as_deref().unwrap_or_default())))
             .with_offline_replicas(Vec::new())
             .with_unknown_tagged_fields(BTreeMap::new())
     }).collect()
-}
\ No newline at end of file
+}
+
+fn broker_ids(ids: &[i32]) -> Vec<BrokerId> {
+    ids.iter().copied().map(BrokerId::from).collect()
+}
+
+pub fn process_create_topics(api_key : ApiKey, header: RequestHeader, req: CreateTopicsRequest) -> BytesMut {
+    let version = header.request_api_version;
+    let mut response_buf = BytesMut::new();
+
+    let _ = ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(
+            &mut response_buf,
+            api_key.response_header_version(version),
+        );
+
+    let written = metadata::write(|image| controller::create_topics(image, &req.topics, req.validate_only));
+    let outcomes = written.unwrap_or_else(|e| {
+        eprintln!("Failed to write topic creations: {}", e);
+        req.topics
+            .iter()
+            .map(|_| Err(ApiError::new(ResponseError::UnknownServerError, "Failed to write the cluster metadata")))
+            .collect()
+    });
+
+    let response_topics = req
+        .topics
+        .iter()
+        .zip(outcomes)
+        .map(|(topic, outcome)| {
+            let result = CreatableTopicResult::default()
+                .with_name(topic.name.clone())
+                .with_error_message(None);
+            let created = match outcome {
+                Ok(created) => created,
+                Err(e) => {
+                    return result
+                        .with_error_code(e.error.code())
+                        .with_error_message(Some(StrBytes::from(e.message)));
+                }
+            };
+
+            if !req.validate_only {
+                create_partition_logs(topic.name.as_str(), created.num_partitions);
+                println!("Created topic {} with id {}", topic.name.as_str(), created.topic_id);
+            }
+
+            // Partition counts, replication factor and configs are returned from v5,
+            // the topic id from v7.
+            let mut result = result;
+            if version >= 5 {
+                let configs = topic
+                    .configs
+                    .iter()
+                    .map(|config| {
+                        CreatableTopicConfigs::default()
+                            .with_name(config.name.clone())
+                            .with_value(config.value.clone())
+                            .with_config_source(DYNAMIC_TOPIC_CONFIG)
+                    })
+                    .collect();
+                result = result
+                    .with_num_partitions(created.num_partitions)
+                    .with_replication_factor(created.replication_factor)
+                    .with_configs(Some(configs));
+            }
+            if version >= 7 && !req.validate_only {
+                result = result.with_topic_id(created.topic_id);
+            }
+            result
+        })
+        .collect();
+
+    let _ = CreateTopicsResponse::default()
+        .with_topics(response_topics)
+        .encode(&mut response_buf, version);
+
+    response_buf
+}
+
+/// Creates `name` with the broker's default partition count and replication factor
+/// for `auto.create.topics.enable`, through the same controller path as CreateTopics.
+/// A topic that appeared concurrently counts as created.
+fn auto_create_topic(name: &str) -> Result<(), ApiError> {
+    let topic = CreatableTopic::default()
+        .with_name(TopicName::from(StrBytes::from(name.to_string())))
+        .with_num_partitions(-1)
+        .with_replication_factor(-1);
+    let created = metadata::write(|image| {
+        if image.topic_by_name(name).is_some() {
+            return (Vec::new(), Ok(None));
+        }
+        match controller::create_topic(image, &topic, &[]) {
+            Ok(mut created) => (std::mem::take(&mut created.records), Ok(Some(created))),
+            Err(e) => (Vec::new(), Err(e)),
+        }
+    })
+    .unwrap_or_else(|e| {
+        eprintln!("Failed to write topic creation: {}", e);
+        Err(ApiError::new(ResponseError::UnknownServerError, "Failed to write the cluster metadata"))
+    })?;
+
+    if let Some(created) = created {
+        create_partition_logs(name, created.num_partitions);
+        println!("Auto-created topic {} with id {}", name, created.topic_id);
+    }
+    Ok(())
+}
+
+fn create_partition_logs(topic_name: &str, num_partitions: i32) {
+    for partition_id in 0..num_partitions as u32 {
+        if let Err(e) = get_log(topic_name, partition_id) {
+            eprintln!("Failed to create log {}-{}: {}", topic_name, partition_id, e);
+        }
+    }
+}
+
+pub fn process_delete_topics(api_key : ApiKey, header: RequestHeader, req: DeleteTopicsRequest) -> BytesMut {
+    let version = header.request_api_version;
+    let mut response_buf = BytesMut::new();
+
+    let _ = ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(
+            &mut response_buf,
+            api_key.response_header_version(version),
+        );
+
+    // Up to v5 topics are listed by name only; v6 lists each by name or by id.
+    let requested: Vec<(Option<TopicName>, uuid::Uuid)> = if version >= 6 {
+        req.topics.iter().map(|topic| (topic.name.clone(), topic.topic_id)).collect()
+    } else {
+        req.topic_names.iter().map(|name| (Some(name.clone()), uuid::Uuid::nil())).collect()
+    };
+    let written = metadata::write(|image| controller::delete_topics(image, &requested));
+    let outcomes = written.unwrap_or_else(|e| {
+        eprintln!("Failed to write topic deletions: {}", e);
+        requested
+            .iter()
+            .map(|_| Err(ApiError::new(ResponseError::UnknownServerError, "Failed to write the cluster metadata")))
+            .collect()
+    });
+
+    let responses = requested
+        .into_iter()
+        .zip(outcomes)
+        .map(|((name, topic_id), outcome)| {
+            let result = DeletableTopicResult::default().with_name(name);
+            let result = if version >= 6 { result.with_topic_id(topic_id) } else { result };
+            match outcome {
+                Ok(deleted) => {
+                    for partition_id in deleted.partitions {
+                        if let Err(e) = delete_log(&deleted.name, partition_id, deleted.topic_id) {
+                            eprintln!("Failed to delete log {}-{}: {}", deleted.name, partition_id, e);
+                        }
+                    }
+                    println!("Deleted topic {} with id {}", deleted.name, deleted.topic_id);
+                    let result = result.with_name(Some(TopicName::from(StrBytes::from(deleted.name))));
+                    if version >= 6 { result.with_topic_id(deleted.topic_id) } else { result }
+                }
+                Err(e) => {
+                    let result = result.with_error_code(e.error.code());
+                    // error_message exists from v5
+                    if version >= 5 {
+                        result.with_error_message(Some(StrBytes::from(e.message)))
+                    } else {
+                        result
+                    }
+                }
+            }
+        })
+        .collect();
+
+    let _ = DeleteTopicsResponse::default()
+        .with_responses(responses)
+        .encode(&mut response_buf, version);
+
+    response_buf
+}
+
+pub fn process_create_partitions(api_key : ApiKey, header: RequestHeader, req: CreatePartitionsRequest) -> BytesMut {
+    let mut response_buf = BytesMut::new();
+
+    let _ = ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(
+            &mut response_buf,
+            api_key.response_header_version(header.request_api_version),
+        );
+
+    let mut name_counts: HashMap<&str, usize> = HashMap::new();
+    for topic in &req.topics {
+        *name_counts.entry(topic.name.as_str()).or_default() += 1;
+    }
+
+    let written = metadata::write(|image| {
+        let mut records = Vec::new();
+        let outcomes: Vec<Result<Vec<u32>, ApiError>> = req
+            .topics
+            .iter()
+            .map(|topic| {
+                if name_counts[topic.name.as_str()] > 1 {
+                    return Err(ApiError::new(ResponseError::InvalidRequest, "Duplicate topic name."));
+                }
+                let partitions = controller::create_partitions(image, topic)?;
+                let partition_ids = partitions.iter().map(|p| p.partition_id).collect();
+                if !req.validate_only {
+                    records.extend(partitions.into_iter().map(RecordType::PartitionValue));
+                }
+                Ok(partition_ids)
+            })
+            .collect();
+        (records, outcomes)
+    });
+    let outcomes = written.unwrap_or_else(|e| {
+        eprintln!("Failed to write partition creations: {}", e);
+        req.topics
+            .iter()
+            .map(|_| Err(ApiError::new(ResponseError::UnknownServerError, "Failed to write the cluster metadata")))
+            .collect()
+    });
+
+    let results = req
+        .topics
+        .iter()
+        .zip(outcomes)
+        .map(|(topic, outcome)| {
+            let result = CreatePartitionsTopicResult::default().with_name(topic.name.clone());
+            match outcome {
+                Ok(partition_ids) => {
+                    if !req.validate_only {
+                        for partition_id in partition_ids {
+                            if let Err(e) = get_log(topic.name.as_str(), partition_id) {
+                                eprintln!("Failed to create log {}-{}: {}", topic.name.as_str(), partition_id, e);
+                            }
+                        }
+                        println!("Grew topic {} to {} partitions", topic.name.as_str(), topic.count);
+                    }
+                    result
+                }
+                Err(e) => result
+                    .with_error_code(e.error.code())
+                    .with_error_message(Some(StrBytes::from(e.message))),
+            }
+        })
+        .collect();
+
+    let _ = CreatePartitionsResponse::default()
+        .with_results(results)
+        .encode(&mut response_buf, header.request_api_version);
+
+    response_buf
+}
+
+/// FindCoordinator key types we coordinate; this broker is the coordinator for every key.
+const GROUP_KEY_TYPE: i8 = 0;
+const TRANSACTION_KEY_TYPE: i8 = 1;
+
+pub fn process_find_coordinator(api_key : ApiKey, header: RequestHeader, req: FindCoordinatorRequest) -> BytesMut {
+    let version = header.request_api_version;
+    let broker_config = config::get();
+    let mut response_buf = BytesMut::new();
+
+    let _ = ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(
+            &mut response_buf,
+            api_key.response_header_version(version),
+        );
+
+    let error = match req.key_type {
+        GROUP_KEY_TYPE | TRANSACTION_KEY_TYPE => None,
+        _ => Some(ResponseError::InvalidRequest),
+    };
+
+    // v4+ batches the keys into coordinators; earlier versions look up a single key.
+    let response = if version >= 4 {
+        let coordinators = req
+            .coordinator_keys
+            .into_iter()
+            .map(|key| {
+                let coordinator = Coordinator::default().with_key(key);
+                match error {
+                    Some(e) => coordinator
+                        .with_node_id(BrokerId::from(-1))
+                        .with_port(-1)
+                        .with_error_code(e.code())
+                        .with_error_message(Some(StrBytes::from_static_str("Unsupported coordinator key type"))),
+                    None => coordinator
+                        .with_node_id(BrokerId::from(broker_config.node_id))
+                        .with_host(StrBytes::from(broker_config.advertised_host.clone()))
+                        .with_port(broker_config.advertised_port)
+                        .with_error_message(None),
+                }
+            })
+            .collect();
+        FindCoordinatorResponse::default().with_coordinators(coordinators)
+    } else {
+        let response = match error {
+            Some(e) => FindCoordinatorResponse::default()
+                .with_error_code(e.code())
+                .with_node_id(BrokerId::from(-1))
+                .with_port(-1),
+            None => FindCoordinatorResponse::default()
+                .with_node_id(BrokerId::from(broker_config.node_id))
+                .with_host(StrBytes::from(broker_config.advertised_host.clone()))
+                .with_port(broker_config.advertised_port),
+        };
+        if version >= 1 {
+            response.with_error_message(error.map(|_| StrBytes::from_static_str("Unsupported coordinator key type")))
+        } else {
+            response
+        }
+    };
+
+    let _ = response.encode(&mut response_buf, version);
+    response_buf
+}
+
+pub fn process_join_group(api_key : ApiKey, header: RequestHeader, req: JoinGroupRequest) -> BytesMut {
+    let version = header.request_api_version;
+    let mut response_buf = BytesMut::new();
+
+    let _ = ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(
+            &mut response_buf,
+            api_key.response_header_version(version),
+        );
+
+    let client_id = header.client_id.as_ref().map_or("", |id| id.as_str());
+    let result = group_coordinator::join_group(&req, version, client_id, &crate::client_host());
+
+    let members = result
+        .members
+        .into_iter()
+        .map(|member| {
+            JoinGroupResponseMember::default()
+                .with_member_id(StrBytes::from(member.member_id))
+                .with_group_instance_id(if version >= 5 { member.group_instance_id.map(StrBytes::from) } else { None })
+                .with_metadata(member.metadata)
+        })
+        .collect();
+
+    // The protocol name is only nullable from v7, which also adds the protocol type.
+    let protocol_name = match result.protocol_name {
+        Some(name) => Some(StrBytes::from(name)),
+        None if version < 7 => Some(StrBytes::default()),
+        None => None,
+    };
+    let mut response = JoinGroupResponse::default()
+        .with_error_code(result.error.map_or(0, |e| e.code()))
+        .with_generation_id(result.generation_id)
+        .with_protocol_name(protocol_name)
+        .with_leader(StrBytes::from(result.leader_id))
+        .with_member_id(StrBytes::from(result.member_id))
+        .with_members(members);
+    if version >= 7 {
+        response = response.with_protocol_type(result.protocol_type.map(StrBytes::from));
+    }
+    if version >= 9 {
+        response = response.with_skip_assignment(result.skip_assignment);
+    }
+
+    let _ = response.encode(&mut response_buf, version);
+    response_buf
+}
+
+pub fn process_heartbeat(api_key : ApiKey, header: RequestHeader, req: HeartbeatRequest) -> BytesMut {
+    let mut response_buf = BytesMut::new();
+
+    let _ = ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(
+            &mut response_buf,
+            api_key.response_header_version(header.request_api_version),
+        );
+
+    let error_code = match group_coordinator::heartbeat(&req) {
+        Ok(()) => 0,
+        Err(e) => e.code(),
+    };
+
+    let _ = HeartbeatResponse::default()
+        .with_error_code(error_code)
+        .encode(&mut response_buf, header.request_api_version);
+    response_buf
+}
+
+pub fn process_leave_group(api_key : ApiKey, header: RequestHeader, req: LeaveGroupRequest) -> BytesMut {
+    let version = header.request_api_version;
+    let mut response_buf = BytesMut::new();
+
+    let _ = ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(
+            &mut response_buf,
+            api_key.response_header_version(version),
+        );
+
+    // v3+ removes a batch of members; earlier versions name a single member.
+    let leaving = if version >= 3 {
+        req.members
+    } else {
+        vec![MemberIdentity::default().with_member_id(req.member_id)]
+    };
+    let results = group_coordinator::leave_group(req.group_id.as_str(), &leaving);
+
+    let response = if version >= 3 {
+        let members = leaving
+            .into_iter()
+            .zip(results)
+            .map(|(identity, result)| {
+                MemberResponse::default()
+                    .with_member_id(identity.member_id)
+                    .with_group_instance_id(identity.group_instance_id)
+                    .with_error_code(result.err().map_or(0, |e| e.code()))
+            })
+            .collect();
+        LeaveGroupResponse::default().with_members(members)
+    } else {
+        let error_code = results.into_iter().find_map(Result::err).map_or(0, |e| e.code());
+        LeaveGroupResponse::default().with_error_code(error_code)
+    };
+
+    let _ = response.encode(&mut response_buf, version);
+    response_buf
+}
+
+pub fn process_sync_group(api_key : ApiKey, header: RequestHeader, req: SyncGroupRequest) -> BytesMut {
+    let version = header.request_api_version;
+    let mut response_buf = BytesMut::new();
+
+    let _ = ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(
+            &mut response_buf,
+            api_key.response_header_version(version),
+        );
+
+    let response = match group_coordinator::sync_group(&req) {
+        Ok(result) => {
+            let response = SyncGroupResponse::default().with_assignment(result.assignment);
+            // The protocol type and name are returned from v5.
+            if version >= 5 {
+                response
+                    .with_protocol_type(result.protocol_type.map(StrBytes::from))
+                    .with_protocol_name(result.protocol_name.map(StrBytes::from))
+            } else {
+                response
+            }
+        }
+        Err(e) => SyncGroupResponse::default().with_error_code(e.code()),
+    };
+
+    let _ = response.encode(&mut response_buf, version);
+    response_buf
+}
+
+pub fn process_offset_commit(api_key : ApiKey, header: RequestHeader, req: OffsetCommitRequest) -> BytesMut {
+    let version = header.request_api_version;
+    let image = metadata::image();
+    let mut response_buf = BytesMut::new();
+
+    let _ = ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(
+            &mut response_buf,
+            api_key.response_header_version(version),
+        );
+
+    let group_id = req.group_id.as_str();
+    let membership = group_coordinator::validate_offset_commit(
+        group_id,
+        req.member_id.as_str(),
+        req.group_instance_id.as_deref(),
+        req.generation_id_or_member_epoch,
+        version,
+    );
+
+    let commit_timestamp = now_ms();
+    let max_metadata_bytes = config::get().offset_metadata_max_bytes;
+    let mut accepted = Vec::new();
+    let mut errors: Vec<Vec<Option<ResponseError>>> = req
+        .topics
+        .iter()
+        .map(|topic| {
+            let known_topic = image.topic_by_name(topic.name.as_str());
+            topic
+                .partitions
+                .iter()
+                .map(|partition| {
+                    if let Err(e) = membership {
+                        return Some(e);
+                    }
+                    let metadata = partition.committed_metadata.as_deref().unwrap_or_default();
+                    if known_topic.and_then(|tp| tp.partition(partition.partition_index as u32)).is_none() {
+                        Some(ResponseError::UnknownTopicOrPartition)
+                    } else if metadata.len() > max_metadata_bytes {
+                        Some(ResponseError::OffsetMetadataTooLarge)
+                    } else {
+                        accepted.push((
+                            (topic.name.to_string(), partition.partition_index),
+                            CommittedOffset {
+                                offset: partition.committed_offset,
+                                leader_epoch: partition.committed_leader_epoch,
+                                metadata: metadata.to_string(),
+                                commit_timestamp,
+                            },
+                        ));
+                        None
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    if let Err(e) = consumer_offsets::commit(group_id, accepted) {
+        eprintln!("Failed to commit offsets of group {}: {}", group_id, e);
+        for error in errors.iter_mut().flatten() {
+            error.get_or_insert(ResponseError::UnknownServerError);
+        }
+    }
+
+    let response_topics = req
+        .topics
+        .into_iter()
+        .zip(errors)
+        .map(|(topic, errors)| {
+            let partitions = topic
+                .partitions
+                .iter()
+                .zip(errors)
+                .map(|(partition, error)| {
+                    OffsetCommitResponsePartition::default()
+                        .with_partition_index(partition.partition_index)
+                        .with_error_code(error.map_or(0, |e| e.code()))
+                })
+                .collect();
+            OffsetCommitResponseTopic::default()
+                .with_name(topic.name)
+                .with_partitions(partitions)
+        })
+        .collect();
+
+    let _ = OffsetCommitResponse::default()
+        .with_topics(response_topics)
+        .encode(&mut response_buf, version);
+    response_buf
+}
+
+/// A topic's partitions with their committed offset, if any.
+type TopicOffsets = (TopicName, Vec<(i32, Option<CommittedOffset>)>);
+
+/// A group's committed offsets for the requested partitions, or for every
+/// partition it has committed when `topics` is null. Partitions without a
+/// commit come back with no offset.
+fn committed_offsets(group_id: &str, topics: Option<Vec<(TopicName, Vec<i32>)>>) -> Vec<TopicOffsets> {
+    let committed = consumer_offsets::fetch(group_id);
+    match topics {
+        Some(topics) => topics
+            .into_iter()
+            .map(|(name, partitions)| {
+                let partitions = partitions
+                    .into_iter()
+                    .map(|partition| (partition, committed.get(&(name.to_string(), partition)).cloned()))
+                    .collect();
+                (name, partitions)
+            })
+            .collect(),
+        None => {
+            let mut topics: Vec<TopicOffsets> = Vec::new();
+            for ((topic, partition), offset) in committed {
+                match topics.last_mut() {
+                    Some((name, partitions)) if name.as_str() == topic => partitions.push((partition, Some(offset))),
+                    _ => topics.push((TopicName::from(StrBytes::from(topic)), vec![(partition, Some(offset))])),
+                }
+            }
+            topics
+        }
+    }
+}
+
+pub fn process_offset_fetch(api_key : ApiKey, header: RequestHeader, req: OffsetFetchRequest) -> BytesMut {
+    let version = header.request_api_version;
+    let mut response_buf = BytesMut::new();
+
+    let _ = ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(
+            &mut response_buf,
+            api_key.response_header_version(version),
+        );
+
+    // The leader epoch is returned from v5.
+    let leader_epoch = |offset: &Option<CommittedOffset>| match offset {
+        Some(offset) if version >= 5 => offset.leader_epoch,
+        _ => -1,
+    };
+
+    // v8+ fetches for a batch of groups; earlier versions for a single group.
+    let response = if version >= 8 {
+        let groups = req
+            .groups
+            .into_iter()
+            .map(|group| {
+                // v9+ consumers fetch as members of a consumer protocol group.
+                if version >= 9 {
+                    let member_id = group.member_id.as_ref().map(|id| id.as_str());
+                    if let Err(e) = consumer_group::validate_offset_fetch(group.group_id.as_str(), member_id, group.member_epoch) {
+                        return OffsetFetchResponseGroup::default()
+                            .with_group_id(group.group_id)
+                            .with_error_code(e.code());
+                    }
+                }
+                let requested = group
+                    .topics
+                    .map(|topics| topics.into_iter().map(|t| (t.name, t.partition_indexes)).collect());
+                let topics = committed_offsets(group.group_id.as_str(), requested)
+                    .into_iter()
+                    .map(|(name, partitions)| {
+                        let partitions = partitions
+                            .into_iter()
+                            .map(|(partition, offset)| {
+                                OffsetFetchResponsePartitions::default()
+                                    .with_partition_index(partition)
+                                    .with_committed_offset(offset.as_ref().map_or(-1, |o| o.offset))
+                                    .with_committed_leader_epoch(leader_epoch(&offset))
+                                    .with_metadata(Some(StrBytes::from(offset.map(|o| o.metadata).unwrap_or_default())))
+                            })
+                            .collect();
+                        OffsetFetchResponseTopics::default()
+                            .with_name(name)
+                            .with_partitions(partitions)
+                    })
+                    .collect();
+                OffsetFetchResponseGroup::default()
+                    .with_group_id(group.group_id)
+                    .with_topics(topics)
+            })
+            .collect();
+        OffsetFetchResponse::default().with_groups(groups)
+    } else {
+        let requested = req
+            .topics
+            .map(|topics| topics.into_iter().map(|t| (t.name, t.partition_indexes)).collect());
+        let topics = committed_offsets(req.group_id.as_str(), requested)
+            .into_iter()
+            .map(|(name, partitions)| {
+                let partitions = partitions
+                    .into_iter()
+                    .map(|(partition, offset)| {
+                        OffsetFetchResponsePartition::default()
+                            .with_partition_index(partition)
+                            .with_committed_offset(offset.as_ref().map_or(-1, |o| o.offset))
+                            .with_committed_leader_epoch(leader_epoch(&offset))
+                            .with_metadata(Some(StrBytes::from(offset.map(|o| o.metadata).unwrap_or_default())))
+                    })
+                    .collect();
+                OffsetFetchResponseTopic::default()
+                    .with_name(name)
+                    .with_partitions(partitions)
+            })
+            .collect();
+        OffsetFetchResponse::default().with_topics(topics)
+    };
+
+    let _ = response.encode(&mut response_buf, version);
+    response_buf
+}
+
+pub fn process_describe_groups(api_key : ApiKey, header: RequestHeader, req: DescribeGroupsRequest) -> BytesMut {
+    let version = header.request_api_version;
+    let mut response_buf = BytesMut::new();
+
+    let _ = ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(
+            &mut response_buf,
+            api_key.response_header_version(version),
+        );
+
+    let groups = req
+        .groups
+        .into_iter()
+        .map(|group_id| {
+            let Some(description) = group_coordinator::describe_group(group_id.as_str()) else {
+                // Unknown groups are described as dead; v6 also reports them as not found.
+                let described = DescribedGroup::default()
+                    .with_group_state(StrBytes::from_static_str(group_coordinator::DEAD_STATE));
+                return if version >= 6 {
+                    described
+                        .with_error_code(ResponseError::GroupIdNotFound.code())
+                        .with_error_message(Some(StrBytes::from(format!("Group {} not found.", group_id.as_str()))))
+                        .with_group_id(group_id)
+                } else {
+                    described.with_group_id(group_id)
+                };
+            };
+            let members = description
+                .members
+                .into_iter()
+                .map(|member| {
+                    DescribedGroupMember::default()
+                        .with_member_id(StrBytes::from(member.member_id))
+                        .with_group_instance_id(if version >= 4 { member.group_instance_id.map(StrBytes::from) } else { None })
+                        .with_client_id(StrBytes::from(member.client_id))
+                        .with_client_host(StrBytes::from(member.client_host))
+                        .with_member_metadata(member.metadata)
+                        .with_member_assignment(member.assignment)
+                })
+                .collect();
+            DescribedGroup::default()
+                .with_group_id(group_id)
+                .with_group_state(StrBytes::from_static_str(description.state))
+                .with_protocol_type(StrBytes::from(description.protocol_type))
+                .with_protocol_data(StrBytes::from(description.protocol_name))
+                .with_members(members)
+        })
+        .collect();
+
+    let _ = DescribeGroupsResponse::default()
+        .with_groups(groups)
+        .encode(&mut response_buf, version);
+    response_buf
+}
+
+pub fn process_list_groups(api_key : ApiKey, header: RequestHeader, req: ListGroupsRequest) -> BytesMut {
+    let version = header.request_api_version;
+    let mut response_buf = BytesMut::new();
+
+    let _ = ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(
+            &mut response_buf,
+            api_key.response_header_version(version),
+        );
+
+    // Both filters match case-insensitively.
+    let matches = |filter: &[StrBytes], value: &str| {
+        filter.is_empty() || filter.iter().any(|f| f.as_str().eq_ignore_ascii_case(value))
+    };
+    let groups = group_coordinator::list_groups()
+        .into_iter()
+        .filter(|group| matches(&req.states_filter, group.state) && matches(&req.types_filter, group.group_type))
+        .map(|group| {
+            let listed = ListedGroup::default()
+                .with_group_id(StrBytes::from(group.group_id).into())
+                .with_protocol_type(StrBytes::from(group.protocol_type));
+            // The state is returned from v4 and the group type from v5.
+            let listed = if version >= 4 { listed.with_group_state(StrBytes::from_static_str(group.state)) } else { listed };
+            if version >= 5 { listed.with_group_type(StrBytes::from_static_str(group.group_type)) } else { listed }
+        })
+        .collect();
+
+    let _ = ListGroupsResponse::default()
+        .with_groups(groups)
+        .encode(&mut response_buf, version);
+    response_buf
+}
+
+pub fn process_delete_groups(api_key : ApiKey, header: RequestHeader, req: DeleteGroupsRequest) -> BytesMut {
+    let version = header.request_api_version;
+    let mut response_buf = BytesMut::new();
+
+    let _ = ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(
+            &mut response_buf,
+            api_key.response_header_version(version),
+        );
+
+    let mut seen = HashSet::new();
+    let results = req
+        .groups_names
+        .into_iter()
+        .filter(|group_id| seen.insert(group_id.clone()))
+        .map(|group_id| {
+            let error_code = match group_coordinator::delete_group(group_id.as_str()) {
+                Ok(()) => 0,
+                Err(e) => e.code(),
+            };
+            DeletableGroupResult::default()
+                .with_group_id(group_id)
+                .with_error_code(error_code)
+        })
+        .collect();
+
+    let _ = DeleteGroupsResponse::default()
+        .with_results(results)
+        .encode(&mut response_buf, version);
+    response_buf
+}
+
+pub fn process_offset_delete(api_key : ApiKey, header: RequestHeader, req: OffsetDeleteRequest) -> BytesMut {
+    let version = header.request_api_version;
+    let mut response_buf = BytesMut::new();
+
+    let _ = ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(
+            &mut response_buf,
+            api_key.response_header_version(version),
+        );
+
+    let partitions: Vec<(String, i32)> = req
+        .topics
+        .iter()
+        .flat_map(|topic| topic.partitions.iter().map(|p| (topic.name.to_string(), p.partition_index)))
+        .collect();
+
+    let response = match group_coordinator::delete_offsets(req.group_id.as_str(), &partitions) {
+        Ok(errors) => {
+            let mut errors = errors.into_iter();
+            let topics = req
+                .topics
+                .into_iter()
+                .map(|topic| {
+                    let partitions = topic
+                        .partitions
+                        .iter()
+                        .zip(errors.by_ref())
+                        .map(|(partition, error)| {
+                            OffsetDeleteResponsePartition::default()
+                                .with_partition_index(partition.partition_index)
+                                .with_error_code(error.map_or(0, |e| e.code()))
+                        })
+                        .collect();
+                    OffsetDeleteResponseTopic::default()
+                        .with_name(topic.name)
+                        .with_partitions(partitions)
+                })
+                .collect();
+            OffsetDeleteResponse::default().with_topics(topics)
+        }
+        Err(e) => OffsetDeleteResponse::default().with_error_code(e.code()),
+    };
+
+    let _ = response.encode(&mut response_buf, version);
+    response_buf
+}
+
+pub fn process_consumer_group_heartbeat(api_key : ApiKey, header: RequestHeader, req: ConsumerGroupHeartbeatRequest) -> BytesMut {
+    let version = header.request_api_version;
+    let mut response_buf = BytesMut::new();
+
+    let _ = ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(
+            &mut response_buf,
+            api_key.response_header_version(version),
+        );
+
+    let client_id = header.client_id.as_ref().map_or("", |id| id.as_str());
+    let response = match consumer_group::heartbeat(&req, version, client_id, &crate::client_host()) {
+        Ok(result) => {
+            let assignment = result.assignment.map(|assignment| {
+                let topic_partitions = assignment
+                    .into_iter()
+                    .map(|(topic_id, partitions)| {
+                        consumer_group_heartbeat_response::TopicPartitions::default()
+                            .with_topic_id(topic_id)
+                            .with_partitions(partitions.into_iter().collect())
+                    })
+                    .collect();
+                consumer_group_heartbeat_response::Assignment::default().with_topic_partitions(topic_partitions)
+            });
+            ConsumerGroupHeartbeatResponse::default()
+                .with_member_id(Some(StrBytes::from(result.member_id)))
+                .with_member_epoch(result.member_epoch)
+                .with_heartbeat_interval_ms(result.heartbeat_interval_ms)
+                .with_assignment(assignment)
+        }
+        Err(ApiError { error, message }) => ConsumerGroupHeartbeatResponse::default()
+            .with_error_code(error.code())
+            .with_error_message(Some(StrBytes::from(message))),
+    };
+
+    let _ = response.encode(&mut response_buf, version);
+    response_buf
+}
+
+pub fn process_consumer_group_describe(api_key : ApiKey, header: RequestHeader, req: ConsumerGroupDescribeRequest) -> BytesMut {
+    let version = header.request_api_version;
+    let image = metadata::image();
+    let mut response_buf = BytesMut::new();
+
+    let _ = ResponseHeader::default()
+        .with_correlation_id(header.correlation_id)
+        .with_unknown_tagged_fields(BTreeMap::new())
+        .encode(
+            &mut response_buf,
+            api_key.response_header_version(version),
+        );
+
+    // Partitions of topics deleted since they were assigned are left out.
+    let to_assignment = |assignment: consumer_group::Assignment| {
+        let topic_partitions = assignment
+            .into_iter()
+            .filter_map(|(topic_id, partitions)| {
+                let topic = image.topic_by_id(topic_id)?;
+                Some(
+                    consumer_group_describe_response::TopicPartitions::default()
+                        .with_topic_id(topic_id)
+                        .with_topic_name(StrBytes::from(topic.topic.name.clone()).into())
+                        .with_partitions(partitions.into_iter().collect()),
+                )
+            })
+            .collect();
+        consumer_group_describe_response::Assignment::default().with_topic_partitions(topic_partitions)
+    };
+    let groups = req
+        .group_ids
+        .into_iter()
+        .map(|group_id| {
+            let Some(description) = consumer_group::describe_group(group_id.as_str()) else {
+                return consumer_group_describe_response::DescribedGroup::default()
+                    .with_error_code(ResponseError::GroupIdNotFound.code())
+                    .with_error_message(Some(StrBytes::from(format!("Group {} not found.", group_id.as_str()))))
+                    .with_group_id(group_id)
+                    .with_group_state(StrBytes::from_static_str(group_coordinator::DEAD_STATE));
+            };
+            let members = description
+                .members
+                .into_iter()
+                .map(|member| {
+                    let described = consumer_group_describe_response::Member::default()
+                        .with_member_id(StrBytes::from(member.member_id))
+                        .with_instance_id(member.instance_id.map(StrBytes::from))
+                        .with_rack_id(member.rack_id.map(StrBytes::from))
+                        .with_member_epoch(member.member_epoch)
+                        .with_client_id(StrBytes::from(member.client_id))
+                        .with_client_host(StrBytes::from(member.client_host))
+                        .with_subscribed_topic_names(
+                            member.subscribed_topic_names.into_iter().map(|name| StrBytes::from(name).into()).collect(),
+                        )
+                        .with_assignment(to_assignment(member.assignment))
+                        .with_target_assignment(to_assignment(member.target_assignment));
+                    // The member type is returned from v1; every member here is a consumer member.
+                    if version >= 1 { described.with_member_type(1) } else { described }
+                })
+                .collect();
+            consumer_group_describe_response::DescribedGroup::default()
+                .with_group_id(group_id)
+                .with_group_state(StrBytes::from_static_str(description.state))
+                .with_group_epoch(description.group_epoch)
+                .with_assignment_epoch(description.assignment_epoch)
+                .with_assignor_name(StrBytes::from(description.assignor_name))
+                .with_members(members)
+        })
+        .collect();
+
+    let _ = ConsumerGroupDescribeResponse::default()
+        .with_groups(groups)
+        .encode(&mut response_buf, version);
+    response_buf
+}
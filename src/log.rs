@@ -0,0 +1,777 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::bail;
+use bytes::{Bytes, BytesMut};
+use kafka_protocol::records::Record;
+use uuid::Uuid;
+use crate::{config, metadata};
+use crate::utils::{scan_batches, BatchHeader};
+
+const LOG_SUFFIX: &str = ".log";
+const INDEX_SUFFIX: &str = ".index";
+const TIME_INDEX_SUFFIX: &str = ".timeindex";
+const DELETE_DIR_SUFFIX: &str = "-delete";
+const INDEX_ENTRY_SIZE: usize = 8;
+const TIME_INDEX_ENTRY_SIZE: usize = 12;
+
+#[derive(Debug, Clone, Copy)]
+pub struct LogConfig {
+    pub segment_bytes: u64,
+    pub segment_ms: i64,
+    pub index_interval_bytes: usize,
+}
+
+impl LogConfig {
+    pub fn from_broker() -> LogConfig {
+        let broker_config = config::get();
+        LogConfig {
+            segment_bytes: broker_config.log_segment_bytes,
+            segment_ms: broker_config.log_roll_ms,
+            index_interval_bytes: broker_config.log_index_interval_bytes,
+        }
+    }
+
+    /// The broker defaults with a topic's own `segment.bytes`, `segment.ms` and
+    /// `index.interval.bytes` on top.
+    pub fn for_topic(topic_configs: Option<&HashMap<String, String>>) -> LogConfig {
+        let defaults = LogConfig::from_broker();
+        let Some(configs) = topic_configs else {
+            return defaults;
+        };
+        LogConfig {
+            segment_bytes: config::parse_or(configs, "segment.bytes", defaults.segment_bytes),
+            segment_ms: config::parse_or(configs, "segment.ms", defaults.segment_ms),
+            index_interval_bytes: config::parse_or(configs, "index.interval.bytes", defaults.index_interval_bytes),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PartitionOffsets {
+    pub log_start_offset: i64,
+    pub log_end_offset: i64,
+}
+
+/// A record located by a timestamp lookup.
+#[derive(Debug, Clone, Copy)]
+pub struct TimestampOffset {
+    pub timestamp: i64,
+    pub offset: i64,
+    pub leader_epoch: i32,
+}
+
+pub fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+fn segment_file(dir: &Path, base_offset: i64, suffix: &str) -> PathBuf {
+    dir.join(format!("{:020}{}", base_offset, suffix))
+}
+
+/// Sparse mapping from offsets to byte positions in a segment, stored as
+/// 4-byte relative offset / 4-byte position pairs like Kafka's `.index` files.
+struct OffsetIndex {
+    file: File,
+    entries: Vec<(i32, u32)>,
+}
+
+impl OffsetIndex {
+    fn create(path: &Path) -> anyhow::Result<OffsetIndex> {
+        let file = OpenOptions::new().create(true).write(true).truncate(true).open(path)?;
+        Ok(OffsetIndex { file, entries: Vec::new() })
+    }
+
+    fn load(path: &Path) -> anyhow::Result<OffsetIndex> {
+        let data = fs::read(path)?;
+        let entries = data
+            .chunks_exact(INDEX_ENTRY_SIZE)
+            .map(|entry| {
+                let relative_offset = i32::from_be_bytes(entry[0..4].try_into().unwrap());
+                let position = u32::from_be_bytes(entry[4..8].try_into().unwrap());
+                (relative_offset, position)
+            })
+            .collect();
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(OffsetIndex { file, entries })
+    }
+
+    fn append(&mut self, relative_offset: i32, position: u32) -> anyhow::Result<()> {
+        let mut entry = [0u8; INDEX_ENTRY_SIZE];
+        entry[0..4].copy_from_slice(&relative_offset.to_be_bytes());
+        entry[4..8].copy_from_slice(&position.to_be_bytes());
+        self.file.write_all(&entry)?;
+        self.entries.push((relative_offset, position));
+        Ok(())
+    }
+
+    /// Position of the last indexed batch starting at or before `relative_offset`.
+    fn lookup(&self, relative_offset: i32) -> u32 {
+        let idx = self.entries.partition_point(|(offset, _)| *offset <= relative_offset);
+        if idx == 0 {
+            0
+        } else {
+            self.entries[idx - 1].1
+        }
+    }
+}
+
+/// Maps the largest timestamp seen so far in a segment to the offset that
+/// carried it, as 8-byte timestamp / 4-byte relative offset pairs like Kafka's
+/// `.timeindex` files. Timestamps in the index only ever increase.
+struct TimeIndex {
+    file: File,
+    entries: Vec<(i64, i32)>,
+}
+
+impl TimeIndex {
+    fn create(path: &Path) -> anyhow::Result<TimeIndex> {
+        let file = OpenOptions::new().create(true).write(true).truncate(true).open(path)?;
+        Ok(TimeIndex { file, entries: Vec::new() })
+    }
+
+    fn load(path: &Path) -> anyhow::Result<TimeIndex> {
+        let data = fs::read(path)?;
+        let entries = data
+            .chunks_exact(TIME_INDEX_ENTRY_SIZE)
+            .map(|entry| {
+                let timestamp = i64::from_be_bytes(entry[0..8].try_into().unwrap());
+                let relative_offset = i32::from_be_bytes(entry[8..12].try_into().unwrap());
+                (timestamp, relative_offset)
+            })
+            .collect();
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(TimeIndex { file, entries })
+    }
+
+    fn last_timestamp(&self) -> i64 {
+        self.entries.last().map_or(-1, |(timestamp, _)| *timestamp)
+    }
+
+    /// Appends an entry unless it would not advance the largest indexed timestamp.
+    fn maybe_append(&mut self, timestamp: i64, relative_offset: i32) -> anyhow::Result<()> {
+        if timestamp <= self.last_timestamp() {
+            return Ok(());
+        }
+        let mut entry = [0u8; TIME_INDEX_ENTRY_SIZE];
+        entry[0..8].copy_from_slice(&timestamp.to_be_bytes());
+        entry[8..12].copy_from_slice(&relative_offset.to_be_bytes());
+        self.file.write_all(&entry)?;
+        self.entries.push((timestamp, relative_offset));
+        Ok(())
+    }
+
+    /// Relative offset from which a scan for `timestamp` can start: every record
+    /// before it is known to be older than `timestamp`.
+    fn lookup(&self, timestamp: i64) -> i32 {
+        let idx = self.entries.partition_point(|(ts, _)| *ts < timestamp);
+        if idx == 0 {
+            0
+        } else {
+            self.entries[idx - 1].1
+        }
+    }
+}
+
+pub struct LogSegment {
+    base_offset: i64,
+    log: File,
+    index: OffsetIndex,
+    time_index: TimeIndex,
+    max_timestamp: i64,
+    offset_of_max_timestamp: i64,
+    size: u64,
+    next_offset: i64,
+    created_ms: i64,
+    bytes_since_last_index_entry: usize,
+}
+
+impl LogSegment {
+    fn create(dir: &Path, base_offset: i64) -> anyhow::Result<LogSegment> {
+        let log = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(segment_file(dir, base_offset, LOG_SUFFIX))?;
+        let index = OffsetIndex::create(&segment_file(dir, base_offset, INDEX_SUFFIX))?;
+        let time_index = TimeIndex::create(&segment_file(dir, base_offset, TIME_INDEX_SUFFIX))?;
+        Ok(LogSegment {
+            base_offset,
+            log,
+            index,
+            time_index,
+            max_timestamp: -1,
+            offset_of_max_timestamp: -1,
+            size: 0,
+            next_offset: base_offset,
+            created_ms: now_ms(),
+            bytes_since_last_index_entry: 0,
+        })
+    }
+
+    /// Opens an existing segment, dropping a partially written trailing batch and
+    /// rebuilding both indexes from the log contents.
+    fn recover(dir: &Path, base_offset: i64, index_interval_bytes: usize) -> anyhow::Result<LogSegment> {
+        let log_path = segment_file(dir, base_offset, LOG_SUFFIX);
+        let data = fs::read(&log_path)?;
+        let batches = scan_batches(&data);
+        let valid_size = batches.last().map_or(0, |b| b.position + b.total_size());
+        if valid_size < data.len() {
+            eprintln!(
+                "Truncating {} from {} to {} bytes",
+                log_path.display(),
+                data.len(),
+                valid_size
+            );
+        }
+
+        let log = OpenOptions::new().read(true).append(true).open(&log_path)?;
+        log.set_len(valid_size as u64)?;
+        let metadata = log.metadata()?;
+        let created_ms = metadata
+            .created()
+            .or_else(|_| metadata.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or_else(now_ms, |d| d.as_millis() as i64);
+
+        let mut segment = LogSegment {
+            base_offset,
+            log,
+            index: OffsetIndex::create(&segment_file(dir, base_offset, INDEX_SUFFIX))?,
+            time_index: TimeIndex::create(&segment_file(dir, base_offset, TIME_INDEX_SUFFIX))?,
+            max_timestamp: -1,
+            offset_of_max_timestamp: -1,
+            size: 0,
+            next_offset: base_offset,
+            created_ms,
+            bytes_since_last_index_entry: 0,
+        };
+        for batch in &batches {
+            segment.track_batch(batch, index_interval_bytes)?;
+        }
+        Ok(segment)
+    }
+
+    /// Opens a sealed segment, trusting its indexes unless one is missing. The
+    /// last time index entry of a sealed segment holds its largest timestamp.
+    fn open_sealed(dir: &Path, base_offset: i64, next_offset: i64, index_interval_bytes: usize) -> anyhow::Result<LogSegment> {
+        let index_path = segment_file(dir, base_offset, INDEX_SUFFIX);
+        let time_index_path = segment_file(dir, base_offset, TIME_INDEX_SUFFIX);
+        if !index_path.exists() || !time_index_path.exists() {
+            return LogSegment::recover(dir, base_offset, index_interval_bytes);
+        }
+        let time_index = TimeIndex::load(&time_index_path)?;
+        let (max_timestamp, offset_of_max_timestamp) = time_index
+            .entries
+            .last()
+            .map_or((-1, -1), |(ts, relative)| (*ts, base_offset + *relative as i64));
+        let log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(segment_file(dir, base_offset, LOG_SUFFIX))?;
+        let size = log.metadata()?.len();
+        Ok(LogSegment {
+            base_offset,
+            log,
+            index: OffsetIndex::load(&index_path)?,
+            time_index,
+            max_timestamp,
+            offset_of_max_timestamp,
+            size,
+            next_offset,
+            created_ms: 0,
+            bytes_since_last_index_entry: 0,
+        })
+    }
+
+    /// Updates size, next offset and the sparse indexes for a batch stored at `batch.position`.
+    fn track_batch(&mut self, batch: &BatchHeader, index_interval_bytes: usize) -> anyhow::Result<()> {
+        if batch.max_timestamp > self.max_timestamp {
+            self.max_timestamp = batch.max_timestamp;
+            self.offset_of_max_timestamp = batch.last_offset();
+        }
+        if self.size == 0 || self.bytes_since_last_index_entry >= index_interval_bytes {
+            self.index
+                .append((batch.base_offset - self.base_offset) as i32, batch.position as u32)?;
+            self.time_index
+                .maybe_append(self.max_timestamp, (self.offset_of_max_timestamp - self.base_offset) as i32)?;
+            self.bytes_since_last_index_entry = 0;
+        }
+        self.size += batch.total_size() as u64;
+        self.bytes_since_last_index_entry += batch.total_size();
+        self.next_offset = batch.last_offset() + 1;
+        Ok(())
+    }
+
+    fn append(&mut self, batches: &[u8], index_interval_bytes: usize) -> anyhow::Result<()> {
+        let start = self.size as usize;
+        self.log.write_all(batches)?;
+        for mut batch in scan_batches(batches) {
+            batch.position += start;
+            self.track_batch(&batch, index_interval_bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Records the segment's final largest timestamp before it stops taking appends.
+    fn seal(&mut self) -> anyhow::Result<()> {
+        self.time_index
+            .maybe_append(self.max_timestamp, (self.offset_of_max_timestamp - self.base_offset) as i32)
+    }
+
+    fn read_header(&self, position: u64) -> anyhow::Result<Option<BatchHeader>> {
+        if position + BatchHeader::SIZE as u64 > self.size {
+            return Ok(None);
+        }
+        let mut header = [0u8; BatchHeader::SIZE];
+        self.log.read_at(&mut header, position)?;
+        Ok(BatchHeader::parse(&header, 0).map(|mut h| {
+            h.position = position as usize;
+            h
+        }))
+    }
+
+    /// Position of the first batch containing `offset` or later, found by seeking
+    /// through the index and scanning forward from there.
+    fn position_of(&self, offset: i64) -> anyhow::Result<Option<u64>> {
+        let mut position = self.index.lookup((offset - self.base_offset) as i32) as u64;
+        while let Some(header) = self.read_header(position)? {
+            if header.last_offset() >= offset {
+                return Ok(Some(position));
+            }
+            position += header.total_size() as u64;
+        }
+        Ok(None)
+    }
+
+    fn read_bytes(&self, start: u64, end: u64) -> anyhow::Result<Vec<u8>> {
+        let mut data = vec![0u8; (end - start) as usize];
+        self.log.read_exact_at(&mut data, start)?;
+        Ok(data)
+    }
+
+    /// Decodes the records of the batch stored at `position`.
+    fn read_batch_records(&self, header: &BatchHeader) -> anyhow::Result<Vec<Record>> {
+        let position = header.position as u64;
+        let data = self.read_bytes(position, position + header.total_size() as u64)?;
+        let mut local = header.clone();
+        local.position = 0;
+        Ok(local.records(&data))
+    }
+
+    /// First record at or after `timestamp`, seeking via the time index and then
+    /// the offset index instead of reading the segment from the start.
+    fn find_by_timestamp(&self, timestamp: i64) -> anyhow::Result<Option<TimestampOffset>> {
+        let start_offset = self.base_offset + self.time_index.lookup(timestamp) as i64;
+        let Some(mut position) = self.position_of(start_offset)? else {
+            return Ok(None);
+        };
+        while let Some(header) = self.read_header(position)? {
+            if header.max_timestamp >= timestamp {
+                let record = self
+                    .read_batch_records(&header)?
+                    .into_iter()
+                    .find(|r| r.timestamp >= timestamp);
+                if let Some(record) = record {
+                    return Ok(Some(TimestampOffset {
+                        timestamp: record.timestamp,
+                        offset: record.offset,
+                        leader_epoch: header.partition_leader_epoch,
+                    }));
+                }
+            }
+            position += header.total_size() as u64;
+        }
+        Ok(None)
+    }
+}
+
+/// The on-disk log of a single partition: a set of segments named by their base offset.
+pub struct Log {
+    dir: PathBuf,
+    config: LogConfig,
+    segments: BTreeMap<i64, LogSegment>,
+}
+
+impl Log {
+    pub fn open(dir: PathBuf, config: LogConfig) -> anyhow::Result<Log> {
+        fs::create_dir_all(&dir)?;
+
+        let mut base_offsets: Vec<i64> = fs::read_dir(&dir)?
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                name.strip_suffix(LOG_SUFFIX)?.parse().ok()
+            })
+            .collect();
+        base_offsets.sort_unstable();
+
+        let mut segments = BTreeMap::new();
+        for (i, base_offset) in base_offsets.iter().enumerate() {
+            let segment = match base_offsets.get(i + 1) {
+                Some(next_base) => LogSegment::open_sealed(&dir, *base_offset, *next_base, config.index_interval_bytes)?,
+                None => LogSegment::recover(&dir, *base_offset, config.index_interval_bytes)?,
+            };
+            segments.insert(*base_offset, segment);
+        }
+        if segments.is_empty() {
+            segments.insert(0, LogSegment::create(&dir, 0)?);
+        }
+
+        Ok(Log { dir, config, segments })
+    }
+
+    fn active_segment(&mut self) -> &mut LogSegment {
+        self.segments.values_mut().next_back().expect("log has at least one segment")
+    }
+
+    pub fn offsets(&self) -> PartitionOffsets {
+        PartitionOffsets {
+            log_start_offset: self.segments.keys().next().copied().unwrap_or(0),
+            log_end_offset: self.segments.values().next_back().map_or(0, |s| s.next_offset),
+        }
+    }
+
+    /// Appends a producer's record batches, assigning them offsets from the log end
+    /// offset. Returns the offsets as they were before the append, so
+    /// `log_end_offset` is the base offset of the first appended batch.
+    pub fn append(&mut self, records: Bytes, leader_epoch: i32) -> anyhow::Result<PartitionOffsets> {
+        let current = self.offsets();
+        let mut batches = BytesMut::from(&records[..]);
+        assign_offsets(&mut batches, current.log_end_offset, leader_epoch)?;
+
+        let config = self.config;
+        let active = self.active_segment();
+        let too_big = active.size + batches.len() as u64 > config.segment_bytes;
+        let too_old = now_ms() - active.created_ms >= config.segment_ms;
+        if active.size > 0 && (too_big || too_old) {
+            active.seal()?;
+            let segment = LogSegment::create(&self.dir, current.log_end_offset)?;
+            println!("Rolled new segment {} in {}", current.log_end_offset, self.dir.display());
+            self.segments.insert(current.log_end_offset, segment);
+        }
+
+        self.active_segment().append(&batches, config.index_interval_bytes)?;
+        Ok(current)
+    }
+
+    /// Reads whole batches starting with the one containing `offset`, stopping
+    /// before `max_bytes` is exceeded. With `min_one_batch` the first batch is
+    /// returned even when it is larger than `max_bytes`, so a consumer can make
+    /// progress past it.
+    pub fn read(&self, offset: i64, max_bytes: usize, min_one_batch: bool) -> anyhow::Result<Bytes> {
+        let offsets = self.offsets();
+        if offset < offsets.log_start_offset || offset > offsets.log_end_offset {
+            bail!("Offset {} out of range {:?}", offset, offsets);
+        }
+
+        let mut out = BytesMut::new();
+        let first_base = self
+            .segments
+            .range(..=offset)
+            .next_back()
+            .map_or(offsets.log_start_offset, |(base, _)| *base);
+        for segment in self.segments.range(first_base..).map(|(_, s)| s) {
+            let mut position = if out.is_empty() {
+                match segment.position_of(offset)? {
+                    Some(position) => position,
+                    None => continue,
+                }
+            } else {
+                0
+            };
+            let start = position;
+            while let Some(header) = segment.read_header(position)? {
+                let selected = out.len() + (position - start) as usize;
+                let exempt = selected == 0 && min_one_batch;
+                if !exempt && selected + header.total_size() > max_bytes {
+                    break;
+                }
+                position += header.total_size() as u64;
+            }
+            if position > start {
+                out.extend_from_slice(&segment.read_bytes(start, position)?);
+            }
+            if position < segment.size {
+                break;
+            }
+        }
+        Ok(out.freeze())
+    }
+
+    /// First record whose timestamp is at or after `target`. Segments whose
+    /// largest timestamp is older than `target` are skipped entirely.
+    pub fn offset_for_timestamp(&self, target: i64) -> anyhow::Result<Option<TimestampOffset>> {
+        for segment in self.segments.values().filter(|s| s.max_timestamp >= target) {
+            if let Some(found) = segment.find_by_timestamp(target)? {
+                return Ok(Some(found));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The record with the largest timestamp in the log.
+    pub fn max_timestamp_offset(&self) -> anyhow::Result<Option<TimestampOffset>> {
+        let Some(segment) = self
+            .segments
+            .values()
+            .filter(|s| s.max_timestamp >= 0)
+            .reduce(|best, s| if s.max_timestamp > best.max_timestamp { s } else { best })
+        else {
+            return Ok(None);
+        };
+        let Some(position) = segment.position_of(segment.offset_of_max_timestamp)? else {
+            return Ok(None);
+        };
+        let Some(header) = segment.read_header(position)? else {
+            return Ok(None);
+        };
+        let offset = segment
+            .read_batch_records(&header)?
+            .into_iter()
+            .find(|r| r.timestamp == segment.max_timestamp)
+            .map_or(segment.offset_of_max_timestamp, |r| r.offset);
+        Ok(Some(TimestampOffset {
+            timestamp: segment.max_timestamp,
+            offset,
+            leader_epoch: header.partition_leader_epoch,
+        }))
+    }
+}
+
+/// Rewrites base offset and leader epoch of every batch in place and refreshes
+/// its CRC. Returns the offset following the last rewritten batch.
+fn assign_offsets(batches: &mut BytesMut, mut next_offset: i64, leader_epoch: i32) -> anyhow::Result<i64> {
+    let headers = scan_batches(batches);
+    let covered: usize = headers.iter().map(BatchHeader::total_size).sum();
+    if headers.is_empty() || covered != batches.len() {
+        bail!("Malformed record batches: {} of {} bytes parsed", covered, batches.len());
+    }
+
+    for header in headers {
+        let batch = &mut batches[header.position..header.position + header.total_size()];
+        if batch[BatchHeader::MAGIC_OFFSET] != 2 {
+            bail!("Unsupported record batch magic {}", batch[BatchHeader::MAGIC_OFFSET]);
+        }
+        batch[0..8].copy_from_slice(&next_offset.to_be_bytes());
+        batch[12..16].copy_from_slice(&leader_epoch.to_be_bytes());
+        let crc = crc32c::crc32c(&batch[BatchHeader::ATTRIBUTES_OFFSET..]);
+        batch[BatchHeader::CRC_OFFSET..BatchHeader::ATTRIBUTES_OFFSET].copy_from_slice(&crc.to_be_bytes());
+        next_offset += header.last_offset_delta as i64 + 1;
+    }
+    Ok(next_offset)
+}
+
+pub type SharedLog = Arc<Mutex<Log>>;
+
+static LOGS: LazyLock<Mutex<HashMap<(String, u32), SharedLog>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn partition_dir(topic_name: &str, partition_id: u32) -> PathBuf {
+    Path::new(&config::get().log_dir).join(format!("{}-{}", topic_name, partition_id))
+}
+
+/// Returns the log of a partition, opening (and creating) it on first use.
+pub fn get_log(topic_name: &str, partition_id: u32) -> anyhow::Result<SharedLog> {
+    let mut logs = LOGS.lock().unwrap();
+    let key = (topic_name.to_string(), partition_id);
+    if let Some(log) = logs.get(&key) {
+        return Ok(log.clone());
+    }
+    let log = Arc::new(Mutex::new(Log::open(
+        partition_dir(topic_name, partition_id),
+        LogConfig::for_topic(metadata::image().topic_configs(topic_name)),
+    )?));
+    logs.insert(key, log.clone());
+    Ok(log)
+}
+
+/// Closes a partition's log and removes its directory. Like Kafka, the directory
+/// is first renamed to `<topic>-<partition>.<topic id>-delete`, so a crash halfway
+/// through leaves a directory `load_logs` cleans up rather than a partial log.
+pub fn delete_log(topic_name: &str, partition_id: u32, topic_id: Uuid) -> anyhow::Result<()> {
+    LOGS.lock().unwrap().remove(&(topic_name.to_string(), partition_id));
+    let dir = partition_dir(topic_name, partition_id);
+    if !dir.exists() {
+        return Ok(());
+    }
+    let deleted = dir.with_file_name(format!("{}-{}.{}{}", topic_name, partition_id, topic_id.simple(), DELETE_DIR_SUFFIX));
+    fs::rename(&dir, &deleted)?;
+    fs::remove_dir_all(&deleted)?;
+    Ok(())
+}
+
+/// Opens every `<topic>-<partition>` directory under the log dir so their
+/// segments are recovered before the first request arrives. Runs after the
+/// metadata is loaded, so each log is opened with its topic's configs.
+pub fn load_logs() {
+    let Ok(entries) = fs::read_dir(&config::get().log_dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let dir_name = entry.file_name().to_string_lossy().to_string();
+        if dir_name.ends_with(DELETE_DIR_SUFFIX) {
+            if let Err(e) = fs::remove_dir_all(entry.path()) {
+                eprintln!("Failed to remove deleted log {}: {}", dir_name, e);
+            }
+            continue;
+        }
+        let Some((topic_name, partition)) = dir_name.rsplit_once('-') else {
+            continue;
+        };
+        let Ok(partition_id) = partition.parse::<u32>() else {
+            continue;
+        };
+        if topic_name == "__cluster_metadata" || !entry.path().is_dir() {
+            continue;
+        }
+        match get_log(topic_name, partition_id) {
+            Ok(log) => println!("Recovered {}: {:?}", dir_name, log.lock().unwrap().offsets()),
+            Err(e) => eprintln!("Failed to recover {}: {}", dir_name, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_protocol::records::{Compression, RecordBatchEncoder, RecordEncodeOptions, TimestampType};
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("log-test-{}", Uuid::new_v4().simple()))
+    }
+
+    fn config(segment_bytes: u64, index_interval_bytes: usize) -> LogConfig {
+        LogConfig { segment_bytes, segment_ms: i64::MAX, index_interval_bytes }
+    }
+
+    /// One batch holding a record per timestamp.
+    fn batch(timestamps: &[i64]) -> Bytes {
+        let records: Vec<Record> = timestamps
+            .iter()
+            .enumerate()
+            .map(|(i, timestamp)| Record {
+                transactional: false,
+                control: false,
+                partition_leader_epoch: 0,
+                producer_id: -1,
+                producer_epoch: -1,
+                timestamp_type: TimestampType::Creation,
+                offset: i as i64,
+                sequence: i as i32,
+                timestamp: *timestamp,
+                key: None,
+                value: Some(Bytes::from_static(b"value")),
+                headers: Default::default(),
+            })
+            .collect();
+        let mut buf = BytesMut::new();
+        RecordBatchEncoder::encode(&mut buf, &records, &RecordEncodeOptions { version: 2, compression: Compression::None })
+            .unwrap();
+        buf.freeze()
+    }
+
+    fn base_offsets(data: &[u8]) -> Vec<i64> {
+        scan_batches(data).iter().map(|batch| batch.base_offset).collect()
+    }
+
+    #[test]
+    fn recovery_drops_a_torn_batch_and_rebuilds_the_index() {
+        let dir = temp_dir();
+        let mut log = Log::open(dir.clone(), config(u64::MAX, 0)).unwrap();
+        for _ in 0..3 {
+            log.append(batch(&[1, 2]), 0).unwrap();
+        }
+        drop(log);
+
+        let segment = segment_file(&dir, 0, LOG_SUFFIX);
+        let index = segment_file(&dir, 0, INDEX_SUFFIX);
+        let size = fs::metadata(&segment).unwrap().len();
+        OpenOptions::new().append(true).open(&segment).unwrap().write_all(&batch(&[3])[..20]).unwrap();
+        fs::write(&index, b"garbage").unwrap();
+
+        let mut log = Log::open(dir.clone(), config(u64::MAX, 0)).unwrap();
+        assert_eq!(fs::metadata(&segment).unwrap().len(), size);
+        assert_eq!(fs::metadata(&index).unwrap().len(), 3 * INDEX_ENTRY_SIZE as u64);
+        assert_eq!(log.offsets().log_end_offset, 6);
+        assert_eq!(base_offsets(&log.read(3, usize::MAX, true).unwrap()), vec![2, 4]);
+        assert_eq!(log.append(batch(&[4]), 0).unwrap().log_end_offset, 6);
+        assert_eq!(log.offsets().log_end_offset, 7);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sealed_segment_missing_its_index_is_recovered() {
+        let dir = temp_dir();
+        // Every append after the first rolls a segment.
+        let mut log = Log::open(dir.clone(), config(1, 0)).unwrap();
+        for _ in 0..3 {
+            log.append(batch(&[1, 2]), 0).unwrap();
+        }
+        drop(log);
+        fs::remove_file(segment_file(&dir, 2, INDEX_SUFFIX)).unwrap();
+
+        let log = Log::open(dir.clone(), config(1, 0)).unwrap();
+        assert_eq!(log.segments.keys().copied().collect::<Vec<_>>(), vec![0, 2, 4]);
+        assert!(segment_file(&dir, 2, INDEX_SUFFIX).exists());
+        assert_eq!((log.offsets().log_start_offset, log.offsets().log_end_offset), (0, 6));
+        assert_eq!(base_offsets(&log.read(3, usize::MAX, true).unwrap()), vec![2, 4]);
+        assert_eq!(base_offsets(&log.read(0, 1, true).unwrap()), vec![0]);
+        assert!(log.read(0, 1, false).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn found(log: &Log, timestamp: i64) -> Option<(i64, i64)> {
+        log.offset_for_timestamp(timestamp).unwrap().map(|found| (found.timestamp, found.offset))
+    }
+
+    #[test]
+    fn timestamp_lookups_survive_reopening() {
+        let dir = temp_dir();
+        // Only the first batch of each segment gets index entries.
+        let mut log = Log::open(dir.clone(), config(u64::MAX, usize::MAX)).unwrap();
+        log.append(batch(&[100, 300]), 0).unwrap();
+        log.append(batch(&[200]), 0).unwrap();
+        log.append(batch(&[400, 350]), 0).unwrap();
+        drop(log);
+
+        let log = Log::open(dir.clone(), config(u64::MAX, usize::MAX)).unwrap();
+        assert_eq!(found(&log, 50), Some((100, 0)));
+        assert_eq!(found(&log, 150), Some((300, 1)));
+        assert_eq!(found(&log, 300), Some((300, 1)));
+        assert_eq!(found(&log, 320), Some((400, 3)));
+        assert_eq!(found(&log, 401), None);
+        let max = log.max_timestamp_offset().unwrap().unwrap();
+        assert_eq!((max.timestamp, max.offset), (400, 3));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sealed_segment_missing_its_time_index_is_recovered() {
+        let dir = temp_dir();
+        let mut log = Log::open(dir.clone(), config(1, 0)).unwrap();
+        log.append(batch(&[100, 500]), 0).unwrap();
+        log.append(batch(&[200]), 0).unwrap();
+        log.append(batch(&[300]), 0).unwrap();
+        drop(log);
+        fs::remove_file(segment_file(&dir, 0, TIME_INDEX_SUFFIX)).unwrap();
+
+        let log = Log::open(dir.clone(), config(1, 0)).unwrap();
+        assert!(segment_file(&dir, 0, TIME_INDEX_SUFFIX).exists());
+        assert_eq!(found(&log, 400), Some((500, 1)));
+        assert_eq!(found(&log, 501), None);
+        // The first match in offset order wins, even though a later segment holds 200.
+        assert_eq!(found(&log, 150), Some((500, 1)));
+        let max = log.max_timestamp_offset().unwrap().unwrap();
+        assert_eq!((max.timestamp, max.offset), (500, 1));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
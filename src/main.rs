@@ -1,22 +1,70 @@
+mod config;
+mod consumer_group;
+mod consumer_offsets;
+mod controller;
+mod errors;
+mod fetch_session;
+mod framing;
+mod group_coordinator;
 mod handlers;
+mod log;
 mod meta_parser;
+mod metadata;
+mod purgatory;
+mod registry;
 mod utils;
 
-use kafka_protocol::messages::{ApiKey, ApiVersionsRequest, DescribeTopicPartitionsRequest, FetchRequest, ProduceRequest, RequestHeader, RequestKind};
+use kafka_protocol::messages::{ApiKey, ApiVersionsRequest, RequestHeader, RequestKind};
+use std::cell::RefCell;
 use std::io;
-use std::io::{Read, Write};
+use std::io::Write;
 use std::net::{TcpListener, TcpStream};
 use std::thread;
 use anyhow::bail;
-use bytes::{Buf, BytesMut};
+use bytes::{Bytes, BytesMut};
 use kafka_protocol::protocol::buf::ByteBuf;
 use kafka_protocol::protocol::{Decodable, StrBytes};
-use crate::handlers::{process_api_version, process_describe_topic_partitions, process_fetch, process_produce};
+use kafka_protocol::ResponseError;
+use crate::config::BrokerConfig;
+use crate::errors::error_response;
+use crate::framing::FrameReader;
+
+thread_local! {
+    /// The address of the client served by this connection thread, as DescribeGroups reports it.
+    static CLIENT_HOST: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// The host of the client whose request this thread is handling.
+pub fn client_host() -> String {
+    CLIENT_HOST.with(|host| host.borrow().clone())
+}
 
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:9092").expect("Failed to bind to port 9092");
+    let broker_config = match std::env::args().nth(1) {
+        Some(path) => BrokerConfig::load(&path).unwrap_or_else(|e| {
+            eprintln!("Failed to load config {}: {}, using defaults", path, e);
+            BrokerConfig::default()
+        }),
+        None => BrokerConfig::default(),
+    };
+    config::init(broker_config);
+    if let Err(e) = metadata::load() {
+        // Serving or writing on top of metadata that could not be read would lose every topic.
+        eprintln!("Failed to decode cluster metadata: {:#}", e);
+        std::process::exit(1);
+    }
+    log::load_logs();
+    if let Err(e) = metadata::bootstrap() {
+        eprintln!("Failed to bootstrap cluster metadata: {}", e);
+    }
+    if let Err(e) = consumer_offsets::load() {
+        eprintln!("Failed to load committed offsets: {:#}", e);
+    }
+
+    let address = format!("{}:{}", config::get().listener_host, config::get().listener_port);
+    let listener = TcpListener::bind(&address).unwrap_or_else(|e| panic!("Failed to bind to {}: {}", address, e));
     BytesMut::new();
-    println!("Kafka broker listening on 127.0.0.1:9092");
+    println!("Kafka broker listening on {}", address);
     ApiVersionsRequest::default().with_client_software_name(StrBytes::from(""));
     for stream_result in listener.incoming() {
         match stream_result {
@@ -35,16 +83,31 @@ fn main() {
 }
 
 fn handle_client(mut stream: TcpStream) -> io::Result<()> {
+    let mut frames = FrameReader::new(stream.try_clone()?, config::get().socket_request_max_bytes);
+    if let Ok(peer) = stream.peer_addr() {
+        CLIENT_HOST.with(|host| *host.borrow_mut() = format!("/{}", peer.ip()));
+    }
     loop {
-        let response_buf = match parse_kafka_request(&mut stream) {
-            Ok((api_key, header, request)) => {
-                handle_request(api_key, header, request)
+        let frame = match frames.read_frame() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("Failed to read Kafka request: {}", e);
+                break;
             }
+        };
+        let response_buf = match parse_kafka_request(frame) {
+            Ok((api_key, header, Ok(request))) => handle_request(api_key, header, request),
+            Ok((api_key, header, Err(error))) => error_response(api_key, &header, error.code()),
             Err(e) => {
                 eprintln!("Failed to parse Kafka request: {}", e);
                 break;
             }
         };
+        let Some(response_buf) = response_buf else {
+            eprintln!("Unable to encode a response, closing connection");
+            break;
+        };
         let mut buf = BytesMut::new();
         let message_length = response_buf.len() as i32;
         buf.extend_from_slice(&message_length.to_be_bytes());
@@ -54,58 +117,48 @@ fn handle_client(mut stream: TcpStream) -> io::Result<()> {
     Ok(())
 }
 
-fn handle_request(api_key: ApiKey, header: RequestHeader ,request: RequestKind) -> BytesMut {
+fn handle_request(api_key: ApiKey, header: RequestHeader ,request: RequestKind) -> Option<BytesMut> {
     println!("Request: {:?}", request);
-    match request {
-        RequestKind::ApiVersions(req) => process_api_version(header, req),
-        RequestKind::DescribeTopicPartitions(req) => process_describe_topic_partitions(api_key, header,req),
-        RequestKind::Fetch(req) => process_fetch(api_key, header,req),
-        RequestKind::Produce(req) => process_produce(api_key, header,req),
-        _ => {
-            panic!("Unsupported request kind");
-        }
-    }
+    let Some(entry) = registry::lookup(api_key) else {
+        eprintln!("Unsupported request kind {:?}", api_key);
+        return error_response(api_key, &header, ResponseError::UnsupportedVersion.code());
+    };
+    Some((entry.handler)(api_key, header, request))
 }
 
-fn parse_kafka_request(stream: &mut TcpStream) -> anyhow::Result<(ApiKey, RequestHeader, RequestKind)> {
-    let mut buf = BytesMut::zeroed(1024);
-    let bytes_read = stream.read(&mut buf)?;
-    buf.truncate(bytes_read);
-
-    let _message_length = buf.get_i32();
+/// Splits a frame into its header and body. The body is only decoded at versions
+/// we implement; otherwise, or when it is malformed, the error to answer with is
+/// returned in its place.
+fn parse_kafka_request(mut buf: Bytes) -> anyhow::Result<(ApiKey, RequestHeader, Result<RequestKind, ResponseError>)> {
+    if buf.len() < 4 {
+        bail!("Request too short: {} bytes", buf.len());
+    }
 
     let api_key_value = bytes::Buf::get_i16(&mut buf.peek_bytes(0..2));
     let api_key =
-        ApiKey::try_from(api_key_value).map_err(|_| anyhow::Error::msg("Unknown API key"))?;
+        ApiKey::try_from(api_key_value).map_err(|_| anyhow::anyhow!("Unknown API key {}", api_key_value))?;
 
     let api_version = bytes::Buf::get_i16(&mut buf.peek_bytes(2..4));
     let header_version = api_key.request_header_version(api_version);
     let header = RequestHeader::decode(&mut buf, header_version)?;
+    // ApiVersions answers unknown versions itself so clients can negotiate down.
+    if api_key != ApiKey::ApiVersions && !registry::lookup(api_key).is_some_and(|entry| entry.supports(api_version)) {
+        eprintln!("Unsupported version {} for {:?}", api_version, api_key);
+        return Ok((api_key, header, Err(ResponseError::UnsupportedVersion)));
+    }
     let request = match api_key {
         ApiKey::ApiVersions => {
             let api_versions_request = ApiVersionsRequest::decode(&mut buf, header.request_api_version).unwrap_or_else(|_| {
                 //Will send ApiVersionsRequest with error in handlers
                 ApiVersionsRequest::default()
             });
-            RequestKind::ApiVersions(api_versions_request)
-        }
-        ApiKey::DescribeTopicPartitions => {
-            let describe_request =
-                DescribeTopicPartitionsRequest::decode(&mut buf, header.request_api_version)?;
-            RequestKind::DescribeTopicPartitions(describe_request)
+            Ok(RequestKind::ApiVersions(api_versions_request))
         }
-        ApiKey::Fetch => {
-            let describe_request =
-                FetchRequest::decode(&mut buf, header.request_api_version)?;
-            RequestKind::Fetch(describe_request)
-        }
-        ApiKey::Produce => {
-            let describe_request =
-                ProduceRequest::decode(&mut buf, header.request_api_version)?;
-            RequestKind::Produce(describe_request)
-        }
-        _ => bail!("Unsupported API key: {:?}", api_key),
+        _ => RequestKind::decode(api_key, &mut buf, header.request_api_version).map_err(|e| {
+            eprintln!("Failed to decode {:?} request: {}", api_key, e);
+            ResponseError::InvalidRequest
+        }),
     };
 
     Ok((api_key, header, request))
-}
\ No newline at end of file
+}
@@ -1,212 +1,1609 @@
-use bytes::{Buf, Bytes, BytesMut};
+use anyhow::{anyhow, bail, Context};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use kafka_protocol::records::{RecordBatchDecoder};
 use std::{fs};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+use crate::log::partition_dir;
 
-pub fn decode() -> anyhow::Result<Vec<RecordType>> {
-    let path = "/tmp/kraft-combined-logs/__cluster_metadata-0/00000000000000000000.log";
+const LOG_SUFFIX: &str = ".log";
+const CHECKPOINT_SUFFIX: &str = ".checkpoint";
+
+/// The KRaft metadata log is the single partition of this internal topic.
+pub const METADATA_TOPIC: &str = "__cluster_metadata";
+
+pub fn metadata_dir() -> PathBuf {
+    partition_dir(METADATA_TOPIC, 0)
+}
+
+/// Log segments (`{base_offset}.log`) and snapshots (`{end_offset}-{epoch}.checkpoint`)
+/// in `dir`, each with the offset its file name starts with.
+/// In-progress `.checkpoint.part` files and deleted segments are not included.
+pub fn metadata_files(dir: &Path) -> anyhow::Result<Vec<(i64, PathBuf)>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // A fresh data directory has no metadata until the controller writes some.
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut files: Vec<(i64, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let offset = match name.strip_suffix(LOG_SUFFIX) {
+                Some(base_offset) => base_offset,
+                None => name.strip_suffix(CHECKPOINT_SUFFIX)?.split('-').next()?,
+            };
+            Some((offset.parse().ok()?, entry.path()))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Reads the metadata as of the end of the log in `dir`: the latest snapshot, if
+/// any, followed by every log record from the snapshot's end offset on.
+pub fn decode_dir(dir: &Path) -> anyhow::Result<Vec<RecordType>> {
+    let files = metadata_files(dir)?;
+    let is_checkpoint = |path: &Path| path.to_string_lossy().ends_with(CHECKPOINT_SUFFIX);
+
+    let mut result = Vec::new();
+    // A snapshot named after offset N holds the state of every record below N.
+    let mut next_offset = 0;
+    if let Some((end_offset, path)) = files.iter().rev().find(|(_, path)| is_checkpoint(path)) {
+        println!("Loading metadata snapshot {}", path.display());
+        decode_file(path, 0, &mut result)?;
+        next_offset = *end_offset;
+    }
+
+    let segments: Vec<&(i64, PathBuf)> = files.iter().filter(|(_, path)| !is_checkpoint(path)).collect();
+    for (i, (_, path)) in segments.iter().enumerate() {
+        // Skip segments the snapshot fully covers.
+        if segments.get(i + 1).is_some_and(|(next_base, _)| *next_base <= next_offset) {
+            continue;
+        }
+        decode_file(path, next_offset, &mut result)?;
+    }
+    Ok(result)
+}
+
+/// Appends the metadata records of one log segment or snapshot file, skipping
+/// control records (leader changes, snapshot headers and footers) and records
+/// below `min_offset`.
+fn decode_file(path: &Path, min_offset: i64, result: &mut Vec<RecordType>) -> anyhow::Result<()> {
     let file = fs::read(path)?;
     let mut buf = BytesMut::from(&file[..]);
-    let res = RecordBatchDecoder::decode_all(&mut buf)?;
-    // println!("{:?}", res);
-    let result : Vec<RecordType> = res
-        .iter()
-        .flat_map(|rec| {
-            rec.records.iter().map(|rec| {
-                //eprintln!("{:?}", rec);
-                let mut data = rec.value.clone().unwrap();
-                //eprintln!("REM-> {:?}", data.remaining());
-                let header = get_header(&mut data);
-                //eprintln!("{:?}", header);
-                //eprintln!("REM-> {:?}", data.remaining());
-
-                match header.record_type {
-                    2 => {
-                        // Topic
-                        let name_length = data.get_u8() - 1;
-                        let name =
-                            String::from_utf8(data.copy_to_bytes(name_length as usize).to_vec())
-                                .unwrap();
-                        let uuid = uuid::Uuid::from_u128(data.get_u128());
-                        RecordType::TopicValue(Topic { header, name, uuid })
-                    }
-                    3 => {
-                        // Partition
-                        let partition_id = data.get_u32();
-                        let topic_uuid = uuid::Uuid::from_u128(data.get_u128());
-                        let rep_array_length = data.get_u8();
-                        let rep_array = data.get_i32();
-                        let in_sync_rep_arr_length = data.get_u8();
-                        let in_sync_rep_arr = data.get_i32();
-                        let rmv_rep_arr_length = data.get_u8();
-                        let adding_rep_arr_length = data.get_u8();
-                        let leader = data.get_i32();
-                        let leader_eponch = data.get_i32();
-                        let partition_eponch = data.get_u32();
-                        let directories_arr_length = data.get_u8();
-                        let directories_arr = uuid::Uuid::from_u128(data.get_u128());
-                        let tagged_fields_count = data.get_u8();
-                        RecordType::PartitionValue(Partition {
-                            header,
-                            partition_id,
-                            topic_uuid,
-                            rep_array_length,
-                            rep_array,
-                            in_sync_rep_arr_length,
-                            in_sync_rep_arr,
-                            rmv_rep_arr_length,
-                            adding_rep_arr_length,
-                            leader,
-                            leader_eponch,
-                            partition_eponch,
-                            directories_arr_length,
-                            directories_arr,
-                            tagged_fields_count,
-                        })
-                    }
-                    12 => { // Feature
-                        let name_length = data.get_u8() - 1;
-                        let name =
-                            String::from_utf8(data.copy_to_bytes(name_length as usize).to_vec()).unwrap();
-                        let feature_level = data.get_u16();
-                        let _tagged_fields_count = data.get_u8();
-                        RecordType::FeatureValue(Feature {
-                            header,
-                            name,
-                            feature_level,
-                        })
-                    }
-                    _ => {
-                        RecordType::None
-                    }
-                }
-            }).collect::<Vec<_>>().into_iter()
-        }).collect();
-    Ok(result)
+    let batches = RecordBatchDecoder::decode_all(&mut buf)
+        .with_context(|| format!("invalid record batch in {}", path.display()))?;
+
+    for batch in &batches {
+        for record in &batch.records {
+            if record.control || record.offset < min_offset {
+                continue;
+            }
+            let Some(value) = record.value.clone() else {
+                bail!("metadata record at offset {} in {} has no value", record.offset, path.display());
+            };
+            let parsed = decode_record(value).with_context(|| {
+                format!("invalid metadata record at offset {} in {}", record.offset, path.display())
+            })?;
+            result.push(parsed);
+        }
+    }
+    Ok(())
+}
+
+/// Decodes one serialized metadata record: an unsigned varint frame version,
+/// record type (the schema's api key) and record version, followed by the
+/// record body in the flexible encoding every metadata schema uses.
+pub fn decode_record(value: Bytes) -> anyhow::Result<RecordType> {
+    let mut reader = MetadataReader::new(value);
+    let header = Header::read(&mut reader)?;
+    if header.frame_version != 1 {
+        bail!("unsupported metadata record frame version {}", header.frame_version);
+    }
+    let r = &mut reader;
+    let record = match header.record_type {
+        RegisterBrokerRecord::TYPE => RecordType::RegisterBrokerValue(RegisterBrokerRecord::read(r, header)?),
+        UnregisterBrokerRecord::TYPE => RecordType::UnregisterBrokerValue(UnregisterBrokerRecord::read(r, header)?),
+        TopicRecord::TYPE => RecordType::TopicValue(TopicRecord::read(r, header)?),
+        PartitionRecord::TYPE => RecordType::PartitionValue(PartitionRecord::read(r, header)?),
+        ConfigRecord::TYPE => RecordType::ConfigValue(ConfigRecord::read(r, header)?),
+        PartitionChangeRecord::TYPE => RecordType::PartitionChangeValue(PartitionChangeRecord::read(r, header)?),
+        AccessControlEntryRecord::TYPE => RecordType::AccessControlEntryValue(AccessControlEntryRecord::read(r, header)?),
+        RemoveAccessControlEntryRecord::TYPE => {
+            RecordType::RemoveAccessControlEntryValue(RemoveAccessControlEntryRecord::read(r, header)?)
+        }
+        FenceBrokerRecord::TYPE => RecordType::FenceBrokerValue(FenceBrokerRecord::read(r, header)?),
+        UnfenceBrokerRecord::TYPE => RecordType::UnfenceBrokerValue(UnfenceBrokerRecord::read(r, header)?),
+        RemoveTopicRecord::TYPE => RecordType::RemoveTopicValue(RemoveTopicRecord::read(r, header)?),
+        UserScramCredentialRecord::TYPE => RecordType::UserScramCredentialValue(UserScramCredentialRecord::read(r, header)?),
+        RemoveUserScramCredentialRecord::TYPE => {
+            RecordType::RemoveUserScramCredentialValue(RemoveUserScramCredentialRecord::read(r, header)?)
+        }
+        FeatureLevelRecord::TYPE => RecordType::FeatureValue(FeatureLevelRecord::read(r, header)?),
+        ClientQuotaRecord::TYPE => RecordType::ClientQuotaValue(ClientQuotaRecord::read(r, header)?),
+        ProducerIdsRecord::TYPE => RecordType::ProducerIdsValue(ProducerIdsRecord::read(r, header)?),
+        BrokerRegistrationChangeRecord::TYPE => {
+            RecordType::BrokerRegistrationChangeValue(BrokerRegistrationChangeRecord::read(r, header)?)
+        }
+        NoOpRecord::TYPE => RecordType::NoOpValue(NoOpRecord::read(r, header)?),
+        BeginTransactionRecord::TYPE => RecordType::BeginTransactionValue(BeginTransactionRecord::read(r, header)?),
+        EndTransactionRecord::TYPE => RecordType::EndTransactionValue(EndTransactionRecord::read(r, header)?),
+        AbortTransactionRecord::TYPE => RecordType::AbortTransactionValue(AbortTransactionRecord::read(r, header)?),
+        _ => RecordType::None,
+    };
+    Ok(record)
 }
 
-fn get_header(data: &mut Bytes) -> Header {
-    let frame_version = data.get_u8();
-    let record_type = data.get_u8();
-    let version = data.get_u8();
-    Header {
-        frame_version,
-        record_type,
-        version,
+/// Serializes a record the way [`decode_record`] reads it. Returns `None` for
+/// [`RecordType::None`], which has no schema to write.
+pub fn encode_record(record: &RecordType) -> Option<Bytes> {
+    let (header, body): (&Header, &dyn Fn(&mut MetadataWriter)) = match record {
+        RecordType::RegisterBrokerValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::UnregisterBrokerValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::TopicValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::PartitionValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::ConfigValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::PartitionChangeValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::AccessControlEntryValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::RemoveAccessControlEntryValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::FenceBrokerValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::UnfenceBrokerValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::RemoveTopicValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::UserScramCredentialValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::RemoveUserScramCredentialValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::FeatureValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::ClientQuotaValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::ProducerIdsValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::BrokerRegistrationChangeValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::NoOpValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::BeginTransactionValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::EndTransactionValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::AbortTransactionValue(r) => (&r.header, &|w| r.write(w)),
+        RecordType::None => return None,
+    };
+    let mut writer = MetadataWriter::new();
+    header.write(&mut writer);
+    body(&mut writer);
+    Some(writer.finish())
+}
+
+/// Bounds-checked reader for the primitive types of the Kafka message schemas.
+/// Every read fails instead of panicking when the buffer is too short.
+pub struct MetadataReader {
+    data: Bytes,
+}
+
+impl MetadataReader {
+    pub fn new(data: Bytes) -> Self {
+        MetadataReader { data }
+    }
+
+    fn ensure(&self, len: usize) -> anyhow::Result<()> {
+        if self.data.remaining() < len {
+            bail!("needed {} bytes but only {} remain", len, self.data.remaining());
+        }
+        Ok(())
+    }
+
+    pub fn read_bool(&mut self) -> anyhow::Result<bool> {
+        Ok(self.read_i8()? != 0)
+    }
+
+    pub fn read_i8(&mut self) -> anyhow::Result<i8> {
+        self.ensure(1)?;
+        Ok(self.data.get_i8())
+    }
+
+    pub fn read_i16(&mut self) -> anyhow::Result<i16> {
+        self.ensure(2)?;
+        Ok(self.data.get_i16())
+    }
+
+    pub fn read_u16(&mut self) -> anyhow::Result<u16> {
+        self.ensure(2)?;
+        Ok(self.data.get_u16())
+    }
+
+    pub fn read_i32(&mut self) -> anyhow::Result<i32> {
+        self.ensure(4)?;
+        Ok(self.data.get_i32())
+    }
+
+    pub fn read_i64(&mut self) -> anyhow::Result<i64> {
+        self.ensure(8)?;
+        Ok(self.data.get_i64())
+    }
+
+    pub fn read_f64(&mut self) -> anyhow::Result<f64> {
+        self.ensure(8)?;
+        Ok(self.data.get_f64())
+    }
+
+    pub fn read_uuid(&mut self) -> anyhow::Result<Uuid> {
+        self.ensure(16)?;
+        Ok(Uuid::from_u128(self.data.get_u128()))
+    }
+
+    pub fn read_unsigned_varint(&mut self) -> anyhow::Result<u32> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            self.ensure(1)?;
+            let byte = self.data.get_u8();
+            value |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("unsigned varint is longer than 5 bytes")
+    }
+
+    /// Compact lengths are stored plus one so that zero can mean null.
+    fn read_compact_length(&mut self) -> anyhow::Result<Option<usize>> {
+        Ok(self.read_unsigned_varint()?.checked_sub(1).map(|len| len as usize))
+    }
+
+    pub fn read_compact_nullable_string(&mut self) -> anyhow::Result<Option<String>> {
+        let Some(len) = self.read_compact_length()? else {
+            return Ok(None);
+        };
+        self.ensure(len)?;
+        let bytes = self.data.copy_to_bytes(len);
+        Ok(Some(String::from_utf8(bytes.to_vec()).context("string is not valid UTF-8")?))
+    }
+
+    pub fn read_compact_bytes(&mut self) -> anyhow::Result<Bytes> {
+        let len = self.read_compact_length()?.ok_or_else(|| anyhow!("non-nullable bytes are null"))?;
+        self.ensure(len)?;
+        Ok(self.data.copy_to_bytes(len))
+    }
+
+    pub fn read_compact_string(&mut self) -> anyhow::Result<String> {
+        self.read_compact_nullable_string()?
+            .ok_or_else(|| anyhow!("non-nullable string is null"))
+    }
+
+    pub fn read_compact_nullable_array<T>(
+        &mut self,
+        mut read_element: impl FnMut(&mut Self) -> anyhow::Result<T>,
+    ) -> anyhow::Result<Option<Vec<T>>> {
+        let Some(len) = self.read_compact_length()? else {
+            return Ok(None);
+        };
+        // Every element takes at least one byte, so a larger count is corrupt.
+        self.ensure(len)?;
+        (0..len).map(|_| read_element(self)).collect::<anyhow::Result<_>>().map(Some)
+    }
+
+    pub fn read_compact_array<T>(
+        &mut self,
+        read_element: impl FnMut(&mut Self) -> anyhow::Result<T>,
+    ) -> anyhow::Result<Vec<T>> {
+        self.read_compact_nullable_array(read_element)?
+            .ok_or_else(|| anyhow!("non-nullable array is null"))
+    }
+
+    /// Reads a tagged field section, handing each field to `read_field` with a
+    /// reader over just that field's bytes. Unknown tags are skipped.
+    pub fn read_tagged_fields(
+        &mut self,
+        mut read_field: impl FnMut(u32, &mut Self) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let count = self.read_unsigned_varint()?;
+        for _ in 0..count {
+            let tag = self.read_unsigned_varint()?;
+            let size = self.read_unsigned_varint()? as usize;
+            self.ensure(size)?;
+            let mut field = MetadataReader::new(self.data.split_to(size));
+            read_field(tag, &mut field).with_context(|| format!("invalid tagged field {}", tag))?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the primitive types [`MetadataReader`] reads, in the same encoding.
+#[derive(Default)]
+pub struct MetadataWriter {
+    buf: BytesMut,
+}
+
+impl MetadataWriter {
+    pub fn new() -> Self {
+        MetadataWriter { buf: BytesMut::new() }
+    }
+
+    pub fn finish(self) -> Bytes {
+        self.buf.freeze()
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.buf.put_i8(value as i8);
+    }
+
+    pub fn write_i8(&mut self, value: i8) {
+        self.buf.put_i8(value);
+    }
+
+    pub fn write_i16(&mut self, value: i16) {
+        self.buf.put_i16(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.buf.put_u16(value);
+    }
+
+    pub fn write_i32(&mut self, value: i32) {
+        self.buf.put_i32(value);
+    }
+
+    pub fn write_i64(&mut self, value: i64) {
+        self.buf.put_i64(value);
+    }
+
+    pub fn write_f64(&mut self, value: f64) {
+        self.buf.put_f64(value);
+    }
+
+    pub fn write_uuid(&mut self, value: Uuid) {
+        self.buf.put_u128(value.as_u128());
+    }
+
+    pub fn write_unsigned_varint(&mut self, mut value: u32) {
+        while value >= 0x80 {
+            self.buf.put_u8((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        self.buf.put_u8(value as u8);
+    }
+
+    fn write_compact_length(&mut self, len: Option<usize>) {
+        self.write_unsigned_varint(len.map_or(0, |len| len as u32 + 1));
+    }
+
+    pub fn write_compact_bytes(&mut self, value: &[u8]) {
+        self.write_compact_length(Some(value.len()));
+        self.buf.put_slice(value);
+    }
+
+    pub fn write_compact_nullable_string(&mut self, value: Option<&str>) {
+        self.write_compact_length(value.map(str::len));
+        if let Some(value) = value {
+            self.buf.put_slice(value.as_bytes());
+        }
+    }
+
+    pub fn write_compact_string(&mut self, value: &str) {
+        self.write_compact_nullable_string(Some(value));
+    }
+
+    pub fn write_compact_nullable_array<T>(&mut self, values: Option<&[T]>, mut write_element: impl FnMut(&mut Self, &T)) {
+        self.write_compact_length(values.map(<[T]>::len));
+        for value in values.unwrap_or_default() {
+            write_element(self, value);
+        }
+    }
+
+    pub fn write_compact_array<T>(&mut self, values: &[T], write_element: impl FnMut(&mut Self, &T)) {
+        self.write_compact_nullable_array(Some(values), write_element);
+    }
+
+    pub fn write_i32_array(&mut self, values: &[i32]) {
+        self.write_compact_array(values, |w, v| w.write_i32(*v));
+    }
+
+    pub fn write_uuid_array(&mut self, values: &[Uuid]) {
+        self.write_compact_array(values, |w, v| w.write_uuid(*v));
+    }
+
+    /// Writes a tagged field section. `fields` must be in ascending tag order
+    /// and only hold the fields that differ from their defaults.
+    pub fn write_tagged_fields(&mut self, fields: &[(u32, Bytes)]) {
+        self.write_unsigned_varint(fields.len() as u32);
+        for (tag, field) in fields {
+            self.write_unsigned_varint(*tag);
+            self.write_unsigned_varint(field.len() as u32);
+            self.buf.put_slice(field);
+        }
     }
 }
 
+/// Encodes one tagged field's value for [`MetadataWriter::write_tagged_fields`].
+pub fn tagged_field(tag: u32, write: impl FnOnce(&mut MetadataWriter)) -> (u32, Bytes) {
+    let mut field = MetadataWriter::new();
+    write(&mut field);
+    (tag, field.finish())
+}
+
+/// A metadata record schema: its record type and how to read its body.
+pub trait MetadataRecord: Sized {
+    const TYPE: u32;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self>;
+
+    /// Writes the body in the version given by the record's header.
+    fn write(&self, writer: &mut MetadataWriter);
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 pub enum RecordType {
-    FeatureValue(Feature),
-    TopicValue(Topic),
-    PartitionValue(Partition),
+    RegisterBrokerValue(RegisterBrokerRecord),
+    UnregisterBrokerValue(UnregisterBrokerRecord),
+    TopicValue(TopicRecord),
+    PartitionValue(PartitionRecord),
+    ConfigValue(ConfigRecord),
+    PartitionChangeValue(PartitionChangeRecord),
+    AccessControlEntryValue(AccessControlEntryRecord),
+    RemoveAccessControlEntryValue(RemoveAccessControlEntryRecord),
+    FenceBrokerValue(FenceBrokerRecord),
+    UnfenceBrokerValue(UnfenceBrokerRecord),
+    RemoveTopicValue(RemoveTopicRecord),
+    UserScramCredentialValue(UserScramCredentialRecord),
+    RemoveUserScramCredentialValue(RemoveUserScramCredentialRecord),
+    FeatureValue(FeatureLevelRecord),
+    ClientQuotaValue(ClientQuotaRecord),
+    ProducerIdsValue(ProducerIdsRecord),
+    BrokerRegistrationChangeValue(BrokerRegistrationChangeRecord),
+    NoOpValue(NoOpRecord),
+    BeginTransactionValue(BeginTransactionRecord),
+    EndTransactionValue(EndTransactionRecord),
+    AbortTransactionValue(AbortTransactionRecord),
+    /// A record type this broker does not know, such as delegation tokens.
     None
 }
 
 #[allow(dead_code)]
 #[derive(Debug,Clone)]
 pub struct Header {
-    pub frame_version: u8,
-    pub record_type: u8,
-    pub version: u8,
+    pub frame_version: u32,
+    pub record_type: u32,
+    pub version: u32,
+}
+
+impl Header {
+    pub fn new(record_type: u32, version: u32) -> Header {
+        Header { frame_version: 1, record_type, version }
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_unsigned_varint(self.frame_version);
+        writer.write_unsigned_varint(self.record_type);
+        writer.write_unsigned_varint(self.version);
+    }
+
+    fn read(reader: &mut MetadataReader) -> anyhow::Result<Header> {
+        Ok(Header {
+            frame_version: reader.read_unsigned_varint()?,
+            record_type: reader.read_unsigned_varint()?,
+            version: reader.read_unsigned_varint()?,
+        })
+    }
 }
 
 #[allow(dead_code)]
 #[derive(Debug)]
-pub struct Feature {
+pub struct FeatureLevelRecord {
     pub header: Header,
     pub name: String,
-    pub feature_level: u16,
+    pub feature_level: i16,
+}
+
+impl MetadataRecord for FeatureLevelRecord {
+    const TYPE: u32 = 12;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        let name = reader.read_compact_string()?;
+        let feature_level = reader.read_i16()?;
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(FeatureLevelRecord { header, name, feature_level })
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_compact_string(&self.name);
+        writer.write_i16(self.feature_level);
+        writer.write_tagged_fields(&[]);
+    }
 }
 
 #[allow(dead_code)]
-#[derive(Debug)]
-pub struct Topic {
+#[derive(Debug, Clone)]
+pub struct TopicRecord {
     pub header: Header,
     pub name: String,
-    pub uuid: uuid::Uuid,
+    pub uuid: Uuid,
+}
+
+impl MetadataRecord for TopicRecord {
+    const TYPE: u32 = 2;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        let name = reader.read_compact_string()?;
+        let uuid = reader.read_uuid()?;
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(TopicRecord { header, name, uuid })
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_compact_string(&self.name);
+        writer.write_uuid(self.uuid);
+        writer.write_tagged_fields(&[]);
+    }
 }
 
 #[allow(dead_code)]
 #[derive(Debug,Clone)]
-pub struct Partition {
+pub struct PartitionRecord {
     pub header: Header,
     pub partition_id: u32,
-    pub topic_uuid: uuid::Uuid,
-    pub rep_array_length: u8,
-    pub rep_array: i32,
-    pub in_sync_rep_arr_length: u8,
-    pub in_sync_rep_arr: i32,
-    pub rmv_rep_arr_length: u8,
-    pub adding_rep_arr_length: u8,
+    pub topic_uuid: Uuid,
+    pub replicas: Vec<i32>,
+    pub isr: Vec<i32>,
+    pub removing_replicas: Vec<i32>,
+    pub adding_replicas: Vec<i32>,
     pub leader: i32,
-    pub leader_eponch: i32,
-    pub partition_eponch: u32,
-    pub directories_arr_length: u8,
-    pub directories_arr: uuid::Uuid,
-    pub tagged_fields_count: u8,
-}
-
-// impl TryFrom<&mut BytesMut> for RecordValue {
-//     type Error = String;
-//
-//     fn try_from(buf: &mut BytesMut) -> Result<Self, Self::Error> {
-//         let header = RecordHeader::try_from(&mut *buf).unwrap();
-//         match header.record_type {
-//             RecordType::FeatureLevel => {
-//                 let name_length = buf.get_u8() - 1;
-//                 let name =
-//                     String::from_utf8(buf.copy_to_bytes(name_length as usize).to_vec()).unwrap();
-//                 let feature_level = buf.get_u16();
-//                 let _tagged_fields_count = buf.get_u8();
-//                 Ok(RecordValue::FeatureLevel(FeatureLevelRecord {
-//                     header,
-//                     name,
-//                     feature_level,
-//                 }))
-//             }
-//             RecordType::Topic => {
-//                 let name_length = buf.get_u8() - 1;
-//                 let name =
-//                     String::from_utf8(buf.copy_to_bytes(name_length as usize).to_vec()).unwrap();
-//                 let uuid = uuid::Uuid::from_u128(buf.get_u128());
-//                 let _tagged_fields_count = buf.get_u8();
-//                 Ok(RecordValue::Topic(TopicRecord { header, name, uuid }))
-//             }
-//             RecordType::Partition => {
-//                 let partition_id = buf.get_u32();
-//                 let topic_uuid = uuid::Uuid::from_u128(buf.get_u128());
-//                 let rep_array_length = buf.get_u8();
-//                 let rep_array = buf.get_i32();
-//                 let in_sync_rep_arr_length = buf.get_u8();
-//                 let in_sync_rep_arr = buf.get_i32();
-//                 let rmv_rep_arr_length = buf.get_u8();
-//                 let adding_rep_arr_length = buf.get_u8();
-//                 let leader = buf.get_i32();
-//                 let leader_eponch = buf.get_i32();
-//                 let partition_eponch = buf.get_u32();
-//                 let directories_arr_length = buf.get_u8();
-//                 let directories_arr = uuid::Uuid::from_u128(buf.get_u128());
-//                 let tagged_fields_count = buf.get_u8();
-//                 Ok(RecordValue::Partition(PartitionRecord {
-//                     header,
-//                     partition_id,
-//                     topic_uuid,
-//                     rep_array_length,
-//                     rep_array,
-//                     in_sync_rep_arr_length,
-//                     in_sync_rep_arr,
-//                     rmv_rep_arr_length,
-//                     adding_rep_arr_length,
-//                     leader,
-//                     leader_eponch,
-//                     partition_eponch,
-//                     directories_arr_length,
-//                     directories_arr,
-//                     tagged_fields_count,
-//                 }))
-//             }
-//         }
-//     }
-// }
+    pub leader_recovery_state: i8,
+    pub leader_epoch: i32,
+    pub partition_epoch: i32,
+    /// Log directory of each replica, from v1.
+    pub directories: Vec<Uuid>,
+    /// Tagged fields added in v2 for eligible leader replicas (KIP-966).
+    pub eligible_leader_replicas: Option<Vec<i32>>,
+    pub last_known_elr: Option<Vec<i32>>,
+}
+
+impl MetadataRecord for PartitionRecord {
+    const TYPE: u32 = 3;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        let partition_id = reader.read_i32()?;
+        let partition_id = u32::try_from(partition_id)
+            .map_err(|_| anyhow!("negative partition id {}", partition_id))?;
+        let topic_uuid = reader.read_uuid()?;
+        let replicas = reader.read_compact_array(MetadataReader::read_i32)?;
+        let isr = reader.read_compact_array(MetadataReader::read_i32)?;
+        let removing_replicas = reader.read_compact_array(MetadataReader::read_i32)?;
+        let adding_replicas = reader.read_compact_array(MetadataReader::read_i32)?;
+        let leader = reader.read_i32()?;
+        let leader_epoch = reader.read_i32()?;
+        let partition_epoch = reader.read_i32()?;
+        let directories = if header.version >= 1 {
+            reader.read_compact_array(MetadataReader::read_uuid)?
+        } else {
+            Vec::new()
+        };
+
+        let mut leader_recovery_state = 0;
+        let mut eligible_leader_replicas = None;
+        let mut last_known_elr = None;
+        reader.read_tagged_fields(|tag, field| {
+            match tag {
+                0 => leader_recovery_state = field.read_i8()?,
+                1 => eligible_leader_replicas = field.read_compact_nullable_array(MetadataReader::read_i32)?,
+                2 => last_known_elr = field.read_compact_nullable_array(MetadataReader::read_i32)?,
+                _ => {}
+            }
+            Ok(())
+        })?;
+
+        Ok(PartitionRecord {
+            header,
+            partition_id,
+            topic_uuid,
+            replicas,
+            isr,
+            removing_replicas,
+            adding_replicas,
+            leader,
+            leader_recovery_state,
+            leader_epoch,
+            partition_epoch,
+            directories,
+            eligible_leader_replicas,
+            last_known_elr,
+        })
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_i32(self.partition_id as i32);
+        writer.write_uuid(self.topic_uuid);
+        writer.write_i32_array(&self.replicas);
+        writer.write_i32_array(&self.isr);
+        writer.write_i32_array(&self.removing_replicas);
+        writer.write_i32_array(&self.adding_replicas);
+        writer.write_i32(self.leader);
+        writer.write_i32(self.leader_epoch);
+        writer.write_i32(self.partition_epoch);
+        if self.header.version >= 1 {
+            writer.write_uuid_array(&self.directories);
+        }
+
+        let mut tagged = Vec::new();
+        if self.leader_recovery_state != 0 {
+            tagged.push(tagged_field(0, |w| w.write_i8(self.leader_recovery_state)));
+        }
+        if self.header.version >= 2 {
+            if let Some(elr) = &self.eligible_leader_replicas {
+                tagged.push(tagged_field(1, |w| w.write_i32_array(elr)));
+            }
+            if let Some(last_known_elr) = &self.last_known_elr {
+                tagged.push(tagged_field(2, |w| w.write_i32_array(last_known_elr)));
+            }
+        }
+        writer.write_tagged_fields(&tagged);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct BrokerEndpoint {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub security_protocol: i16,
+}
+
+impl BrokerEndpoint {
+    fn read(reader: &mut MetadataReader) -> anyhow::Result<Self> {
+        let endpoint = BrokerEndpoint {
+            name: reader.read_compact_string()?,
+            host: reader.read_compact_string()?,
+            port: reader.read_u16()?,
+            security_protocol: reader.read_i16()?,
+        };
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(endpoint)
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_compact_string(&self.name);
+        writer.write_compact_string(&self.host);
+        writer.write_u16(self.port);
+        writer.write_i16(self.security_protocol);
+        writer.write_tagged_fields(&[]);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct BrokerFeature {
+    pub name: String,
+    pub min_supported_version: i16,
+    pub max_supported_version: i16,
+}
+
+impl BrokerFeature {
+    fn read(reader: &mut MetadataReader) -> anyhow::Result<Self> {
+        let feature = BrokerFeature {
+            name: reader.read_compact_string()?,
+            min_supported_version: reader.read_i16()?,
+            max_supported_version: reader.read_i16()?,
+        };
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(feature)
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_compact_string(&self.name);
+        writer.write_i16(self.min_supported_version);
+        writer.write_i16(self.max_supported_version);
+        writer.write_tagged_fields(&[]);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct RegisterBrokerRecord {
+    pub header: Header,
+    pub broker_id: i32,
+    pub is_migrating_zk_broker: bool,
+    pub incarnation_id: Uuid,
+    pub broker_epoch: i64,
+    pub end_points: Vec<BrokerEndpoint>,
+    pub features: Vec<BrokerFeature>,
+    pub rack: Option<String>,
+    pub fenced: bool,
+    pub in_controlled_shutdown: bool,
+    pub log_dirs: Vec<Uuid>,
+}
+
+impl MetadataRecord for RegisterBrokerRecord {
+    const TYPE: u32 = 0;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        let broker_id = reader.read_i32()?;
+        let is_migrating_zk_broker = header.version >= 2 && reader.read_bool()?;
+        let incarnation_id = reader.read_uuid()?;
+        let broker_epoch = reader.read_i64()?;
+        let end_points = reader.read_compact_array(BrokerEndpoint::read)?;
+        let features = reader.read_compact_array(BrokerFeature::read)?;
+        let rack = reader.read_compact_nullable_string()?;
+        let fenced = reader.read_bool()?;
+        let in_controlled_shutdown = header.version >= 1 && reader.read_bool()?;
+        let log_dirs = if header.version >= 3 {
+            reader.read_compact_array(MetadataReader::read_uuid)?
+        } else {
+            Vec::new()
+        };
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(RegisterBrokerRecord {
+            header,
+            broker_id,
+            is_migrating_zk_broker,
+            incarnation_id,
+            broker_epoch,
+            end_points,
+            features,
+            rack,
+            fenced,
+            in_controlled_shutdown,
+            log_dirs,
+        })
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_i32(self.broker_id);
+        if self.header.version >= 2 {
+            writer.write_bool(self.is_migrating_zk_broker);
+        }
+        writer.write_uuid(self.incarnation_id);
+        writer.write_i64(self.broker_epoch);
+        writer.write_compact_array(&self.end_points, |w, endpoint| endpoint.write(w));
+        writer.write_compact_array(&self.features, |w, feature| feature.write(w));
+        writer.write_compact_nullable_string(self.rack.as_deref());
+        writer.write_bool(self.fenced);
+        if self.header.version >= 1 {
+            writer.write_bool(self.in_controlled_shutdown);
+        }
+        if self.header.version >= 3 {
+            writer.write_uuid_array(&self.log_dirs);
+        }
+        writer.write_tagged_fields(&[]);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct UnregisterBrokerRecord {
+    pub header: Header,
+    pub broker_id: i32,
+    pub broker_epoch: i64,
+}
+
+impl MetadataRecord for UnregisterBrokerRecord {
+    const TYPE: u32 = 1;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        let broker_id = reader.read_i32()?;
+        let broker_epoch = reader.read_i64()?;
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(UnregisterBrokerRecord { header, broker_id, broker_epoch })
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_i32(self.broker_id);
+        writer.write_i64(self.broker_epoch);
+        writer.write_tagged_fields(&[]);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct ConfigRecord {
+    pub header: Header,
+    pub resource_type: i8,
+    pub resource_name: String,
+    pub name: String,
+    /// A null value deletes the config.
+    pub value: Option<String>,
+}
+
+impl MetadataRecord for ConfigRecord {
+    const TYPE: u32 = 4;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        let resource_type = reader.read_i8()?;
+        let resource_name = reader.read_compact_string()?;
+        let name = reader.read_compact_string()?;
+        let value = reader.read_compact_nullable_string()?;
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(ConfigRecord { header, resource_type, resource_name, name, value })
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_i8(self.resource_type);
+        writer.write_compact_string(&self.resource_name);
+        writer.write_compact_string(&self.name);
+        writer.write_compact_nullable_string(self.value.as_deref());
+        writer.write_tagged_fields(&[]);
+    }
+}
+
+/// Leader value meaning the record leaves the leader unchanged.
+pub const NO_LEADER_CHANGE: i32 = -2;
+
+/// A partial update of a partition. Every field besides the partition identity
+/// is tagged; absent fields (`None`, or the defaults for the scalars) keep their
+/// current value.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct PartitionChangeRecord {
+    pub header: Header,
+    pub partition_id: u32,
+    pub topic_uuid: Uuid,
+    pub isr: Option<Vec<i32>>,
+    pub leader: i32,
+    pub replicas: Option<Vec<i32>>,
+    pub removing_replicas: Option<Vec<i32>>,
+    pub adding_replicas: Option<Vec<i32>>,
+    /// -1 leaves the recovery state unchanged.
+    pub leader_recovery_state: i8,
+    pub eligible_leader_replicas: Option<Vec<i32>>,
+    pub last_known_elr: Option<Vec<i32>>,
+    pub directories: Option<Vec<Uuid>>,
+}
+
+impl MetadataRecord for PartitionChangeRecord {
+    const TYPE: u32 = 5;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        let partition_id = reader.read_i32()?;
+        let partition_id = u32::try_from(partition_id)
+            .map_err(|_| anyhow!("negative partition id {}", partition_id))?;
+        let topic_uuid = reader.read_uuid()?;
+
+        let mut record = PartitionChangeRecord {
+            header,
+            partition_id,
+            topic_uuid,
+            isr: None,
+            leader: NO_LEADER_CHANGE,
+            replicas: None,
+            removing_replicas: None,
+            adding_replicas: None,
+            leader_recovery_state: -1,
+            eligible_leader_replicas: None,
+            last_known_elr: None,
+            directories: None,
+        };
+        reader.read_tagged_fields(|tag, field| {
+            match tag {
+                0 => record.isr = field.read_compact_nullable_array(MetadataReader::read_i32)?,
+                1 => record.leader = field.read_i32()?,
+                2 => record.replicas = field.read_compact_nullable_array(MetadataReader::read_i32)?,
+                3 => record.removing_replicas = field.read_compact_nullable_array(MetadataReader::read_i32)?,
+                4 => record.adding_replicas = field.read_compact_nullable_array(MetadataReader::read_i32)?,
+                5 => record.leader_recovery_state = field.read_i8()?,
+                6 => record.eligible_leader_replicas = field.read_compact_nullable_array(MetadataReader::read_i32)?,
+                7 => record.last_known_elr = field.read_compact_nullable_array(MetadataReader::read_i32)?,
+                8 => record.directories = field.read_compact_nullable_array(MetadataReader::read_uuid)?,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(record)
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_i32(self.partition_id as i32);
+        writer.write_uuid(self.topic_uuid);
+
+        let mut tagged = Vec::new();
+        if let Some(isr) = &self.isr {
+            tagged.push(tagged_field(0, |w| w.write_i32_array(isr)));
+        }
+        if self.leader != NO_LEADER_CHANGE {
+            tagged.push(tagged_field(1, |w| w.write_i32(self.leader)));
+        }
+        if let Some(replicas) = &self.replicas {
+            tagged.push(tagged_field(2, |w| w.write_i32_array(replicas)));
+        }
+        if let Some(removing) = &self.removing_replicas {
+            tagged.push(tagged_field(3, |w| w.write_i32_array(removing)));
+        }
+        if let Some(adding) = &self.adding_replicas {
+            tagged.push(tagged_field(4, |w| w.write_i32_array(adding)));
+        }
+        if self.leader_recovery_state != -1 {
+            tagged.push(tagged_field(5, |w| w.write_i8(self.leader_recovery_state)));
+        }
+        if self.header.version >= 2 {
+            if let Some(elr) = &self.eligible_leader_replicas {
+                tagged.push(tagged_field(6, |w| w.write_i32_array(elr)));
+            }
+            if let Some(last_known_elr) = &self.last_known_elr {
+                tagged.push(tagged_field(7, |w| w.write_i32_array(last_known_elr)));
+            }
+        }
+        if self.header.version >= 1 {
+            if let Some(directories) = &self.directories {
+                tagged.push(tagged_field(8, |w| w.write_uuid_array(directories)));
+            }
+        }
+        writer.write_tagged_fields(&tagged);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct AccessControlEntryRecord {
+    pub header: Header,
+    pub id: Uuid,
+    pub resource_type: i8,
+    pub resource_name: String,
+    pub pattern_type: i8,
+    pub principal: String,
+    pub host: String,
+    pub operation: i8,
+    pub permission_type: i8,
+}
+
+impl MetadataRecord for AccessControlEntryRecord {
+    const TYPE: u32 = 6;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        let record = AccessControlEntryRecord {
+            header,
+            id: reader.read_uuid()?,
+            resource_type: reader.read_i8()?,
+            resource_name: reader.read_compact_string()?,
+            pattern_type: reader.read_i8()?,
+            principal: reader.read_compact_string()?,
+            host: reader.read_compact_string()?,
+            operation: reader.read_i8()?,
+            permission_type: reader.read_i8()?,
+        };
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(record)
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_uuid(self.id);
+        writer.write_i8(self.resource_type);
+        writer.write_compact_string(&self.resource_name);
+        writer.write_i8(self.pattern_type);
+        writer.write_compact_string(&self.principal);
+        writer.write_compact_string(&self.host);
+        writer.write_i8(self.operation);
+        writer.write_i8(self.permission_type);
+        writer.write_tagged_fields(&[]);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct RemoveAccessControlEntryRecord {
+    pub header: Header,
+    pub id: Uuid,
+}
+
+impl MetadataRecord for RemoveAccessControlEntryRecord {
+    const TYPE: u32 = 7;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        let id = reader.read_uuid()?;
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(RemoveAccessControlEntryRecord { header, id })
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_uuid(self.id);
+        writer.write_tagged_fields(&[]);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct FenceBrokerRecord {
+    pub header: Header,
+    pub id: i32,
+    pub epoch: i64,
+}
+
+impl MetadataRecord for FenceBrokerRecord {
+    const TYPE: u32 = 8;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        let id = reader.read_i32()?;
+        let epoch = reader.read_i64()?;
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(FenceBrokerRecord { header, id, epoch })
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_i32(self.id);
+        writer.write_i64(self.epoch);
+        writer.write_tagged_fields(&[]);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct UnfenceBrokerRecord {
+    pub header: Header,
+    pub id: i32,
+    pub epoch: i64,
+}
+
+impl MetadataRecord for UnfenceBrokerRecord {
+    const TYPE: u32 = 9;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        let id = reader.read_i32()?;
+        let epoch = reader.read_i64()?;
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(UnfenceBrokerRecord { header, id, epoch })
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_i32(self.id);
+        writer.write_i64(self.epoch);
+        writer.write_tagged_fields(&[]);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct RemoveTopicRecord {
+    pub header: Header,
+    pub topic_id: Uuid,
+}
+
+impl MetadataRecord for RemoveTopicRecord {
+    const TYPE: u32 = 10;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        let topic_id = reader.read_uuid()?;
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(RemoveTopicRecord { header, topic_id })
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_uuid(self.topic_id);
+        writer.write_tagged_fields(&[]);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct UserScramCredentialRecord {
+    pub header: Header,
+    pub name: String,
+    pub mechanism: i8,
+    pub salt: Bytes,
+    pub stored_key: Bytes,
+    pub server_key: Bytes,
+    pub iterations: i32,
+}
+
+impl MetadataRecord for UserScramCredentialRecord {
+    const TYPE: u32 = 11;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        let record = UserScramCredentialRecord {
+            header,
+            name: reader.read_compact_string()?,
+            mechanism: reader.read_i8()?,
+            salt: reader.read_compact_bytes()?,
+            stored_key: reader.read_compact_bytes()?,
+            server_key: reader.read_compact_bytes()?,
+            iterations: reader.read_i32()?,
+        };
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(record)
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_compact_string(&self.name);
+        writer.write_i8(self.mechanism);
+        writer.write_compact_bytes(&self.salt);
+        writer.write_compact_bytes(&self.stored_key);
+        writer.write_compact_bytes(&self.server_key);
+        writer.write_i32(self.iterations);
+        writer.write_tagged_fields(&[]);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct RemoveUserScramCredentialRecord {
+    pub header: Header,
+    pub name: String,
+    pub mechanism: i8,
+}
+
+impl MetadataRecord for RemoveUserScramCredentialRecord {
+    const TYPE: u32 = 22;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        let name = reader.read_compact_string()?;
+        let mechanism = reader.read_i8()?;
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(RemoveUserScramCredentialRecord { header, name, mechanism })
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_compact_string(&self.name);
+        writer.write_i8(self.mechanism);
+        writer.write_tagged_fields(&[]);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ClientQuotaEntity {
+    pub entity_type: String,
+    /// Null for the default entity of this type.
+    pub entity_name: Option<String>,
+}
+
+impl ClientQuotaEntity {
+    fn read(reader: &mut MetadataReader) -> anyhow::Result<Self> {
+        let entity = ClientQuotaEntity {
+            entity_type: reader.read_compact_string()?,
+            entity_name: reader.read_compact_nullable_string()?,
+        };
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(entity)
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_compact_string(&self.entity_type);
+        writer.write_compact_nullable_string(self.entity_name.as_deref());
+        writer.write_tagged_fields(&[]);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct ClientQuotaRecord {
+    pub header: Header,
+    pub entity: Vec<ClientQuotaEntity>,
+    pub key: String,
+    pub value: f64,
+    pub remove: bool,
+}
+
+impl MetadataRecord for ClientQuotaRecord {
+    const TYPE: u32 = 14;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        let record = ClientQuotaRecord {
+            header,
+            entity: reader.read_compact_array(ClientQuotaEntity::read)?,
+            key: reader.read_compact_string()?,
+            value: reader.read_f64()?,
+            remove: reader.read_bool()?,
+        };
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(record)
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_compact_array(&self.entity, |w, entity| entity.write(w));
+        writer.write_compact_string(&self.key);
+        writer.write_f64(self.value);
+        writer.write_bool(self.remove);
+        writer.write_tagged_fields(&[]);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct ProducerIdsRecord {
+    pub header: Header,
+    pub broker_id: i32,
+    pub broker_epoch: i64,
+    pub next_producer_id: i64,
+}
+
+impl MetadataRecord for ProducerIdsRecord {
+    const TYPE: u32 = 15;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        let broker_id = reader.read_i32()?;
+        let broker_epoch = reader.read_i64()?;
+        let next_producer_id = reader.read_i64()?;
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(ProducerIdsRecord { header, broker_id, broker_epoch, next_producer_id })
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_i32(self.broker_id);
+        writer.write_i64(self.broker_epoch);
+        writer.write_i64(self.next_producer_id);
+        writer.write_tagged_fields(&[]);
+    }
+}
+
+/// A partial update of a broker registration. The tri-state `fenced` and
+/// `in_controlled_shutdown` fields use 0 for no change, 1 for set and -1 for clear.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct BrokerRegistrationChangeRecord {
+    pub header: Header,
+    pub broker_id: i32,
+    pub broker_epoch: i64,
+    pub fenced: i8,
+    pub in_controlled_shutdown: i8,
+    pub log_dirs: Option<Vec<Uuid>>,
+}
+
+impl MetadataRecord for BrokerRegistrationChangeRecord {
+    const TYPE: u32 = 17;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        let broker_id = reader.read_i32()?;
+        let broker_epoch = reader.read_i64()?;
+        let mut fenced = 0;
+        let mut in_controlled_shutdown = 0;
+        let mut log_dirs = None;
+        reader.read_tagged_fields(|tag, field| {
+            match tag {
+                0 => fenced = field.read_i8()?,
+                1 => in_controlled_shutdown = field.read_i8()?,
+                2 => log_dirs = field.read_compact_nullable_array(MetadataReader::read_uuid)?,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(BrokerRegistrationChangeRecord {
+            header,
+            broker_id,
+            broker_epoch,
+            fenced,
+            in_controlled_shutdown,
+            log_dirs,
+        })
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_i32(self.broker_id);
+        writer.write_i64(self.broker_epoch);
+
+        let mut tagged = Vec::new();
+        if self.fenced != 0 {
+            tagged.push(tagged_field(0, |w| w.write_i8(self.fenced)));
+        }
+        if self.header.version >= 1 && self.in_controlled_shutdown != 0 {
+            tagged.push(tagged_field(1, |w| w.write_i8(self.in_controlled_shutdown)));
+        }
+        if self.header.version >= 2 {
+            if let Some(log_dirs) = &self.log_dirs {
+                tagged.push(tagged_field(2, |w| w.write_uuid_array(log_dirs)));
+            }
+        }
+        writer.write_tagged_fields(&tagged);
+    }
+}
+
+/// Written by the controller to advance the log; it changes nothing.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct NoOpRecord {
+    pub header: Header,
+}
+
+impl MetadataRecord for NoOpRecord {
+    const TYPE: u32 = 20;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(NoOpRecord { header })
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_tagged_fields(&[]);
+    }
+}
+
+/// Starts a metadata transaction: the records up to the matching
+/// `EndTransactionRecord` apply atomically, or not at all if it is aborted.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct BeginTransactionRecord {
+    pub header: Header,
+    pub name: Option<String>,
+}
+
+impl MetadataRecord for BeginTransactionRecord {
+    const TYPE: u32 = 23;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        let mut name = None;
+        reader.read_tagged_fields(|tag, field| {
+            if tag == 0 {
+                name = field.read_compact_nullable_string()?;
+            }
+            Ok(())
+        })?;
+        Ok(BeginTransactionRecord { header, name })
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        let mut tagged = Vec::new();
+        if let Some(name) = &self.name {
+            tagged.push(tagged_field(0, |w| w.write_compact_string(name)));
+        }
+        writer.write_tagged_fields(&tagged);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct EndTransactionRecord {
+    pub header: Header,
+}
+
+impl MetadataRecord for EndTransactionRecord {
+    const TYPE: u32 = 24;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(EndTransactionRecord { header })
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_tagged_fields(&[]);
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct AbortTransactionRecord {
+    pub header: Header,
+}
+
+impl MetadataRecord for AbortTransactionRecord {
+    const TYPE: u32 = 25;
+
+    fn read(reader: &mut MetadataReader, header: Header) -> anyhow::Result<Self> {
+        reader.read_tagged_fields(|_, _| Ok(()))?;
+        Ok(AbortTransactionRecord { header })
+    }
+
+    fn write(&self, writer: &mut MetadataWriter) {
+        writer.write_tagged_fields(&[]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kafka_protocol::records::{Compression, Record, RecordBatchEncoder, RecordEncodeOptions, TimestampType};
+    use super::*;
+
+    /// Encodes the record, decodes it back and checks it encodes to the same bytes.
+    fn round_trip(record: RecordType) -> RecordType {
+        let encoded = encode_record(&record).unwrap();
+        let decoded = decode_record(encoded.clone()).unwrap();
+        assert_eq!(encode_record(&decoded).unwrap(), encoded);
+        decoded
+    }
+
+    #[test]
+    fn decodes_a_topic_record_as_kafka_writes_it() {
+        let topic_id = Uuid::from_u128(0x0102030405060708090a0b0c0d0e0f10);
+        let mut value = vec![1, 2, 0, 4, b'f', b'o', b'o'];
+        value.extend_from_slice(topic_id.as_bytes());
+        value.push(0);
+
+        let RecordType::TopicValue(topic) = decode_record(Bytes::from(value.clone())).unwrap() else {
+            panic!("not a topic record");
+        };
+        assert_eq!((topic.name.as_str(), topic.uuid), ("foo", topic_id));
+        assert_eq!(encode_record(&RecordType::TopicValue(topic)).unwrap(), Bytes::from(value));
+    }
+
+    #[test]
+    fn partition_record_keeps_directories_and_tagged_fields() {
+        let topic_uuid = Uuid::new_v4();
+        let directory = Uuid::new_v4();
+        let record = RecordType::PartitionValue(PartitionRecord {
+            header: Header::new(PartitionRecord::TYPE, 2),
+            partition_id: 7,
+            topic_uuid,
+            replicas: vec![1, 2, 3],
+            isr: vec![1, 2],
+            removing_replicas: vec![3],
+            adding_replicas: Vec::new(),
+            leader: 1,
+            leader_recovery_state: 1,
+            leader_epoch: 4,
+            partition_epoch: 9,
+            directories: vec![directory; 3],
+            eligible_leader_replicas: Some(vec![2]),
+            last_known_elr: Some(Vec::new()),
+        });
+        let RecordType::PartitionValue(partition) = round_trip(record) else {
+            panic!("not a partition record");
+        };
+        assert_eq!((partition.partition_id, partition.topic_uuid), (7, topic_uuid));
+        assert_eq!((partition.replicas, partition.isr, partition.removing_replicas), (vec![1, 2, 3], vec![1, 2], vec![3]));
+        assert_eq!((partition.leader, partition.leader_epoch, partition.partition_epoch), (1, 4, 9));
+        assert_eq!(partition.leader_recovery_state, 1);
+        assert_eq!(partition.directories, vec![directory; 3]);
+        assert_eq!((partition.eligible_leader_replicas, partition.last_known_elr), (Some(vec![2]), Some(Vec::new())));
+    }
+
+    #[test]
+    fn partition_change_only_carries_what_changed() {
+        let record = RecordType::PartitionChangeValue(PartitionChangeRecord {
+            header: Header::new(PartitionChangeRecord::TYPE, 0),
+            partition_id: 0,
+            topic_uuid: Uuid::new_v4(),
+            isr: Some(vec![1]),
+            leader: NO_LEADER_CHANGE,
+            replicas: None,
+            removing_replicas: None,
+            adding_replicas: None,
+            leader_recovery_state: -1,
+            // Eligible leader replicas only exist from version 2.
+            eligible_leader_replicas: Some(vec![2]),
+            last_known_elr: None,
+            directories: None,
+        });
+        let RecordType::PartitionChangeValue(change) = round_trip(record) else {
+            panic!("not a partition change record");
+        };
+        assert_eq!(change.isr, Some(vec![1]));
+        assert_eq!((change.leader, change.leader_recovery_state), (NO_LEADER_CHANGE, -1));
+        assert_eq!((change.replicas, change.eligible_leader_replicas), (None, None));
+    }
+
+    #[test]
+    fn register_broker_record_round_trips_per_version() {
+        for version in 0..=3 {
+            let record = RecordType::RegisterBrokerValue(RegisterBrokerRecord {
+                header: Header::new(RegisterBrokerRecord::TYPE, version),
+                broker_id: 2,
+                is_migrating_zk_broker: version >= 2,
+                incarnation_id: Uuid::new_v4(),
+                broker_epoch: 11,
+                end_points: vec![BrokerEndpoint {
+                    name: "PLAINTEXT".to_string(),
+                    host: "broker-2".to_string(),
+                    port: 9092,
+                    security_protocol: 0,
+                }],
+                features: vec![BrokerFeature {
+                    name: "metadata.version".to_string(),
+                    min_supported_version: 1,
+                    max_supported_version: 20,
+                }],
+                rack: Some("r1".to_string()),
+                fenced: true,
+                in_controlled_shutdown: version >= 1,
+                log_dirs: if version >= 3 { vec![Uuid::new_v4()] } else { Vec::new() },
+            });
+            let RecordType::RegisterBrokerValue(broker) = round_trip(record) else {
+                panic!("not a broker registration");
+            };
+            assert_eq!((broker.broker_id, broker.broker_epoch), (2, 11));
+            assert_eq!((broker.end_points[0].host.as_str(), broker.end_points[0].port), ("broker-2", 9092));
+            assert_eq!(broker.rack.as_deref(), Some("r1"));
+            assert_eq!(broker.in_controlled_shutdown, version >= 1);
+            assert_eq!(broker.log_dirs.len(), usize::from(version >= 3));
+        }
+    }
+
+    #[test]
+    fn config_and_scram_records_round_trip() {
+        let RecordType::ConfigValue(config) = round_trip(RecordType::ConfigValue(ConfigRecord {
+            header: Header::new(ConfigRecord::TYPE, 0),
+            resource_type: 2,
+            resource_name: "foo".to_string(),
+            name: "retention.ms".to_string(),
+            value: None,
+        })) else {
+            panic!("not a config record");
+        };
+        assert_eq!((config.resource_name.as_str(), config.name.as_str(), config.value), ("foo", "retention.ms", None));
+
+        let RecordType::UserScramCredentialValue(credential) =
+            round_trip(RecordType::UserScramCredentialValue(UserScramCredentialRecord {
+                header: Header::new(UserScramCredentialRecord::TYPE, 0),
+                name: "alice".to_string(),
+                mechanism: 1,
+                salt: Bytes::from_static(b"salt"),
+                stored_key: Bytes::from_static(b"stored"),
+                server_key: Bytes::from_static(b"server"),
+                iterations: 4096,
+            }))
+        else {
+            panic!("not a SCRAM credential record");
+        };
+        assert_eq!((credential.name.as_str(), credential.iterations), ("alice", 4096));
+        assert_eq!(credential.stored_key, Bytes::from_static(b"stored"));
+
+        let RecordType::RemoveUserScramCredentialValue(removed) =
+            round_trip(RecordType::RemoveUserScramCredentialValue(RemoveUserScramCredentialRecord {
+                header: Header::new(RemoveUserScramCredentialRecord::TYPE, 0),
+                name: "alice".to_string(),
+                mechanism: 1,
+            }))
+        else {
+            panic!("not a SCRAM credential removal");
+        };
+        assert_eq!((removed.header.record_type, removed.name.as_str(), removed.mechanism), (22, "alice", 1));
+    }
+
+    #[test]
+    fn unknown_types_decode_to_none_and_truncated_records_fail() {
+        assert!(matches!(decode_record(Bytes::from_static(&[1, 99, 0, 0])).unwrap(), RecordType::None));
+        assert!(decode_record(Bytes::from_static(&[2, 2, 0])).is_err());
+
+        let encoded = encode_record(&RecordType::RemoveTopicValue(RemoveTopicRecord {
+            header: Header::new(RemoveTopicRecord::TYPE, 0),
+            topic_id: Uuid::new_v4(),
+        }))
+        .unwrap();
+        assert!(decode_record(encoded.slice(..encoded.len() - 2)).is_err());
+    }
+
+    fn topic(name: &str) -> Bytes {
+        encode_record(&RecordType::TopicValue(TopicRecord {
+            header: Header::new(TopicRecord::TYPE, 0),
+            name: name.to_string(),
+            uuid: Uuid::new_v4(),
+        }))
+        .unwrap()
+    }
+
+    /// Writes one batch per run of records that are all control or all data records.
+    fn write_file(path: &Path, base_offset: i64, values: &[(bool, Bytes)]) {
+        let records: Vec<Record> = values
+            .iter()
+            .enumerate()
+            .map(|(i, (control, value))| Record {
+                transactional: false,
+                control: *control,
+                partition_leader_epoch: 1,
+                producer_id: -1,
+                producer_epoch: -1,
+                timestamp_type: TimestampType::Creation,
+                offset: base_offset + i as i64,
+                sequence: base_offset as i32 + i as i32,
+                timestamp: 0,
+                key: None,
+                value: Some(value.clone()),
+                headers: Default::default(),
+            })
+            .collect();
+        let mut buf = BytesMut::new();
+        RecordBatchEncoder::encode(&mut buf, &records, &RecordEncodeOptions { version: 2, compression: Compression::None })
+            .unwrap();
+        fs::write(path, buf).unwrap();
+    }
+
+    #[test]
+    fn loads_the_latest_snapshot_and_the_log_after_it() {
+        let dir = std::env::temp_dir().join(format!("metadata-test-{}", Uuid::new_v4().simple()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str| dir.join(name);
+        write_file(&file("00000000000000000001-0000000001.checkpoint"), 0, &[(false, topic("stale"))]);
+        write_file(&file("00000000000000000004-0000000001.checkpoint"), 0, &[(false, topic("snapshot"))]);
+        write_file(&file("00000000000000000000.log"), 0, &[(false, topic("covered0")), (false, topic("covered1"))]);
+        write_file(
+            &file("00000000000000000002.log"),
+            2,
+            &[(false, topic("covered2")), (false, topic("covered3")), (false, topic("log4")), (false, topic("log5"))],
+        );
+        // A leader change control record is skipped.
+        write_file(&file("00000000000000000006.log"), 6, &[(true, Bytes::from_static(&[0, 0, 0, 0])), (false, topic("log7"))]);
+        write_file(&file("00000000000000000008-0000000001.checkpoint.part"), 0, &[(false, topic("partial"))]);
+
+        let names: Vec<String> = decode_dir(&dir)
+            .unwrap()
+            .into_iter()
+            .map(|record| match record {
+                RecordType::TopicValue(topic) => topic.name,
+                other => panic!("unexpected record {:?}", other),
+            })
+            .collect();
+        assert_eq!(names, vec!["snapshot", "log4", "log5", "log7"]);
+
+        assert!(decode_dir(&dir.join("missing")).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
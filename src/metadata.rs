@@ -0,0 +1,427 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use anyhow::bail;
+use bytes::{Bytes, BytesMut};
+use indexmap::IndexMap;
+use kafka_protocol::records::{Compression, Record, RecordBatchEncoder, RecordEncodeOptions, TimestampType};
+use uuid::Uuid;
+use crate::controller::TOPIC_RESOURCE_TYPE;
+use crate::log::{get_log, now_ms, Log, LogConfig};
+use crate::meta_parser::{decode_dir, encode_record, metadata_dir, FeatureLevelRecord, Header, MetadataRecord, PartitionChangeRecord, PartitionRecord, RecordType, RegisterBrokerRecord, METADATA_TOPIC, NO_LEADER_CHANGE};
+use crate::utils::TopicWithPartitions;
+
+static IMAGE: LazyLock<RwLock<Arc<MetadataImage>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(MetadataImage::default())));
+/// Serializes controller operations; see [`write`].
+static CONTROLLER: Mutex<()> = Mutex::new(());
+/// Set once [`load`] has read the metadata log; [`write`] refuses to append before that.
+static LOADED: AtomicBool = AtomicBool::new(false);
+
+/// Leader epoch stamped on the metadata batches this broker writes as controller.
+const CONTROLLER_EPOCH: i32 = 1;
+const METADATA_VERSION_FEATURE: &str = "metadata.version";
+/// The metadata.version a fresh data directory is bootstrapped with (3.8-IV0).
+const BOOTSTRAP_METADATA_VERSION: i16 = 20;
+
+/// An immutable snapshot of the cluster metadata, indexed for lookups by topic
+/// name and topic id. Handlers hold an `Arc` to a snapshot for the whole request.
+/// Only the metadata some handler reads is kept.
+#[derive(Debug, Default, Clone)]
+pub struct MetadataImage {
+    topics: IndexMap<Uuid, TopicWithPartitions>,
+    ids_by_name: HashMap<String, Uuid>,
+    brokers: BTreeMap<i32, RegisterBrokerRecord>,
+    features: BTreeMap<String, i16>,
+    topic_configs: HashMap<String, HashMap<String, String>>,
+}
+
+impl MetadataImage {
+    /// Replays the metadata log in order. Records inside a metadata transaction
+    /// are only applied once its `EndTransactionRecord` is read, so an aborted or
+    /// unfinished transaction leaves the image untouched.
+    pub fn from_records(records: Vec<RecordType>) -> MetadataImage {
+        let mut image = MetadataImage::default();
+        let mut transaction: Option<Vec<RecordType>> = None;
+        for record in records {
+            match record {
+                RecordType::BeginTransactionValue(begin) => {
+                    if transaction.is_some() {
+                        eprintln!("Metadata transaction {:?} began inside another one", begin.name);
+                    }
+                    transaction = Some(Vec::new());
+                }
+                RecordType::EndTransactionValue(_) => {
+                    for record in transaction.take().unwrap_or_default() {
+                        image.apply(record);
+                    }
+                }
+                RecordType::AbortTransactionValue(_) => transaction = None,
+                record => match transaction.as_mut() {
+                    Some(pending) => pending.push(record),
+                    None => image.apply(record),
+                },
+            }
+        }
+        if transaction.is_some() {
+            println!("Ignoring an unfinished metadata transaction");
+        }
+        image
+    }
+
+    /// A copy of this image with a controller change applied.
+    pub fn with_records(&self, records: Vec<RecordType>) -> MetadataImage {
+        let mut next = self.clone();
+        for record in records {
+            next.apply(record);
+        }
+        next
+    }
+
+    fn apply(&mut self, record: RecordType) {
+        match record {
+            RecordType::RegisterBrokerValue(broker) => {
+                self.brokers.insert(broker.broker_id, broker);
+            }
+            RecordType::UnregisterBrokerValue(unregister) => {
+                self.brokers.remove(&unregister.broker_id);
+            }
+            RecordType::FenceBrokerValue(fence) => {
+                if let Some(broker) = self.brokers.get_mut(&fence.id) {
+                    broker.fenced = true;
+                }
+            }
+            RecordType::UnfenceBrokerValue(unfence) => {
+                if let Some(broker) = self.brokers.get_mut(&unfence.id) {
+                    broker.fenced = false;
+                }
+            }
+            RecordType::BrokerRegistrationChangeValue(change) => {
+                let Some(broker) = self.brokers.get_mut(&change.broker_id) else {
+                    return;
+                };
+                match change.fenced {
+                    1 => broker.fenced = true,
+                    -1 => broker.fenced = false,
+                    _ => {}
+                }
+                match change.in_controlled_shutdown {
+                    1 => broker.in_controlled_shutdown = true,
+                    -1 => broker.in_controlled_shutdown = false,
+                    _ => {}
+                }
+                if let Some(log_dirs) = change.log_dirs {
+                    broker.log_dirs = log_dirs;
+                }
+            }
+            RecordType::TopicValue(topic) => {
+                self.ids_by_name.insert(topic.name.clone(), topic.uuid);
+                self.topics.insert(
+                    topic.uuid,
+                    TopicWithPartitions { topic, partitions: Vec::new() },
+                );
+            }
+            RecordType::RemoveTopicValue(remove) => {
+                if let Some(tp) = self.topics.shift_remove(&remove.topic_id) {
+                    self.ids_by_name.remove(&tp.topic.name);
+                    self.topic_configs.remove(&tp.topic.name);
+                }
+            }
+            RecordType::PartitionValue(partition) => self.apply_partition(partition),
+            RecordType::PartitionChangeValue(change) => self.apply_partition_change(change),
+            RecordType::FeatureValue(feature) => {
+                // Level 0 means the feature is disabled.
+                if feature.feature_level == 0 {
+                    self.features.remove(&feature.name);
+                } else {
+                    self.features.insert(feature.name, feature.feature_level);
+                }
+            }
+            RecordType::ConfigValue(config) if config.resource_type == TOPIC_RESOURCE_TYPE => {
+                let configs = self.topic_configs.entry(config.resource_name).or_default();
+                match config.value {
+                    Some(value) => configs.insert(config.name, value),
+                    None => configs.remove(&config.name),
+                };
+            }
+            // Nothing served reads broker configs, ACLs, quotas, SCRAM credentials or producer ids.
+            RecordType::ConfigValue(_)
+            | RecordType::AccessControlEntryValue(_)
+            | RecordType::RemoveAccessControlEntryValue(_)
+            | RecordType::ClientQuotaValue(_)
+            | RecordType::UserScramCredentialValue(_)
+            | RecordType::RemoveUserScramCredentialValue(_)
+            | RecordType::ProducerIdsValue(_)
+            | RecordType::NoOpValue(_)
+            | RecordType::BeginTransactionValue(_)
+            | RecordType::EndTransactionValue(_)
+            | RecordType::AbortTransactionValue(_)
+            | RecordType::None => {}
+        }
+    }
+
+    fn apply_partition(&mut self, partition: PartitionRecord) {
+        let Some(tp) = self.topics.get_mut(&partition.topic_uuid) else {
+            eprintln!("PartitionRecord for unknown topic {}", partition.topic_uuid);
+            return;
+        };
+        match tp.partitions.iter_mut().find(|p| p.partition_id == partition.partition_id) {
+            Some(existing) => *existing = partition,
+            None => tp.partitions.push(partition),
+        }
+    }
+
+    /// Merges a partial partition update the way Kafka's `PartitionRegistration.merge`
+    /// does: a new leader bumps the leader epoch and every change bumps the partition epoch.
+    fn apply_partition_change(&mut self, change: PartitionChangeRecord) {
+        let partition = self
+            .topics
+            .get_mut(&change.topic_uuid)
+            .and_then(|tp| tp.partitions.iter_mut().find(|p| p.partition_id == change.partition_id));
+        let Some(partition) = partition else {
+            eprintln!("PartitionChangeRecord for unknown partition {}-{}", change.topic_uuid, change.partition_id);
+            return;
+        };
+
+        if let Some(isr) = change.isr {
+            partition.isr = isr;
+        }
+        if change.leader != NO_LEADER_CHANGE {
+            partition.leader = change.leader;
+            partition.leader_epoch += 1;
+        }
+        if let Some(replicas) = change.replicas {
+            partition.replicas = replicas;
+        }
+        if let Some(removing) = change.removing_replicas {
+            partition.removing_replicas = removing;
+        }
+        if let Some(adding) = change.adding_replicas {
+            partition.adding_replicas = adding;
+        }
+        if change.leader_recovery_state != -1 {
+            partition.leader_recovery_state = change.leader_recovery_state;
+        }
+        if let Some(directories) = change.directories {
+            partition.directories = directories;
+        }
+        if change.eligible_leader_replicas.is_some() {
+            partition.eligible_leader_replicas = change.eligible_leader_replicas;
+        }
+        if change.last_known_elr.is_some() {
+            partition.last_known_elr = change.last_known_elr;
+        }
+        partition.partition_epoch += 1;
+    }
+
+    pub fn topics(&self) -> impl Iterator<Item = &TopicWithPartitions> {
+        self.topics.values()
+    }
+
+    pub fn topic_by_name(&self, name: &str) -> Option<&TopicWithPartitions> {
+        self.ids_by_name.get(name).and_then(|id| self.topics.get(id))
+    }
+
+    pub fn topic_by_id(&self, id: Uuid) -> Option<&TopicWithPartitions> {
+        self.topics.get(&id)
+    }
+
+    /// Registered brokers that are not fenced, by id.
+    pub fn live_brokers(&self) -> impl Iterator<Item = &RegisterBrokerRecord> {
+        self.brokers.values().filter(|broker| !broker.fenced)
+    }
+
+    pub fn feature_level(&self, name: &str) -> Option<i16> {
+        self.features.get(name).copied()
+    }
+
+    /// The configs set on a topic, by name.
+    pub fn topic_configs(&self, name: &str) -> Option<&HashMap<String, String>> {
+        self.topic_configs.get(name)
+    }
+}
+
+/// Builds the image from the metadata snapshots and log on disk. Run once at
+/// startup; from then on this broker is the only writer and [`write`] keeps the
+/// image current.
+pub fn load() -> anyhow::Result<()> {
+    let _controller = CONTROLLER.lock().unwrap();
+    let image = MetadataImage::from_records(read_log(&metadata_dir())?);
+    println!("Loaded metadata image with {} topics", image.topics.len());
+    *IMAGE.write().unwrap() = Arc::new(image);
+    LOADED.store(true, Ordering::Release);
+    Ok(())
+}
+
+/// Recovers the metadata log the way any partition's is recovered, cutting a
+/// torn trailing batch off its last segment, and decodes what is left.
+fn read_log(dir: &Path) -> anyhow::Result<Vec<RecordType>> {
+    Log::open(dir.to_path_buf(), LogConfig::from_broker())?;
+    decode_dir(dir)
+}
+
+/// The current metadata image.
+pub fn image() -> Arc<MetadataImage> {
+    IMAGE.read().unwrap().clone()
+}
+
+/// Runs a controller operation. `operation` decides from the latest image which
+/// records to write; they are appended to the metadata log as one batch and
+/// applied to a copy of the image that then replaces it. Operations run one at
+/// a time, like on Kafka's single-threaded `QuorumController`, so none of them
+/// acts on an image another is about to change.
+pub fn write<T>(operation: impl FnOnce(&MetadataImage) -> (Vec<RecordType>, T)) -> anyhow::Result<T> {
+    let _controller = CONTROLLER.lock().unwrap();
+    if !LOADED.load(Ordering::Acquire) {
+        bail!("the metadata log has not been loaded");
+    }
+    let current = image();
+    let (records, result) = operation(&current);
+    if !records.is_empty() {
+        append_records(&records)?;
+        *IMAGE.write().unwrap() = Arc::new(current.with_records(records));
+    }
+    Ok(result)
+}
+
+fn append_records(records: &[RecordType]) -> anyhow::Result<()> {
+    let batch = encode_batch(records)?;
+    // The metadata log is laid out like any partition, so the regular log
+    // assigns offsets, rolls segments and recovers a torn tail on restart.
+    let offsets = get_log(METADATA_TOPIC, 0)?
+        .lock()
+        .unwrap()
+        .append(batch, CONTROLLER_EPOCH)?;
+    println!("Wrote {} metadata records at offset {}", records.len(), offsets.log_end_offset);
+    Ok(())
+}
+
+/// Encodes the records of one change as a single batch, so a torn write
+/// loses the whole change rather than leaving part of it in the log.
+fn encode_batch(records: &[RecordType]) -> anyhow::Result<Bytes> {
+    let timestamp = now_ms();
+    let records: Vec<Record> = records
+        .iter()
+        .filter_map(encode_record)
+        .enumerate()
+        .map(|(i, value)| Record {
+            transactional: false,
+            control: false,
+            partition_leader_epoch: CONTROLLER_EPOCH,
+            producer_id: -1,
+            producer_epoch: -1,
+            timestamp_type: TimestampType::Creation,
+            offset: i as i64,
+            // The encoder starts a new batch wherever offset and sequence stop moving together.
+            sequence: i as i32,
+            timestamp,
+            key: None,
+            value: Some(value),
+            headers: Default::default(),
+        })
+        .collect();
+
+    let mut batch = BytesMut::new();
+    RecordBatchEncoder::encode(
+        &mut batch,
+        &records,
+        &RecordEncodeOptions { version: 2, compression: Compression::None },
+    )?;
+    Ok(batch.freeze())
+}
+
+/// Makes a fresh data directory usable without `kafka-storage format` by
+/// recording the initial metadata.version, as a new KRaft controller does.
+pub fn bootstrap() -> anyhow::Result<()> {
+    write(|image| {
+        if image.feature_level(METADATA_VERSION_FEATURE).is_some() {
+            return (Vec::new(), ());
+        }
+        let feature = FeatureLevelRecord {
+            header: Header::new(FeatureLevelRecord::TYPE, 0),
+            name: METADATA_VERSION_FEATURE.to_string(),
+            feature_level: BOOTSTRAP_METADATA_VERSION,
+        };
+        (vec![RecordType::FeatureValue(feature)], ())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use kafka_protocol::records::RecordBatchDecoder;
+    use crate::controller;
+    use crate::meta_parser::decode_record;
+    use crate::utils::scan_batches;
+    use super::*;
+
+    #[test]
+    fn a_change_is_written_as_one_batch() {
+        let configs = vec![("cleanup.policy".to_string(), "compact".to_string())];
+        let created = controller::create_internal_topic(&MetadataImage::default(), "foo", 3, configs);
+        let mut batch = encode_batch(&created.records).unwrap();
+
+        let headers = scan_batches(&batch);
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers[0].last_offset_delta as usize, created.records.len() - 1);
+        assert_eq!(headers[0].partition_leader_epoch, CONTROLLER_EPOCH);
+
+        let records = RecordBatchDecoder::decode(&mut batch)
+            .unwrap()
+            .records
+            .into_iter()
+            .map(|record| decode_record(record.value.unwrap()).unwrap())
+            .collect();
+        let image = MetadataImage::from_records(records);
+        let topic = image.topic_by_name("foo").unwrap();
+        assert_eq!(topic.topic.uuid, created.topic_id);
+        assert_eq!(topic.partitions.len(), 3);
+    }
+
+    #[test]
+    fn a_torn_last_batch_is_cut_off_and_earlier_changes_load() {
+        let dir = std::env::temp_dir().join(format!("metadata-test-{}", Uuid::new_v4().simple()));
+        let create = |name, partitions| controller::create_internal_topic(&MetadataImage::default(), name, partitions, Vec::new());
+        let (foo, bar, baz) = (create("foo", 2), create("bar", 1), create("baz", 1));
+        {
+            let mut log = Log::open(dir.clone(), LogConfig::from_broker()).unwrap();
+            log.append(encode_batch(&foo.records).unwrap(), CONTROLLER_EPOCH).unwrap();
+            log.append(encode_batch(&bar.records).unwrap(), CONTROLLER_EPOCH).unwrap();
+        }
+        let segment = dir.join(format!("{:020}.log", 0));
+        let valid_size = std::fs::metadata(&segment).unwrap().len();
+        let torn = encode_batch(&baz.records).unwrap();
+        OpenOptions::new().append(true).open(&segment).unwrap().write_all(&torn[..torn.len() / 2]).unwrap();
+
+        let image = MetadataImage::from_records(read_log(&dir).unwrap());
+        assert_eq!(image.topic_by_name("foo").unwrap().partitions.len(), 2);
+        assert_eq!(image.topic_by_name("bar").unwrap().topic.uuid, bar.topic_id);
+        assert!(image.topic_by_name("baz").is_none());
+        assert_eq!(std::fs::metadata(&segment).unwrap().len(), valid_size);
+
+        // The next change lands right after the last complete batch.
+        let mut log = Log::open(dir.clone(), LogConfig::from_broker()).unwrap();
+        assert_eq!(log.offsets().log_end_offset as usize, foo.records.len() + bar.records.len());
+        log.append(encode_batch(&baz.records).unwrap(), CONTROLLER_EPOCH).unwrap();
+        assert!(MetadataImage::from_records(read_log(&dir).unwrap()).topic_by_name("baz").is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn topic_configs_reach_the_log_config_until_the_topic_is_removed() {
+        let configs = vec![("segment.bytes".to_string(), "1024".to_string()), ("segment.ms".to_string(), "60000".to_string())];
+        let created = controller::create_internal_topic(&MetadataImage::default(), "foo", 1, configs);
+        let image = MetadataImage::from_records(created.records);
+
+        let defaults = LogConfig::from_broker();
+        let log_config = LogConfig::for_topic(image.topic_configs("foo"));
+        assert_eq!((log_config.segment_bytes, log_config.segment_ms), (1024, 60000));
+        assert_eq!(log_config.index_interval_bytes, defaults.index_interval_bytes);
+        assert_eq!(LogConfig::for_topic(image.topic_configs("bar")).segment_bytes, defaults.segment_bytes);
+
+        let image = image.with_records(vec![controller::delete_topic(created.topic_id)]);
+        assert!(image.topic_configs("foo").is_none());
+    }
+}
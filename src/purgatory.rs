@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Condvar, LazyLock, Mutex};
+use std::time::Instant;
+
+/// Partitions are watched by topic name and partition id.
+pub type PartitionKey = (String, u32);
+
+/// Delayed fetches parked until a produce appends to one of their partitions.
+pub static FETCH_PURGATORY: LazyLock<Purgatory<PartitionKey>> = LazyLock::new(Purgatory::new);
+
+/// Delayed joins and syncs parked until their group, watched by group id, moves on.
+pub static GROUP_PURGATORY: LazyLock<Purgatory<String>> = LazyLock::new(Purgatory::new);
+
+#[derive(Default)]
+struct Waiter {
+    completed: Mutex<bool>,
+    cond: Condvar,
+}
+
+/// Holds delayed operations keyed by what can complete them, in the spirit of
+/// Kafka's `DelayedOperationPurgatory`. Each operation is a blocked request
+/// thread; `check_and_complete` wakes every operation watching a key so it can
+/// re-evaluate whether it is satisfied.
+pub struct Purgatory<K> {
+    watchers: Mutex<HashMap<K, Vec<Arc<Waiter>>>>,
+}
+
+impl<K: Hash + Eq + Clone> Purgatory<K> {
+    pub fn new() -> Self {
+        Purgatory {
+            watchers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Registers interest in `keys`. Register before checking the completion
+    /// condition so an event between the check and the wait is not missed.
+    pub fn watch(&self, keys: &[K]) -> Watch<'_, K> {
+        let waiter = Arc::new(Waiter::default());
+        let mut watchers = self.watchers.lock().unwrap();
+        for key in keys {
+            watchers.entry(key.clone()).or_default().push(waiter.clone());
+        }
+        Watch {
+            purgatory: self,
+            keys: keys.to_vec(),
+            waiter,
+        }
+    }
+
+    /// Wakes every operation watching `key`.
+    pub fn check_and_complete(&self, key: &K) {
+        let waiters = self.watchers.lock().unwrap().remove(key).unwrap_or_default();
+        for waiter in waiters {
+            *waiter.completed.lock().unwrap() = true;
+            waiter.cond.notify_all();
+        }
+    }
+
+    fn unwatch(&self, keys: &[K], waiter: &Arc<Waiter>) {
+        let mut watchers = self.watchers.lock().unwrap();
+        for key in keys {
+            if let Some(list) = watchers.get_mut(key) {
+                list.retain(|w| !Arc::ptr_eq(w, waiter));
+                if list.is_empty() {
+                    watchers.remove(key);
+                }
+            }
+        }
+    }
+}
+
+/// A registered delayed operation; unregisters itself when dropped.
+pub struct Watch<'a, K: Hash + Eq + Clone> {
+    purgatory: &'a Purgatory<K>,
+    keys: Vec<K>,
+    waiter: Arc<Waiter>,
+}
+
+impl<K: Hash + Eq + Clone> Watch<'_, K> {
+    /// Blocks until one of the watched keys is completed or `deadline` passes.
+    /// Returns whether the wake-up came from a completion.
+    pub fn wait_until(&self, deadline: Instant) -> bool {
+        let mut completed = self.waiter.completed.lock().unwrap();
+        while !*completed {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            completed = self.waiter.cond.wait_timeout(completed, deadline - now).unwrap().0;
+        }
+        true
+    }
+}
+
+impl<K: Hash + Eq + Clone> Drop for Watch<'_, K> {
+    fn drop(&mut self) {
+        self.purgatory.unwatch(&self.keys, &self.waiter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+    use super::*;
+
+    fn key(partition_id: u32) -> PartitionKey {
+        ("foo".to_string(), partition_id)
+    }
+
+    /// Appends to a partition's byte count and wakes its fetches, as a produce does.
+    fn produce(purgatory: &Purgatory<PartitionKey>, log: &Mutex<usize>, partition_id: u32, bytes: usize) {
+        *log.lock().unwrap() += bytes;
+        purgatory.check_and_complete(&key(partition_id));
+    }
+
+    /// Parks like `process_fetch` until `min_bytes` are in the log or `max_wait` passes.
+    fn fetch(purgatory: &Purgatory<PartitionKey>, log: &Mutex<usize>, min_bytes: usize, max_wait: Duration) -> usize {
+        let deadline = Instant::now() + max_wait;
+        loop {
+            let watch = purgatory.watch(&[key(0)]);
+            let fetched = *log.lock().unwrap();
+            if fetched >= min_bytes || !watch.wait_until(deadline) {
+                return fetched;
+            }
+        }
+    }
+
+    #[test]
+    fn a_parked_fetch_wakes_when_a_produce_appends() {
+        let purgatory = Purgatory::new();
+        let log = Mutex::new(0);
+        let started = Instant::now();
+        let fetched = thread::scope(|scope| {
+            let fetcher = scope.spawn(|| fetch(&purgatory, &log, 10, Duration::from_secs(30)));
+            thread::sleep(Duration::from_millis(50));
+            // Neither another partition nor too few bytes completes the fetch.
+            produce(&purgatory, &log, 1, 0);
+            produce(&purgatory, &log, 0, 4);
+            thread::sleep(Duration::from_millis(50));
+            assert!(!fetcher.is_finished());
+            produce(&purgatory, &log, 0, 6);
+            fetcher.join().unwrap()
+        });
+        assert_eq!(fetched, 10);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(purgatory.watchers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_fetch_returns_at_max_wait_when_nothing_arrives() {
+        let purgatory = Purgatory::new();
+        let log = Mutex::new(3);
+        let started = Instant::now();
+        assert_eq!(fetch(&purgatory, &log, 10, Duration::from_millis(100)), 3);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert!(purgatory.watchers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_completion_before_the_wait_is_not_lost() {
+        let purgatory = Purgatory::new();
+        let watch = purgatory.watch(&[key(0), key(1)]);
+        // A produce landing after the fetch checked the log but before it waits.
+        purgatory.check_and_complete(&key(1));
+        let started = Instant::now();
+        assert!(watch.wait_until(started + Duration::from_secs(30)));
+        assert!(started.elapsed() < Duration::from_secs(10));
+        drop(watch);
+        assert!(purgatory.watchers.lock().unwrap().is_empty());
+
+        // A watch registered after the completion waits for the next one.
+        let late = purgatory.watch(&[key(1)]);
+        assert!(!late.wait_until(Instant::now() + Duration::from_millis(20)));
+    }
+}
@@ -0,0 +1,62 @@
+use bytes::BytesMut;
+use kafka_protocol::messages::{ApiKey, RequestHeader, RequestKind};
+use kafka_protocol::protocol::VersionRange;
+use crate::handlers::{process_api_version, process_consumer_group_describe, process_consumer_group_heartbeat, process_create_partitions, process_create_topics, process_delete_groups, process_delete_topics, process_describe_groups, process_describe_topic_partitions, process_fetch, process_find_coordinator, process_heartbeat, process_join_group, process_leave_group, process_list_groups, process_list_offsets, process_metadata, process_offset_commit, process_offset_delete, process_offset_fetch, process_produce, process_sync_group};
+
+pub type Handler = fn(ApiKey, RequestHeader, RequestKind) -> BytesMut;
+
+/// An implemented API: the versions we advertise in ApiVersions and the
+/// function requests of that kind are dispatched to.
+pub struct HandlerEntry {
+    pub api_key: ApiKey,
+    pub versions: VersionRange,
+    pub handler: Handler,
+}
+
+impl HandlerEntry {
+    pub fn supports(&self, version: i16) -> bool {
+        version >= self.versions.min && version <= self.versions.max
+    }
+}
+
+macro_rules! handler {
+    ($kind:ident, $min:expr, $max:expr, $process:path) => {
+        HandlerEntry {
+            api_key: ApiKey::$kind,
+            versions: VersionRange { min: $min, max: $max },
+            handler: |api_key, header, request| match request {
+                RequestKind::$kind(req) => $process(api_key, header, req),
+                other => unreachable!("{:?} dispatched to the {:?} handler", other, api_key),
+            },
+        }
+    };
+}
+
+pub static HANDLERS: &[HandlerEntry] = &[
+    handler!(Produce, 3, 12, process_produce),
+    handler!(Fetch, 4, 16, process_fetch),
+    handler!(ListOffsets, 1, 10, process_list_offsets),
+    handler!(Metadata, 0, 13, process_metadata),
+    handler!(OffsetCommit, 2, 9, process_offset_commit),
+    handler!(OffsetFetch, 1, 9, process_offset_fetch),
+    handler!(FindCoordinator, 0, 5, process_find_coordinator),
+    handler!(JoinGroup, 0, 9, process_join_group),
+    handler!(Heartbeat, 0, 4, process_heartbeat),
+    handler!(LeaveGroup, 0, 5, process_leave_group),
+    handler!(SyncGroup, 0, 5, process_sync_group),
+    handler!(DescribeGroups, 0, 6, process_describe_groups),
+    handler!(ListGroups, 0, 5, process_list_groups),
+    handler!(ApiVersions, 0, 4, process_api_version),
+    handler!(CreateTopics, 2, 7, process_create_topics),
+    handler!(DeleteTopics, 1, 6, process_delete_topics),
+    handler!(CreatePartitions, 0, 3, process_create_partitions),
+    handler!(DeleteGroups, 0, 2, process_delete_groups),
+    handler!(OffsetDelete, 0, 0, process_offset_delete),
+    handler!(ConsumerGroupHeartbeat, 0, 1, process_consumer_group_heartbeat),
+    handler!(ConsumerGroupDescribe, 0, 1, process_consumer_group_describe),
+    handler!(DescribeTopicPartitions, 0, 0, process_describe_topic_partitions),
+];
+
+pub fn lookup(api_key: ApiKey) -> Option<&'static HandlerEntry> {
+    HANDLERS.iter().find(|entry| entry.api_key == api_key)
+}
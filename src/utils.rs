@@ -1,68 +1,111 @@
 use std::fs;
-use std::fs::OpenOptions;
-use std::io::Write;
-use bytes::{Buf, Bytes, BytesMut};
-use indexmap::IndexMap;
-use uuid::Uuid;
-use crate::meta_parser::{Partition, RecordType, Topic};
+use bytes::{Buf, Bytes};
+use kafka_protocol::records::{Record, RecordBatchDecoder};
+use crate::config;
+use crate::log::{get_log, PartitionOffsets};
+use crate::meta_parser::{PartitionRecord, TopicRecord};
+use crate::purgatory::FETCH_PURGATORY;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TopicWithPartitions {
-    pub topic: Topic,
-    pub partitions: Vec<Partition>,
+    pub topic: TopicRecord,
+    pub partitions: Vec<PartitionRecord>,
 }
 
-#[derive(Debug, Default)]
-struct TopicAcc {
-    topic: Option<Topic>,
-    partitions: Vec<Partition>,
+impl TopicWithPartitions {
+    pub fn partition(&self, partition_id: u32) -> Option<&PartitionRecord> {
+        self.partitions.iter().find(|p| p.partition_id == partition_id)
+    }
 }
 
-pub fn group_topics(records: Vec<RecordType>) -> Vec<TopicWithPartitions> {
-    let mut by_topic: IndexMap<Uuid, TopicAcc> = IndexMap::new();
-
-    for r in records {
-        match r {
-            RecordType::TopicValue(t) => {
-                let uuid = t.uuid;
-                by_topic.entry(uuid).or_default().topic = Some(t);
-            }
-            RecordType::PartitionValue(p) => {
-                by_topic
-                    .entry(p.topic_uuid)
-                    .or_default()
-                    .partitions
-                    .push(p);
-            }
-            _ => {}
-        }
-    }
+/// Appends a producer's record batches to the partition log; see [`crate::log::Log::append`].
+pub fn write_records(topic_name: &str, partition_id : u32, leader_epoch: i32, records : Bytes) -> anyhow::Result<PartitionOffsets> {
+    let offsets = get_log(topic_name, partition_id)?.lock().unwrap().append(records, leader_epoch)?;
+    FETCH_PURGATORY.check_and_complete(&(topic_name.to_string(), partition_id));
+    Ok(offsets)
+}
 
-    by_topic
-        .into_iter()
-        .filter_map(|(_uuid, acc)| {
-            let topic = acc.topic?;
-            Some(TopicWithPartitions {
-                topic,
-                partitions: acc.partitions,
-            })
-        })
-        .collect()
+/// Reads `cluster.id` from the `meta.properties` file written by `kafka-storage format`.
+pub fn read_cluster_id() -> Option<String> {
+    let content = fs::read_to_string(format!("{}/meta.properties", config::get().log_dir)).ok()?;
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("cluster.id="))
+        .map(|id| id.trim().to_string())
 }
 
-pub fn read_records(topic_name: &str, partition_id : u32) -> Bytes{
-    let path = format!("/tmp/kraft-combined-logs/{}-{}/00000000000000000000.log", topic_name, partition_id);
-    let file = fs::read(path).unwrap();
-    let mut buf = BytesMut::from(&file[..]);
-    buf.copy_to_bytes(buf.len())
+/// The fixed-size v2 record batch header, as laid out on disk.
+#[derive(Debug, Clone)]
+pub struct BatchHeader {
+    /// Byte position of the batch within the log file.
+    pub position: usize,
+    pub base_offset: i64,
+    pub batch_length: i32,
+    pub partition_leader_epoch: i32,
+    pub last_offset_delta: i32,
+    pub max_timestamp: i64,
 }
 
-pub fn write_records(topic_name: &str, partition_id : u32, records : Bytes) {
-    let path = format!("/tmp/kraft-combined-logs/{}-{}/00000000000000000000.log", topic_name, partition_id);
+impl BatchHeader {
+    pub const SIZE: usize = 61;
+    /// `base_offset` and `batch_length` are not counted in `batch_length`.
+    pub const LOG_OVERHEAD: usize = 12;
+    pub const MAGIC_OFFSET: usize = 16;
+    pub const CRC_OFFSET: usize = 17;
+    /// The CRC covers everything from the attributes to the end of the batch.
+    pub const ATTRIBUTES_OFFSET: usize = 21;
 
-    let mut file = match OpenOptions::new().create(true).append(true).open(&path) {
-        Ok(f) => f,
-        Err(_) => return,
-    };
-    let _ = file.write_all(&*records);
-}
\ No newline at end of file
+    pub fn parse(data: &[u8], position: usize) -> Option<BatchHeader> {
+        let mut buf = data.get(position..position + BatchHeader::SIZE)?;
+        let base_offset = buf.get_i64();
+        let batch_length = buf.get_i32();
+        let partition_leader_epoch = buf.get_i32();
+        let _magic = buf.get_i8();
+        let _crc = buf.get_u32();
+        let _attributes = buf.get_i16();
+        let last_offset_delta = buf.get_i32();
+        let _base_timestamp = buf.get_i64();
+        let max_timestamp = buf.get_i64();
+        Some(BatchHeader {
+            position,
+            base_offset,
+            batch_length,
+            partition_leader_epoch,
+            last_offset_delta,
+            max_timestamp,
+        })
+    }
+
+    pub fn last_offset(&self) -> i64 {
+        self.base_offset + self.last_offset_delta as i64
+    }
+
+    pub fn total_size(&self) -> usize {
+        BatchHeader::LOG_OVERHEAD + self.batch_length.max(0) as usize
+    }
+
+    /// Decodes the records of this batch out of the log bytes it was parsed from.
+    pub fn records(&self, data: &[u8]) -> Vec<Record> {
+        let Some(batch) = data.get(self.position..self.position + self.total_size()) else {
+            return Vec::new();
+        };
+        let mut buf = Bytes::copy_from_slice(batch);
+        RecordBatchDecoder::decode(&mut buf)
+            .map(|set| set.records)
+            .unwrap_or_default()
+    }
+}
+
+/// Walks the batch headers of a log, stopping at the first truncated batch.
+pub fn scan_batches(data: &[u8]) -> Vec<BatchHeader> {
+    let mut batches = Vec::new();
+    let mut position = 0;
+    while let Some(header) = BatchHeader::parse(data, position) {
+        if header.batch_length <= 0 || position + header.total_size() > data.len() {
+            break;
+        }
+        position += header.total_size();
+        batches.push(header);
+    }
+    batches
+}